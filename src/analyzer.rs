@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use crate::parser::{Instruction, InstructionType, Operand};
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Control-Flow Graph                                            ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+// Jump targets are instruction indices (the same values `rip` takes),
+// so a target outside the program simply leaves it.
+fn jump_target(instruction: &Instruction) -> Option<usize> {
+    match instruction.operands.first() {
+        Some(Operand::Immediate(target)) if *target >= 0 => Some(*target as usize),
+        _ => None,
+    }
+}
+
+fn successors(index: usize, instruction: &Instruction) -> Vec<usize> {
+    match instruction.instruction_type {
+        InstructionType::Jmp => jump_target(instruction).into_iter().collect(),
+        InstructionType::Ret => vec![],
+        // Conditional jumps may fall through, and a call eventually returns to the next line
+        InstructionType::Je | InstructionType::Jne |
+        InstructionType::Jg | InstructionType::Jge |
+        InstructionType::Jl | InstructionType::Jle |
+        InstructionType::Loop | InstructionType::Call => {
+            let mut next = vec![index + 1];
+            next.extend(jump_target(instruction));
+            next
+        }
+        _ => vec![index + 1],
+    }
+}
+
+fn build_cfg(program: &[Instruction]) -> Vec<Vec<usize>> {
+    program.iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            successors(i, instruction)
+                .into_iter()
+                .filter(|&target| target < program.len())
+                .collect()
+        })
+        .collect()
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Reachability                                                  ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+/// Returns the indices of instructions that no path from the first instruction reaches.
+pub fn find_unreachable(program: &[Instruction]) -> Vec<usize> {
+    if program.is_empty() {
+        return vec![];
+    }
+
+    let cfg = build_cfg(program);
+    let mut reachable = vec![false; program.len()];
+    let mut queue = VecDeque::from([0]);
+    reachable[0] = true;
+
+    while let Some(index) = queue.pop_front() {
+        for &next in &cfg[index] {
+            if !reachable[next] {
+                reachable[next] = true;
+                queue.push_back(next);
+            }
+        }
+    }
+
+    reachable.iter()
+        .enumerate()
+        .filter(|&(_, &seen)| !seen)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program;
+
+    fn unreachable(lines: &[&str]) -> Vec<usize> {
+        let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        find_unreachable(&parse_program(&lines).unwrap())
+    }
+
+    #[test]
+    fn code_after_jmp_is_dead_until_a_jump_lands_in_it() {
+        assert_eq!(unreachable(&["jmp end", "mov rax, 1", "mov rbx, 2", "end:", "nop"]), vec![1, 2]);
+        assert!(unreachable(&["jmp end", "back:", "ret", "end:", "je back"]).is_empty());
+    }
+
+    #[test]
+    fn conditional_jumps_and_calls_fall_through() {
+        assert!(unreachable(&["je skip", "nop", "skip:", "call f", "ret", "f:", "ret"]).is_empty());
+        assert_eq!(unreachable(&["ret", "nop"]), vec![1]);
+        assert!(unreachable(&[]).is_empty());
+    }
+}
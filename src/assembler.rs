@@ -1,18 +1,135 @@
-use iced_x86::code_asm::{AsmRegister64, CodeAssembler};
+use iced_x86::code_asm::{byte_ptr, dword_ptr, ptr, word_ptr, AsmMemoryOperand, AsmRegister16, AsmRegister32, AsmRegister64, AsmRegister8, CodeAssembler};
 use iced_x86::code_asm::registers::xmm;
-use iced_x86::Register;
-use crate::parser::{Instruction, InstructionType, Operand, Register as ParserRegister};
+use iced_x86::{BlockEncoder, BlockEncoderOptions, Code, InstructionBlock, Register};
+use crate::parser::{parse_label_definition, parse_program, Instruction, InstructionType, Operand, Register as ParserRegister, RegisterSize};
+
+// A general-purpose register at the width the operand names
+enum SizedRegister {
+    Byte(AsmRegister8),
+    Word(AsmRegister16),
+    Dword(AsmRegister32),
+    Qword(AsmRegister64),
+}
+
+impl SizedRegister {
+    fn bits(&self) -> u32 {
+        match self {
+            SizedRegister::Byte(_) => 8,
+            SizedRegister::Word(_) => 16,
+            SizedRegister::Dword(_) => 32,
+            SizedRegister::Qword(_) => 64,
+        }
+    }
+}
+
+// iced has a distinct type per register width, so these expand the body once per width.
+// Instructions without an 8-bit form (lea, bsf, cmov) list the widths they accept.
+macro_rules! with_register {
+    ($reg:expr, |$r:ident| $body:expr) => {
+        with_register!($reg, [Byte, Word, Dword, Qword], |$r| $body)
+    };
+    ($reg:expr, [$($width:ident),*], |$r:ident| $body:expr) => {
+        match $reg {
+            $(SizedRegister::$width($r) => $body.map_err(|e| e.to_string()),)*
+            #[allow(unreachable_patterns)]
+            reg => Err(format!("{}-bit registers aren't supported by this instruction", reg.bits())),
+        }
+    };
+}
+
+macro_rules! with_registers {
+    ($dest:expr, $src:expr, |$d:ident, $s:ident| $body:expr) => {
+        with_registers!($dest, $src, [Byte, Word, Dword, Qword], |$d, $s| $body)
+    };
+    ($dest:expr, $src:expr, [$($width:ident),*], |$d:ident, $s:ident| $body:expr) => {
+        match ($dest, $src) {
+            $((SizedRegister::$width($d), SizedRegister::$width($s)) => $body.map_err(|e| e.to_string()),)*
+            (dest, src) if dest.bits() != src.bits() => Err("Operand sizes do not match".to_string()),
+            (dest, _) => Err(format!("{}-bit registers aren't supported by this instruction", dest.bits())),
+        }
+    };
+}
+
+// movzx/movsx pair a destination with a narrower source; this expands the body for each valid pair
+macro_rules! with_extension_registers {
+    ($dest:expr, $src:expr, |$d:ident, $s:ident| $body:expr) => {
+        match ($dest, $src) {
+            (SizedRegister::Word($d), SizedRegister::Byte($s)) => $body.map_err(|e| e.to_string()),
+            (SizedRegister::Dword($d), SizedRegister::Byte($s)) => $body.map_err(|e| e.to_string()),
+            (SizedRegister::Qword($d), SizedRegister::Byte($s)) => $body.map_err(|e| e.to_string()),
+            (SizedRegister::Dword($d), SizedRegister::Word($s)) => $body.map_err(|e| e.to_string()),
+            (SizedRegister::Qword($d), SizedRegister::Word($s)) => $body.map_err(|e| e.to_string()),
+            (dest, src) => Err(format!("Can't extend a {}-bit register into a {}-bit one", src.bits(), dest.bits())),
+        }
+    };
+}
 
 pub fn assemble_instruction(instruction: &Instruction) -> Result<Vec<u8>, String> {
+    assemble_at(instruction, 0)
+}
+
+/// Assembles a whole program into one run of machine code starting at address 0. Branch targets,
+/// which count instructions, become byte offsets, so the result runs as it does in the emulator.
+pub fn assemble_program(lines: &[String]) -> Result<Vec<u8>, String> {
+    let program = parse_program(lines)?;
+    let line_numbers: Vec<usize> = lines.iter()
+        .enumerate()
+        .filter(|(_, line)| parse_label_definition(line).is_none())
+        .map(|(i, _)| i + 1)
+        .collect();
+
+    // Where each instruction starts, plus the end of the program. A branch's size depends on how far
+    // it reaches, so start with every instruction as small as it can be and grow the offsets until they
+    // settle; sizes only ever grow, so this terminates
+    let mut offsets = vec![0; program.len() + 1];
+    loop {
+        let mut code = Vec::new();
+        let mut next_offsets = vec![0];
+        for (i, instruction) in program.iter().enumerate() {
+            let bytes = branch_to_offset(instruction, &offsets)
+                .and_then(|instruction| assemble_at(&instruction, offsets[i]))
+                .map_err(|e| format!("Error on line {}: {}", line_numbers[i], e))?;
+            code.extend(bytes);
+            next_offsets.push(code.len() as u64);
+        }
+        if next_offsets == offsets {
+            return Ok(code);
+        }
+        offsets = next_offsets;
+    }
+}
+
+// Replaces a branch's instruction-index target with the byte offset of that instruction
+fn branch_to_offset(instruction: &Instruction, offsets: &[u64]) -> Result<Instruction, String> {
+    let mut instruction = instruction.clone();
+    if instruction.instruction_type.is_branch() {
+        if let Some(Operand::Immediate(target)) = instruction.operands.first_mut() {
+            let offset = usize::try_from(*target).ok().and_then(|index| offsets.get(index))
+                .ok_or_else(|| format!("branch target {} is outside the program", target))?;
+            *target = *offset as i64;
+        }
+    }
+    Ok(instruction)
+}
+
+fn assemble_at(instruction: &Instruction, address: u64) -> Result<Vec<u8>, String> {
+    validate_operands(instruction)?;
     let mut assembler = CodeAssembler::new(64).map_err(|e| e.to_string())?;
 
     match instruction.instruction_type {
         InstructionType::Mov => assemble_mov(&mut assembler, instruction),
         InstructionType::Add => assemble_add(&mut assembler, instruction),
         InstructionType::Sub => assemble_sub(&mut assembler, instruction),
+        InstructionType::Adc => assemble_adc(&mut assembler, instruction),
+        InstructionType::Sbb => assemble_sbb(&mut assembler, instruction),
         InstructionType::And => assemble_and(&mut assembler, instruction),
         InstructionType::Or => assemble_or(&mut assembler, instruction),
         InstructionType::Xor => assemble_xor(&mut assembler, instruction),
+        InstructionType::Mul => assemble_mul(&mut assembler, instruction),
+        InstructionType::Imul => assemble_imul(&mut assembler, instruction),
+        InstructionType::Div => assemble_div(&mut assembler, instruction),
+        InstructionType::Idiv => assemble_idiv(&mut assembler, instruction),
+        InstructionType::Cdq | InstructionType::Cqo => assemble_sign_extend_rax(&mut assembler, instruction),
         InstructionType::Inc => assemble_inc(&mut assembler, instruction),
         InstructionType::Dec => assemble_dec(&mut assembler, instruction),
         InstructionType::Neg => assemble_neg(&mut assembler, instruction),
@@ -32,16 +149,40 @@ pub fn assemble_instruction(instruction: &Instruction) -> Result<Vec<u8>, String
         InstructionType::Jge => assemble_jge(&mut assembler, instruction),
         InstructionType::Jl => assemble_jl(&mut assembler, instruction),
         InstructionType::Jle => assemble_jle(&mut assembler, instruction),
+        InstructionType::Loop => assemble_loop(&mut assembler, instruction),
         InstructionType::Call => assemble_call(&mut assembler, instruction),
         InstructionType::Ret => assemble_ret(&mut assembler, instruction),
+        InstructionType::Syscall => assemble_syscall(&mut assembler, instruction),
+        InstructionType::Lea => assemble_lea(&mut assembler, instruction),
         InstructionType::Paddd => assemble_paddd(&mut assembler, instruction), // Vector instruction
         // --- Assembly Wizardry Examples ---
         InstructionType::Bsf => assemble_bsf(&mut assembler, instruction),
+        InstructionType::Bsr => assemble_bsr(&mut assembler, instruction),
+        InstructionType::Bswap => assemble_bswap(&mut assembler, instruction),
+        InstructionType::Popcnt | InstructionType::Lzcnt | InstructionType::Tzcnt => assemble_bit_count(&mut assembler, instruction),
+        InstructionType::Bt | InstructionType::Bts | InstructionType::Btr | InstructionType::Btc => assemble_bit_test(&mut assembler, instruction),
         InstructionType::Cmovne => assemble_cmovne(&mut assembler, instruction),
+        InstructionType::Sete | InstructionType::Setne | InstructionType::Setg | InstructionType::Setge |
+        InstructionType::Setl | InstructionType::Setle | InstructionType::Seta | InstructionType::Setae |
+        InstructionType::Setb | InstructionType::Setbe => assemble_setcc(&mut assembler, instruction),
+        InstructionType::Movzx => assemble_movzx(&mut assembler, instruction),
+        InstructionType::Movsx => assemble_movsx(&mut assembler, instruction),
+        InstructionType::Xchg => assemble_xchg(&mut assembler, instruction),
+        InstructionType::Nop => assemble_nop(&mut assembler, instruction),
+        InstructionType::Cld | InstructionType::Std => assemble_direction_flag(&mut assembler, instruction),
         //_ => return Err(format!("Unsupported instruction: {:?}", instruction.instruction_type)),
     }?;
 
-    assembler.assemble(0).map_err(|e| e.to_string())
+    // The encoder reads a branch target equal to an instruction's own address as a branch to that
+    // instruction, and the assembler leaves every address at 0, so `jne 0` at address 1 would jump
+    // to itself. Placing the instruction where it really is avoids that
+    let mut instructions = assembler.take_instructions();
+    for instruction in &mut instructions {
+        instruction.set_ip(address);
+    }
+    BlockEncoder::encode(64, InstructionBlock::new(&instructions, address), BlockEncoderOptions::NONE)
+        .map(|result| result.code_buffer)
+        .map_err(|e| e.to_string())
 }
 
 fn assemble_mov(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
@@ -50,14 +191,18 @@ fn assemble_mov(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
     }
 
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.mov(dest_reg, *imm as i64).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            // Only the 64-bit form takes a full 64-bit immediate
+            match asm_register(dest, *size) {
+                SizedRegister::Qword(dest_reg) => assembler.mov(dest_reg, *imm).map_err(|e| e.to_string())?,
+                narrower => {
+                    let imm = sized_imm32(*imm, *size)?;
+                    with_register!(narrower, [Byte, Word, Dword], |dest_reg| assembler.mov(dest_reg, imm))?
+                }
+            }
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.mov(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.mov(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for mov instruction".to_string()),
     }
@@ -66,14 +211,12 @@ fn assemble_mov(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 
 fn assemble_add(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.add(dest_reg, *imm as i32).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.add(dest_reg, imm))?;
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.add(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.add(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for add instruction".to_string()),
     }
@@ -82,30 +225,54 @@ fn assemble_add(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 
 fn assemble_sub(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.sub(dest_reg, *imm as i32).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.sub(dest_reg, imm))?;
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.sub(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.sub(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for sub instruction".to_string()),
     }
     Ok(())
 }
 
+fn assemble_adc(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.adc(dest_reg, imm))?;
+        },
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.adc(dest_reg, src_reg))?;
+        },
+        _ => return Err("Invalid operands for adc instruction".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_sbb(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.sbb(dest_reg, imm))?;
+        },
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.sbb(dest_reg, src_reg))?;
+        },
+        _ => return Err("Invalid operands for sbb instruction".to_string()),
+    }
+    Ok(())
+}
+
 fn assemble_and(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.and(dest_reg, *imm as i32).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.and(dest_reg, imm))?;
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.and(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.and(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for and instruction".to_string()),
     }
@@ -114,14 +281,12 @@ fn assemble_and(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 
 fn assemble_or(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.or(dest_reg, *imm as i32).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.or(dest_reg, imm))?;
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.or(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.or(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for or instruction".to_string()),
     }
@@ -130,24 +295,84 @@ fn assemble_or(assembler: &mut CodeAssembler, instruction: &Instruction) -> Resu
 
 fn assemble_xor(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.xor(dest_reg, *imm as i32).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.xor(dest_reg, imm))?;
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.xor(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.xor(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for xor instruction".to_string()),
     }
     Ok(())
 }
 
+fn assemble_mul(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    match instruction.operands.as_slice() {
+        [Operand::Register(reg, size)] => {
+            with_register!(asm_register(reg, *size), |asm_reg| assembler.mul(asm_reg))?;
+        },
+        [Operand::Immediate(_)] => return Err("mul can't take an immediate; load the value into a register first".to_string()),
+        _ => return Err("MUL instruction requires exactly one register operand (rax is the implied other factor)".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_div(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    match instruction.operands.as_slice() {
+        [Operand::Register(reg, size)] => {
+            with_register!(asm_register(reg, *size), |asm_reg| assembler.div(asm_reg))?;
+        },
+        [Operand::Immediate(_)] => return Err("div can't take an immediate; load the divisor into a register first".to_string()),
+        _ => return Err("DIV instruction requires exactly one register operand (rdx:rax is the implied dividend)".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_idiv(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    match instruction.operands.as_slice() {
+        [Operand::Register(reg, size)] => {
+            with_register!(asm_register(reg, *size), |asm_reg| assembler.idiv(asm_reg))?;
+        },
+        [Operand::Immediate(_)] => return Err("idiv can't take an immediate; load the divisor into a register first".to_string()),
+        _ => return Err("IDIV instruction requires exactly one register operand (rdx:rax is the implied dividend)".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_imul(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    // There's no 8-bit form of the two- and three-operand multiplies
+    match instruction.operands.as_slice() {
+        [Operand::Register(dest, size), Operand::Immediate(imm)] => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), [Word, Dword, Qword], |dest_reg| assembler.imul_3(dest_reg, dest_reg, imm))?;
+        },
+        [Operand::Register(dest, dest_size), Operand::Register(src, src_size)] => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), [Word, Dword, Qword], |dest_reg, src_reg| assembler.imul_2(dest_reg, src_reg))?;
+        },
+        [Operand::Register(dest, dest_size), Operand::Register(src, src_size), Operand::Immediate(imm)] => {
+            let imm = sized_imm32(*imm, *dest_size)?;
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), [Word, Dword, Qword], |dest_reg, src_reg| assembler.imul_3(dest_reg, src_reg, imm))?;
+        },
+        _ => return Err("Invalid operands for imul instruction".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_sign_extend_rax(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if !instruction.operands.is_empty() {
+        return Err(format!("{} instruction takes no operands; it always reads rax and writes rdx", instruction.instruction_type.to_string().to_uppercase()));
+    }
+    match instruction.instruction_type {
+        InstructionType::Cdq => assembler.cdq(),
+        _ => assembler.cqo(),
+    }.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
 fn assemble_inc(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let Operand::Register(reg) = &instruction.operands[0] {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.inc(asm_reg).map_err(|e| e.to_string())?;
+    if let Operand::Register(reg, size) = &instruction.operands[0] {
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.inc(asm_reg))?;
     } else {
         return Err("Invalid operand for inc instruction".to_string());
     }
@@ -155,9 +380,8 @@ fn assemble_inc(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_dec(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let Operand::Register(reg) = &instruction.operands[0] {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.dec(asm_reg).map_err(|e| e.to_string())?;
+    if let Operand::Register(reg, size) = &instruction.operands[0] {
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.dec(asm_reg))?;
     } else {
         return Err("Invalid operand for dec instruction".to_string());
     }
@@ -165,9 +389,8 @@ fn assemble_dec(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_neg(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let Operand::Register(reg) = &instruction.operands[0] {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.neg(asm_reg).map_err(|e| e.to_string())?;
+    if let Operand::Register(reg, size) = &instruction.operands[0] {
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.neg(asm_reg))?;
     } else {
         return Err("Invalid operand for neg instruction".to_string());
     }
@@ -175,9 +398,8 @@ fn assemble_neg(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_not(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let Operand::Register(reg) = &instruction.operands[0] {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.not(asm_reg).map_err(|e| e.to_string())?;
+    if let Operand::Register(reg, size) = &instruction.operands[0] {
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.not(asm_reg))?;
     } else {
         return Err("Invalid operand for not instruction".to_string());
     }
@@ -185,9 +407,9 @@ fn assemble_not(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_shl(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.shl(asm_reg, *shift as i32).map_err(|e| e.to_string())?;
+    if let (Operand::Register(reg, size), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
+        let shift = imm32(*shift)?;
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.shl(asm_reg, shift))?;
     } else {
         return Err("Invalid operands for shl instruction".to_string());
     }
@@ -195,9 +417,9 @@ fn assemble_shl(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_shr(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.shr(asm_reg, *shift as i32).map_err(|e| e.to_string())?;
+    if let (Operand::Register(reg, size), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
+        let shift = imm32(*shift)?;
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.shr(asm_reg, shift))?;
     } else {
         return Err("Invalid operands for shr instruction".to_string());
     }
@@ -205,9 +427,9 @@ fn assemble_shr(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_rol(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.rol(asm_reg, *shift as i32).map_err(|e| e.to_string())?;
+    if let (Operand::Register(reg, size), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
+        let shift = imm32(*shift)?;
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.rol(asm_reg, shift))?;
     } else {
         return Err("Invalid operands for rol instruction".to_string());
     }
@@ -215,9 +437,9 @@ fn assemble_rol(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_ror(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-        let asm_reg = parser_register_to_asm_register64(reg);
-        assembler.ror(asm_reg, *shift as i32).map_err(|e| e.to_string())?;
+    if let (Operand::Register(reg, size), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
+        let shift = imm32(*shift)?;
+        with_register!(asm_register(reg, *size), |asm_reg| assembler.ror(asm_reg, shift))?;
     } else {
         return Err("Invalid operands for ror instruction".to_string());
     }
@@ -225,7 +447,8 @@ fn assemble_ror(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 }
 
 fn assemble_push(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let Operand::Register(reg) = &instruction.operands[0] {
+    // Only the 64-bit forms are encodable in 64-bit mode
+    if let Operand::Register(reg, RegisterSize::Qword) = &instruction.operands[0] {
         let asm_reg = parser_register_to_asm_register64(reg);
         assembler.push(asm_reg).map_err(|e| e.to_string())?;
     } else {
@@ -235,7 +458,8 @@ fn assemble_push(assembler: &mut CodeAssembler, instruction: &Instruction) -> Re
 }
 
 fn assemble_pop(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
-    if let Operand::Register(reg) = &instruction.operands[0] {
+    // Only the 64-bit forms are encodable in 64-bit mode
+    if let Operand::Register(reg, RegisterSize::Qword) = &instruction.operands[0] {
         let asm_reg = parser_register_to_asm_register64(reg);
         assembler.pop(asm_reg).map_err(|e| e.to_string())?;
     } else {
@@ -246,14 +470,12 @@ fn assemble_pop(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 
 fn assemble_cmp(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.cmp(dest_reg, *imm as i32).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.cmp(dest_reg, imm))?;
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.cmp(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.cmp(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for cmp instruction".to_string()),
     }
@@ -262,14 +484,12 @@ fn assemble_cmp(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
 
 fn assemble_test(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Immediate(imm)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            assembler.test(dest_reg, *imm as i32).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, size), Operand::Immediate(imm)) => {
+            let imm = sized_imm32(*imm, *size)?;
+            with_register!(asm_register(dest, *size), |dest_reg| assembler.test(dest_reg, imm))?;
         },
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.test(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.test(dest_reg, src_reg))?;
         },
         _ => return Err("Invalid operands for test instruction".to_string()),
     }
@@ -340,6 +560,15 @@ fn assemble_jle(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
     Ok(())
 }
 
+fn assemble_loop(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if let Some(Operand::Immediate(target)) = instruction.operands.first() {
+        assembler.loop_(*target as u64).map_err(|e| e.to_string())?;
+    } else {
+        return Err("Invalid operand for loop instruction".to_string());
+    }
+    Ok(())
+}
+
 fn assemble_call(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     if let Operand::Immediate(target) = instruction.operands[0] {
         assembler.call(target as u64).map_err(|e| e.to_string())?;
@@ -354,6 +583,140 @@ fn assemble_ret(assembler: &mut CodeAssembler, _instruction: &Instruction) -> Re
     Ok(())
 }
 
+fn assemble_syscall(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if !instruction.operands.is_empty() {
+        return Err("SYSCALL instruction takes no operands; the call number goes in rax".to_string());
+    }
+    assembler.syscall().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn assemble_nop(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if !instruction.operands.is_empty() {
+        return Err("NOP instruction takes no operands".to_string());
+    }
+    assembler.nop().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn assemble_direction_flag(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if !instruction.operands.is_empty() {
+        return Err(format!("{} instruction takes no operands", instruction.instruction_type.to_string().to_uppercase()));
+    }
+    match instruction.instruction_type {
+        InstructionType::Cld => assembler.cld(),
+        _ => assembler.std(),
+    }.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn assemble_lea(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if instruction.operands.len() != 2 {
+        return Err("LEA instruction requires exactly two operands".to_string());
+    }
+
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, size), memory @ Operand::Memory { .. }) => {
+            let address = memory_operand_to_asm(memory)?;
+            with_register!(asm_register(dest, *size), [Word, Dword, Qword], |dest_reg| assembler.lea(dest_reg, ptr(address)))?;
+        }
+        (Operand::Register(..), _) => return Err("lea takes a memory operand as its source, e.g. `lea rax, [rbx+rcx*4+8]`".to_string()),
+        _ => return Err("Invalid operands for lea instruction".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_movzx(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if instruction.operands.len() != 2 {
+        return Err("MOVZX instruction requires exactly two operands".to_string());
+    }
+
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, dest_size), memory @ Operand::Memory { size, .. }) => {
+            let address = memory_operand_to_asm(memory)?;
+            let source = match extension_source_width(*size, *dest_size)? {
+                RegisterSize::Byte => byte_ptr(address),
+                RegisterSize::Word => word_ptr(address),
+                _ => return Err("A 32-bit source needs no movzx; `mov` to a 32-bit register already zero-extends".to_string()),
+            };
+            with_register!(asm_register(dest, *dest_size), [Word, Dword, Qword], |dest_reg| assembler.movzx(dest_reg, source))?;
+        }
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            if extension_source_width(Some(*src_size), *dest_size)? == RegisterSize::Dword {
+                return Err("A 32-bit source needs no movzx; `mov` to a 32-bit register already zero-extends".to_string());
+            }
+            with_extension_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.movzx(dest_reg, src_reg))?;
+        }
+        _ => return Err("Invalid operands for movzx instruction".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_movsx(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if instruction.operands.len() != 2 {
+        return Err("MOVSX instruction requires exactly two operands".to_string());
+    }
+
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, dest_size), memory @ Operand::Memory { size, .. }) => {
+            let address = memory_operand_to_asm(memory)?;
+            match extension_source_width(*size, *dest_size)? {
+                RegisterSize::Byte => with_register!(asm_register(dest, *dest_size), [Word, Dword, Qword], |dest_reg| assembler.movsx(dest_reg, byte_ptr(address)))?,
+                RegisterSize::Word => with_register!(asm_register(dest, *dest_size), [Dword, Qword], |dest_reg| assembler.movsx(dest_reg, word_ptr(address)))?,
+                // A 32-bit source has its own mnemonic, movsxd
+                _ => with_register!(asm_register(dest, *dest_size), [Qword], |dest_reg| assembler.movsxd(dest_reg, dword_ptr(address)))?,
+            }
+        }
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            extension_source_width(Some(*src_size), *dest_size)?;
+            match (asm_register(dest, *dest_size), asm_register(src, *src_size)) {
+                (SizedRegister::Qword(dest_reg), SizedRegister::Dword(src_reg)) => assembler.movsxd(dest_reg, src_reg).map_err(|e| e.to_string())?,
+                (dest_reg, src_reg) => with_extension_registers!(dest_reg, src_reg, |dest_reg, src_reg| assembler.movsx(dest_reg, src_reg))?,
+            }
+        }
+        _ => return Err("Invalid operands for movsx instruction".to_string()),
+    }
+    Ok(())
+}
+
+// The source of movzx/movsx must have a known width narrower than the destination
+fn extension_source_width(source: Option<RegisterSize>, dest: RegisterSize) -> Result<RegisterSize, String> {
+    let source = source.ok_or("The memory source needs a width, e.g. `byte [rbx]` or `word [rbx]`")?;
+    if source.bits() >= dest.bits() {
+        return Err(format!("The source ({} bits) must be narrower than the destination ({} bits)", source.bits(), dest.bits()));
+    }
+    Ok(source)
+}
+
+fn assemble_xchg(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if instruction.operands.len() != 2 {
+        return Err("XCHG instruction requires exactly two operands".to_string());
+    }
+
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        // The short form of `xchg eax, eax` would be 0x90, which is `nop` and leaves the upper half
+        // of rax alone; the long form is what actually performs the zero-extending write
+        (Operand::Register(ParserRegister::Rax, RegisterSize::Dword), Operand::Register(ParserRegister::Rax, RegisterSize::Dword)) => {
+            let xchg = iced_x86::Instruction::with2(Code::Xchg_rm32_r32, Register::EAX, Register::EAX).map_err(|e| e.to_string())?;
+            assembler.add_instruction(xchg).map_err(|e| e.to_string())?;
+        }
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), |dest_reg, src_reg| assembler.xchg(dest_reg, src_reg))?;
+        }
+        // The register/memory form is symmetric, so both operand orders encode the same way
+        (Operand::Register(reg, reg_size), memory @ Operand::Memory { size, .. }) |
+        (memory @ Operand::Memory { size, .. }, Operand::Register(reg, reg_size)) => {
+            if size.is_some_and(|size| size.bits() != reg_size.bits()) {
+                return Err("Operand sizes do not match".to_string());
+            }
+            let address = memory_operand_to_asm(memory)?;
+            with_register!(asm_register(reg, *reg_size), |reg| assembler.xchg(ptr(address), reg))?;
+        }
+        _ => return Err("Invalid operands for xchg instruction".to_string()),
+    }
+    Ok(())
+}
+
 fn assemble_paddd(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     if instruction.operands.len() != 2 {
         return Err("PADDD instruction requires exactly two operands".to_string());
@@ -362,10 +725,10 @@ fn assemble_paddd(assembler: &mut CodeAssembler, instruction: &Instruction) -> R
     match (&instruction.operands[0], &instruction.operands[1]) {
         (Operand::XmmRegister(dest), Operand::XmmRegister(src)) => {
             let dest_reg = xmm_index_to_register(*dest)
-                .and_then(|r| xmm::get_xmm(r))
+                .and_then(xmm::get_xmm)
                 .ok_or("Invalid destination XMM register")?;
             let src_reg = xmm_index_to_register(*src)
-                .and_then(|r| xmm::get_xmm(r))
+                .and_then(xmm::get_xmm)
                 .ok_or("Invalid source XMM register")?;
             assembler.paddd(dest_reg, src_reg).map_err(|e| e.to_string())?;
         }
@@ -405,32 +768,270 @@ fn assemble_bsf(assembler: &mut CodeAssembler, instruction: &Instruction) -> Res
     }
 
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.bsf(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), [Word, Dword, Qword], |dest_reg, src_reg| assembler.bsf(dest_reg, src_reg))?;
         }
         _ => return Err("Invalid operands for bsf instruction".to_string()),
     }
     Ok(())
 }
 
+fn assemble_bsr(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if instruction.operands.len() != 2 {
+        return Err("BSR instruction requires exactly two operands".to_string());
+    }
+
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), [Word, Dword, Qword], |dest_reg, src_reg| assembler.bsr(dest_reg, src_reg))?;
+        }
+        _ => return Err("Invalid operands for bsr instruction".to_string()),
+    }
+    Ok(())
+}
+
+fn assemble_bswap(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if let Some(Operand::Register(reg, size)) = instruction.operands.first() {
+        // bswap on a 16-bit register is undefined, so only 32 and 64 bits are accepted
+        with_register!(asm_register(reg, *size), [Dword, Qword], |asm_reg| assembler.bswap(asm_reg))?;
+    } else {
+        return Err("Invalid operand for bswap instruction".to_string());
+    }
+    Ok(())
+}
+
+fn assemble_bit_count(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if instruction.operands.len() != 2 {
+        return Err(format!("{} instruction requires exactly two operands", instruction.instruction_type.to_string().to_uppercase()));
+    }
+
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), [Word, Dword, Qword], |dest_reg, src_reg| match instruction.instruction_type {
+                InstructionType::Popcnt => assembler.popcnt(dest_reg, src_reg),
+                InstructionType::Lzcnt => assembler.lzcnt(dest_reg, src_reg),
+                _ => assembler.tzcnt(dest_reg, src_reg),
+            })?;
+        }
+        _ => return Err(format!("Invalid operands for {} instruction", instruction.instruction_type)),
+    }
+    Ok(())
+}
+
+fn assemble_bit_test(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    if instruction.operands.len() != 2 {
+        return Err(format!("{} instruction requires exactly two operands", instruction.instruction_type.to_string().to_uppercase()));
+    }
+
+    match (&instruction.operands[0], &instruction.operands[1]) {
+        (Operand::Register(dest, size), Operand::Immediate(index)) => {
+            let index = u8::try_from(*index).map_err(|_| format!("Bit index {} must be between 0 and 255", index))? as i32;
+            with_register!(asm_register(dest, *size), [Word, Dword, Qword], |dest_reg| match instruction.instruction_type {
+                InstructionType::Bt => assembler.bt(dest_reg, index),
+                InstructionType::Bts => assembler.bts(dest_reg, index),
+                InstructionType::Btr => assembler.btr(dest_reg, index),
+                _ => assembler.btc(dest_reg, index),
+            })?;
+        }
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), [Word, Dword, Qword], |dest_reg, src_reg| match instruction.instruction_type {
+                InstructionType::Bt => assembler.bt(dest_reg, src_reg),
+                InstructionType::Bts => assembler.bts(dest_reg, src_reg),
+                InstructionType::Btr => assembler.btr(dest_reg, src_reg),
+                _ => assembler.btc(dest_reg, src_reg),
+            })?;
+        }
+        _ => return Err(format!("Invalid operands for {} instruction", instruction.instruction_type)),
+    }
+    Ok(())
+}
+
 fn assemble_cmovne(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
     if instruction.operands.len() != 2 {
         return Err("CMOVNE instruction requires exactly two operands".to_string());
     }
 
     match (&instruction.operands[0], &instruction.operands[1]) {
-        (Operand::Register(dest), Operand::Register(src)) => {
-            let dest_reg = parser_register_to_asm_register64(dest);
-            let src_reg = parser_register_to_asm_register64(src);
-            assembler.cmovne(dest_reg, src_reg).map_err(|e| e.to_string())?;
+        (Operand::Register(dest, dest_size), Operand::Register(src, src_size)) => {
+            with_registers!(asm_register(dest, *dest_size), asm_register(src, *src_size), [Word, Dword, Qword], |dest_reg, src_reg| assembler.cmovne(dest_reg, src_reg))?;
         }
         _ => return Err("Invalid operands for cmovne instruction".to_string()),
     }
     Ok(())
 }
 
+fn assemble_setcc(assembler: &mut CodeAssembler, instruction: &Instruction) -> Result<(), String> {
+    let [Operand::Register(dest, size)] = instruction.operands.as_slice() else {
+        return Err(format!("{} takes a single byte register, e.g. `{} al`", instruction.instruction_type, instruction.instruction_type));
+    };
+
+    with_register!(asm_register(dest, *size), [Byte], |dest_reg| match instruction.instruction_type {
+        InstructionType::Sete => assembler.sete(dest_reg),
+        InstructionType::Setne => assembler.setne(dest_reg),
+        InstructionType::Setg => assembler.setg(dest_reg),
+        InstructionType::Setge => assembler.setge(dest_reg),
+        InstructionType::Setl => assembler.setl(dest_reg),
+        InstructionType::Setle => assembler.setle(dest_reg),
+        InstructionType::Seta => assembler.seta(dest_reg),
+        InstructionType::Setae => assembler.setae(dest_reg),
+        InstructionType::Setb => assembler.setb(dest_reg),
+        _ => assembler.setbe(dest_reg),
+    })?;
+    Ok(())
+}
+
+// Catches the common `mov 5, rax` mistake (and stray labels) with a precise message instead of a generic operand error
+fn validate_operands(instruction: &Instruction) -> Result<(), String> {
+    use InstructionType::*;
+
+    if let Some(Operand::Label(name)) = instruction.operands.iter().find(|op| matches!(op, Operand::Label(_))) {
+        return Err(if instruction.instruction_type.is_branch() {
+            format!("{}: label '{}' can only be used in a program (see :multi)", instruction.instruction_type, name)
+        } else {
+            // Register names must match exactly, so typos like `raxx` or `r1` end up here
+            format!("{}: '{}' is not a register or a value", instruction.instruction_type, name)
+        });
+    }
+    if let Some(Operand::Immediate(value)) = instruction.operands.first() {
+        match instruction.instruction_type {
+            Mov | Add | Sub | Adc | Sbb | And | Or | Xor | Imul | Inc | Dec | Neg | Not |
+            Shl | Shr | Rol | Ror | Pop | Lea | Bsf | Bsr | Bswap | Popcnt | Lzcnt | Tzcnt | Bt | Bts | Btr | Btc | Cmovne |
+            Sete | Setne | Setg | Setge | Setl | Setle | Seta | Setae | Setb | Setbe | Movzx | Movsx | Xchg | Paddd => {
+                return Err(format!("{}: destination cannot be an immediate ({})", instruction.instruction_type, value));
+            }
+            Cmp | Test => {
+                return Err(format!("{}: the first operand cannot be an immediate ({}); put the register first", instruction.instruction_type, value));
+            }
+            Push | Mul | Div | Idiv | Jmp | Je | Jne | Jg | Jge | Jl | Jle | Loop | Call | Ret | Syscall | Nop | Cld | Std | Cdq | Cqo => {}
+        }
+    }
+    Ok(())
+}
+
+// Most instructions only encode a sign-extended 32-bit immediate; only `mov` takes a full 64 bits
+fn imm32(value: i64) -> Result<i32, String> {
+    i32::try_from(value).map_err(|_| format!("Immediate {:#x} does not fit in a sign-extended 32-bit field", value))
+}
+
+// A narrower operation takes any pattern of its width, signed or not, e.g. `and eax, 0xffffffff` or `mov al, -1`
+fn sized_imm32(value: i64, size: RegisterSize) -> Result<i32, String> {
+    if size == RegisterSize::Qword {
+        return imm32(value);
+    }
+    let bits = size.bits();
+    let min = -(1i64 << (bits - 1));
+    let max = (1i64 << bits) - 1;
+    if (min..=max).contains(&value) {
+        Ok(value as i32)
+    } else {
+        Err(format!("Immediate {:#x} does not fit in {} bits", value, bits))
+    }
+}
+
+fn memory_operand_to_asm(operand: &Operand) -> Result<AsmMemoryOperand, String> {
+    let Operand::Memory { base, index, scale, disp, .. } = operand else {
+        return Err("Expected a memory operand".to_string());
+    };
+    let disp = i32::try_from(*disp).map_err(|_| format!("Displacement {} does not fit in 32 bits", disp))?;
+
+    let mut address = AsmMemoryOperand::from(disp);
+    if let Some(base) = base {
+        address = address + parser_register_to_asm_register64(base);
+    }
+    if let Some(index) = index {
+        address = address + parser_register_to_asm_register64(index) * *scale;
+    }
+    Ok(address)
+}
+
+fn asm_register(reg: &ParserRegister, size: RegisterSize) -> SizedRegister {
+    match size {
+        RegisterSize::Byte => SizedRegister::Byte(parser_register_to_asm_register8(reg)),
+        RegisterSize::HighByte => SizedRegister::Byte(parser_register_to_asm_high_byte(reg)),
+        RegisterSize::Word => SizedRegister::Word(parser_register_to_asm_register16(reg)),
+        RegisterSize::Dword => SizedRegister::Dword(parser_register_to_asm_register32(reg)),
+        RegisterSize::Qword => SizedRegister::Qword(parser_register_to_asm_register64(reg)),
+    }
+}
+
+fn parser_register_to_asm_register8(reg: &ParserRegister) -> AsmRegister8 {
+    use iced_x86::code_asm::registers::*;
+    match reg {
+        ParserRegister::Rax => al,
+        ParserRegister::Rbx => bl,
+        ParserRegister::Rcx => cl,
+        ParserRegister::Rdx => dl,
+        ParserRegister::Rsi => sil,
+        ParserRegister::Rdi => dil,
+        ParserRegister::Rbp => bpl,
+        ParserRegister::Rsp => spl,
+        ParserRegister::R8  => r8b,
+        ParserRegister::R9  => r9b,
+        ParserRegister::R10 => r10b,
+        ParserRegister::R11 => r11b,
+        ParserRegister::R12 => r12b,
+        ParserRegister::R13 => r13b,
+        ParserRegister::R14 => r14b,
+        ParserRegister::R15 => r15b,
+    }
+}
+
+// The parser only produces high-byte operands for rax..rdx
+fn parser_register_to_asm_high_byte(reg: &ParserRegister) -> AsmRegister8 {
+    use iced_x86::code_asm::registers::*;
+    match reg {
+        ParserRegister::Rax => ah,
+        ParserRegister::Rbx => bh,
+        ParserRegister::Rcx => ch,
+        ParserRegister::Rdx => dh,
+        other => unreachable!("{} has no high-byte form", other),
+    }
+}
+
+fn parser_register_to_asm_register16(reg: &ParserRegister) -> AsmRegister16 {
+    use iced_x86::code_asm::registers::*;
+    match reg {
+        ParserRegister::Rax => ax,
+        ParserRegister::Rbx => bx,
+        ParserRegister::Rcx => cx,
+        ParserRegister::Rdx => dx,
+        ParserRegister::Rsi => si,
+        ParserRegister::Rdi => di,
+        ParserRegister::Rbp => bp,
+        ParserRegister::Rsp => sp,
+        ParserRegister::R8  => r8w,
+        ParserRegister::R9  => r9w,
+        ParserRegister::R10 => r10w,
+        ParserRegister::R11 => r11w,
+        ParserRegister::R12 => r12w,
+        ParserRegister::R13 => r13w,
+        ParserRegister::R14 => r14w,
+        ParserRegister::R15 => r15w,
+    }
+}
+
+fn parser_register_to_asm_register32(reg: &ParserRegister) -> AsmRegister32 {
+    use iced_x86::code_asm::registers::*;
+    match reg {
+        ParserRegister::Rax => eax,
+        ParserRegister::Rbx => ebx,
+        ParserRegister::Rcx => ecx,
+        ParserRegister::Rdx => edx,
+        ParserRegister::Rsi => esi,
+        ParserRegister::Rdi => edi,
+        ParserRegister::Rbp => ebp,
+        ParserRegister::Rsp => esp,
+        ParserRegister::R8  => r8d,
+        ParserRegister::R9  => r9d,
+        ParserRegister::R10 => r10d,
+        ParserRegister::R11 => r11d,
+        ParserRegister::R12 => r12d,
+        ParserRegister::R13 => r13d,
+        ParserRegister::R14 => r14d,
+        ParserRegister::R15 => r15d,
+    }
+}
+
 fn parser_register_to_asm_register64(reg: &ParserRegister) -> AsmRegister64 {
     use iced_x86::code_asm::registers::*;
     match reg {
@@ -451,4 +1052,22 @@ fn parser_register_to_asm_register64(reg: &ParserRegister) -> AsmRegister64 {
         ParserRegister::R14 => r14,
         ParserRegister::R15 => r15,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_complete_instruction;
+
+    fn assemble(line: &str) -> Vec<u8> {
+        assemble_instruction(&parse_complete_instruction(line).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn xchg_with_itself_is_not_the_one_byte_nop() {
+        assert_eq!(assemble("nop"), vec![0x90]);
+        assert_eq!(assemble("xchg rax, rax"), vec![0x48, 0x90]);
+        // 0x90 would leave the upper half of rax alone, but this clears it
+        assert_eq!(assemble("xchg eax, eax"), vec![0x87, 0xc0]);
+    }
+}
@@ -0,0 +1,85 @@
+/// The lines entered in multi-instruction mode, waiting for `run`. Lines are numbered from 1,
+/// like breakpoints and the parser's errors.
+pub struct Buffer {
+    lines: Vec<String>,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Buffer { lines: Vec::new() }
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Removes and returns line `number`.
+    pub fn delete(&mut self, number: usize) -> Result<String, String> {
+        if number == 0 || number > self.lines.len() {
+            return Err(self.out_of_range(number));
+        }
+        Ok(self.lines.remove(number - 1))
+    }
+
+    /// Inserts `line` before line `number`; one past the last line appends.
+    pub fn insert(&mut self, number: usize, line: String) -> Result<(), String> {
+        if number == 0 || number > self.lines.len() + 1 {
+            return Err(format!("Can't insert before line {}: use a line from 1 to {}", number, self.lines.len() + 1));
+        }
+        self.lines.insert(number - 1, line);
+        Ok(())
+    }
+
+    fn out_of_range(&self, number: usize) -> String {
+        match self.lines.len() {
+            0 => format!("No line {}: the buffer is empty", number),
+            len => format!("No line {}: the buffer has lines 1 to {}", number, len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[&str]) -> Buffer {
+        let mut buffer = Buffer::new();
+        for line in lines {
+            buffer.push(line.to_string());
+        }
+        buffer
+    }
+
+    #[test]
+    fn delete_and_insert_count_from_one() {
+        let mut buffer = buffer(&["a", "b", "c"]);
+        assert_eq!(buffer.delete(2), Ok("b".to_string()));
+        buffer.insert(1, "z".to_string()).unwrap();
+        buffer.insert(4, "end".to_string()).unwrap();
+        assert_eq!(buffer.lines(), ["z", "a", "c", "end"]);
+    }
+
+    #[test]
+    fn rejects_lines_out_of_range() {
+        let mut buffer = buffer(&["a"]);
+        assert_eq!(buffer.delete(0), Err("No line 0: the buffer has lines 1 to 1".to_string()));
+        assert_eq!(buffer.delete(2), Err("No line 2: the buffer has lines 1 to 1".to_string()));
+        assert_eq!(buffer.insert(3, "x".to_string()),
+                   Err("Can't insert before line 3: use a line from 1 to 2".to_string()));
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.delete(1), Err("No line 1: the buffer is empty".to_string()));
+    }
+}
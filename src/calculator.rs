@@ -1,8 +1,59 @@
+use std::collections::HashMap;
 use std::f64;
 use crate::cpu::CPU;
+use crate::expr;
 
-pub fn calculate(input: &str, cpu: &CPU) -> Result<String, String> {
-    let tokens: Vec<&str> = input.split_whitespace().collect();
+// Holds the last integer result
+const ANSWER: &str = "ans";
+
+// What a calculation shows, and the integer it produced, if any, for `ans` and assignments
+struct Calculated {
+    text: String,
+    value: Option<i64>,
+}
+
+impl Calculated {
+    fn integer(text: String, value: i64) -> Self {
+        Calculated { text, value: Some(value) }
+    }
+
+    fn text(text: String) -> Self {
+        Calculated { text, value: None }
+    }
+}
+
+/// Runs one line of calculator mode. `x = <calculation>` stores the result in a variable, and
+/// every integer result is kept in `ans`; both can be used in later calculations.
+pub fn calculate(input: &str, cpu: &CPU, variables: &mut HashMap<String, i64>) -> Result<String, String> {
+    let calculated = match input.split_once('=') {
+        Some((name, calculation)) => assign(name.trim(), calculation, cpu, variables)?,
+        None => evaluate(input, cpu, variables)?,
+    };
+    if let Some(value) = calculated.value {
+        variables.insert(ANSWER.to_string(), value);
+    }
+    Ok(calculated.text)
+}
+
+fn assign(name: &str, calculation: &str, cpu: &CPU, variables: &mut HashMap<String, i64>) -> Result<Calculated, String> {
+    let is_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_name {
+        return Err(format!("Can't assign to '{}': a variable name is letters, digits and _, starting with a letter", name));
+    }
+    let name = name.to_lowercase();
+    if name == ANSWER || expr::is_register_name(&name) {
+        return Err(format!("Can't assign to '{}': it's reserved", name));
+    }
+    let value = evaluate(calculation, cpu, variables)?.value
+        .ok_or_else(|| format!("Can't store '{}' in {}: only integers can be stored", calculation.trim(), name))?;
+    variables.insert(name.clone(), value);
+    Ok(Calculated::integer(format!("{} = {} ({:#x})", name, value, value), value))
+}
+
+fn evaluate(input: &str, cpu: &CPU, variables: &HashMap<String, i64>) -> Result<Calculated, String> {
+    let tokens = substitute_variables(input, variables);
+    let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
     
     if tokens.is_empty() {
         return Err("No input provided".to_string());
@@ -10,6 +61,7 @@ pub fn calculate(input: &str, cpu: &CPU) -> Result<String, String> {
 
     match tokens[0] {
         "hex" | "bin" | "dec" => convert_base(tokens),
+        "base" => convert_any_base(tokens),
         "and" | "or" | "xor" | "not" => bitwise_op(tokens),
         "sin" | "cos" | "tan" => trig_op(tokens),
         "+" | "-" | "*" | "/" => arithmetic_op(tokens),
@@ -17,74 +69,157 @@ pub fn calculate(input: &str, cpu: &CPU) -> Result<String, String> {
         "rol" | "ror" => bit_rotate(tokens),
         "twos" => twos_complement(tokens),
         "float_to_ieee" => float_to_ieee754(tokens),
+        "double_to_ieee" => double_to_ieee754(tokens),
+        "ieee_to_float" | "ieee_to_double" => ieee754_to_float(tokens),
         "reg" => register_value(tokens, cpu),
-        _ => Err("Unknown operation".to_string()),
+        // Anything else is an infix expression, like `(3 + 4) * 2`
+        _ => expr::evaluate(input, cpu, variables).map(|value| Calculated::integer(format!("Result: {} ({:#x})", value, value), value)),
+    }
+}
+
+// Replaces the arguments of the prefix forms (`* ans 4`) that name variables with their values
+fn substitute_variables(input: &str, variables: &HashMap<String, i64>) -> Vec<String> {
+    let mut tokens = input.split_whitespace();
+    let Some(command) = tokens.next() else {
+        return Vec::new();
+    };
+    // `base` takes no prefixes, and in base 36 `ans` is already a number
+    if command == "base" {
+        return input.split_whitespace().map(String::from).collect();
     }
+    // The base conversions and bit patterns read digits in their own base, but a `0x` prefix overrides it
+    let base_conversion = matches!(command, "hex" | "bin" | "dec" | "ieee_to_float" | "ieee_to_double");
+    let arguments = tokens.map(|token| match variables.get(&token.to_lowercase()) {
+        Some(value) if base_conversion => format!("{:#x}", *value as u64),
+        Some(value) => value.to_string(),
+        None => token.to_string(),
+    });
+    std::iter::once(command.to_string()).chain(arguments).collect()
 }
 
-fn bit_shift(tokens: Vec<&str>) -> Result<String, String> {
+// A `0x` hex or `0b` binary literal as its 64-bit pattern, or None if `token` has neither prefix
+fn parse_prefixed(token: &str) -> Option<Result<u64, String>> {
+    let (radix, name) = match token.get(..2).map(str::to_lowercase).as_deref() {
+        Some("0x") => (16, "hex"),
+        Some("0b") => (2, "binary"),
+        _ => return None,
+    };
+    let digits = &token[2..];
+    if digits.is_empty() {
+        return Some(Err(format!("expected {} digits after {}", name, &token[..2])));
+    }
+    Some(u64::from_str_radix(digits, radix).map_err(|e| format!("invalid {} literal {}: {}", name, token, e)))
+}
+
+/// Parses a decimal, `0x` hex or `0b` binary integer, optionally negative. Values past `i64::MAX`,
+/// like `0xffffffffffffffff`, come back as the same 64-bit pattern, so they read as negative.
+pub fn parse_number(token: &str) -> Result<i64, String> {
+    if let Some(bits) = parse_prefixed(token) {
+        return bits.map(|bits| bits as i64);
+    }
+    if let Some(bits) = token.strip_prefix('-').and_then(parse_prefixed) {
+        return bits.and_then(|bits| match bits {
+            bits if bits > i64::MIN.unsigned_abs() => Err(format!("{} is out of range", token)),
+            bits => Ok((bits as i64).wrapping_neg()),
+        });
+    }
+    token.parse::<i64>()
+        .or_else(|e| token.parse::<u64>().map(|bits| bits as i64).map_err(|_| e))
+        .map_err(|e| e.to_string())
+}
+
+// Shift and rotate counts, which can be written in any base too
+fn parse_count(token: &str) -> Result<u32, String> {
+    let count = parse_number(token)?;
+    u32::try_from(count).map_err(|_| format!("{} is out of range", count))
+}
+
+fn bit_shift(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() != 3 {
         return Err("Usage: shl/shr <value> <shift amount>".to_string());
     }
-    let value = tokens[1].parse::<u64>().map_err(|e| format!("Invalid value: {}", e))?;
-    let shift = tokens[2].parse::<u32>().map_err(|e| format!("Invalid shift amount: {}", e))?;
+    let value = parse_number(tokens[1]).map_err(|e| format!("Invalid value: {}", e))? as u64;
+    let shift = parse_count(tokens[2]).map_err(|e| format!("Invalid shift amount: {}", e))?;
+    // The count is masked to 6 bits, as for `shl rax, cl`, so `shl 1 64` shifts by 0
     let result = match tokens[0] {
-        "shl" => value << shift,
-        "shr" => value >> shift,
+        "shl" => value.wrapping_shl(shift),
+        "shr" => value.wrapping_shr(shift),
         _ => unreachable!(),
     };
-    Ok(format!("Result: {:#x} ({})", result, result))
+    Ok(Calculated::integer(format!("Result: {:#x} ({})", result, result), result as i64))
 }
 
-fn bit_rotate(tokens: Vec<&str>) -> Result<String, String> {
+fn bit_rotate(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() != 3 {
         return Err("Usage: rol/ror <value> <rotate amount>".to_string());
     }
-    let value = tokens[1].parse::<u64>().map_err(|e| format!("Invalid value: {}", e))?;
-    let rotate = tokens[2].parse::<u32>().map_err(|e| format!("Invalid rotate amount: {}", e))?;
+    let value = parse_number(tokens[1]).map_err(|e| format!("Invalid value: {}", e))? as u64;
+    let rotate = parse_count(tokens[2]).map_err(|e| format!("Invalid rotate amount: {}", e))?;
     let result = match tokens[0] {
         "rol" => value.rotate_left(rotate),
         "ror" => value.rotate_right(rotate),
         _ => unreachable!(),
     };
-    Ok(format!("Result: {:#x} ({})", result, result))
+    Ok(Calculated::integer(format!("Result: {:#x} ({})", result, result), result as i64))
 }
 
-fn twos_complement(tokens: Vec<&str>) -> Result<String, String> {
+fn twos_complement(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() != 2 {
         return Err("Usage: twos <value>".to_string());
     }
-    let value = tokens[1].parse::<i64>().map_err(|e| format!("Invalid value: {}", e))?;
+    let value = parse_number(tokens[1]).map_err(|e| format!("Invalid value: {}", e))?;
     let result = (!value).wrapping_add(1);
-    Ok(format!("Two's complement: {:#x} ({})", result, result))
+    Ok(Calculated::integer(format!("Two's complement: {:#x} ({})", result, result), result))
 }
 
-fn float_to_ieee754(tokens: Vec<&str>) -> Result<String, String> {
+fn float_to_ieee754(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() != 2 {
         return Err("Usage: float_to_ieee <value>".to_string());
     }
     let value = tokens[1].parse::<f32>().map_err(|e| format!("Invalid float: {}", e))?;
     let bits = value.to_bits();
-    Ok(format!("IEEE 754: {:#x}", bits))
+    Ok(Calculated::integer(format!("IEEE 754: {:#x}", bits), bits as i64))
 }
 
-fn register_value(tokens: Vec<&str>, cpu: &CPU) -> Result<String, String> {
+fn double_to_ieee754(tokens: Vec<&str>) -> Result<Calculated, String> {
+    if tokens.len() != 2 {
+        return Err("Usage: double_to_ieee <value>".to_string());
+    }
+    let value = tokens[1].parse::<f64>().map_err(|e| format!("Invalid double: {}", e))?;
+    let bits = value.to_bits();
+    Ok(Calculated::integer(format!("IEEE 754: {:#x}", bits), bits as i64))
+}
+
+// The bit pattern is hex, with or without `0x`; a `0b` prefix reads it as binary instead
+fn ieee754_to_float(tokens: Vec<&str>) -> Result<Calculated, String> {
+    if tokens.len() != 2 {
+        return Err(format!("Usage: {} <bits>", tokens[0]));
+    }
+    let bits = match parse_prefixed(tokens[1]) {
+        Some(bits) => bits,
+        None => u64::from_str_radix(tokens[1], 16).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| format!("Invalid bit pattern: {}", e))?;
+
+    let text = if tokens[0] == "ieee_to_float" {
+        let bits = u32::try_from(bits).map_err(|_| format!("Invalid bit pattern: {:#x} is wider than 32 bits", bits))?;
+        format!("Float: {:?}", f32::from_bits(bits))
+    } else {
+        format!("Double: {:?}", f64::from_bits(bits))
+    };
+    Ok(Calculated::text(text))
+}
+
+fn register_value(tokens: Vec<&str>, cpu: &CPU) -> Result<Calculated, String> {
     if tokens.len() != 2 {
         return Err("Usage: reg <register_name>".to_string());
     }
     let reg_name = tokens[1].to_uppercase();
-    let value = match reg_name.as_str() {
-        "RAX" => cpu.rax,
-        "RBX" => cpu.rbx,
-        "RCX" => cpu.rcx,
-        "RDX" => cpu.rdx,
-        // ... add other registers as needed
-        _ => return Err(format!("Unknown register: {}", reg_name)),
-    };
-    Ok(format!("{} value: {:#x} ({})", reg_name, value, value))
+    let value = expr::read_register(tokens[1], cpu).ok_or_else(|| format!("Unknown register: {}", reg_name))?;
+    Ok(Calculated::integer(format!("{} value: {:#x} ({})", reg_name, value, value), value as i64))
 }
 
-fn convert_base(tokens: Vec<&str>) -> Result<String, String> {
+fn convert_base(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() != 2 {
         return Err("Usage: hex/bin/dec <value>".to_string());
     }
@@ -96,19 +231,63 @@ fn convert_base(tokens: Vec<&str>) -> Result<String, String> {
         _ => return Err("Invalid base specified".to_string()),
     };
 
-    let value = i64::from_str_radix(value, base)
-        .map_err(|e| format!("Invalid input: {}", e))?;
+    // An explicit `0x` or `0b` prefix wins over the command's base
+    let value = match parse_prefixed(value) {
+        Some(bits) => bits.map(|bits| bits as i64),
+        None => i64::from_str_radix(value, base).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| format!("Invalid input: {}", e))?;
     
-    Ok(format!("Hex: {:#x}\nDecimal: {}\nBinary: {:#b}", value, value, value))
+    Ok(Calculated::integer(format!("Hex: {:#x}\nDecimal: {}\nBinary: {:#b}", value, value, value), value))
+}
+
+// `base 16 2 ff`: from any base from 2 to 36 to any other
+fn convert_any_base(tokens: Vec<&str>) -> Result<Calculated, String> {
+    if tokens.len() != 4 {
+        return Err("Usage: base <from> <to> <value>, e.g. base 16 2 ff".to_string());
+    }
+    let from = parse_radix(tokens[1])?;
+    let to = parse_radix(tokens[2])?;
+    let digits = tokens[3].strip_prefix(['-', '+']).unwrap_or(tokens[3]);
+    if let Some(bad) = digits.chars().find(|c| !c.is_digit(from)) {
+        return Err(format!("Invalid input: '{}' isn't a digit in base {}", bad, from));
+    }
+    let value = i64::from_str_radix(tokens[3], from).map_err(|e| format!("Invalid input: {}", e))?;
+
+    Ok(Calculated::integer(format!("Base {}: {}\nDecimal: {}", to, format_radix(value, to), value), value))
 }
 
-fn bitwise_op(tokens: Vec<&str>) -> Result<String, String> {
+fn parse_radix(token: &str) -> Result<u32, String> {
+    match token.parse::<u32>() {
+        Ok(radix @ 2..=36) => Ok(radix),
+        _ => Err(format!("Invalid base '{}': use a base from 2 to 36", token)),
+    }
+}
+
+// std only formats bases 2, 8, 10 and 16; digits past 9 are lowercase letters
+fn format_radix(value: i64, radix: u32) -> String {
+    let mut magnitude = value.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(std::char::from_digit((magnitude % radix as u64) as u32, radix).expect("a digit below the radix"));
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if value < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+fn bitwise_op(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() < 2 {
         return Err("Not enough arguments for bitwise operation".to_string());
     }
     
     let op = tokens[0];
-    let values: Result<Vec<u64>, _> = tokens[1..].iter().map(|&s| s.parse()).collect();
+    let values: Result<Vec<u64>, _> = tokens[1..].iter().map(|&s| parse_number(s).map(|v| v as u64)).collect();
     let values = values.map_err(|e| format!("Invalid input: {}", e))?;
     
     let result = match op {
@@ -119,10 +298,10 @@ fn bitwise_op(tokens: Vec<&str>) -> Result<String, String> {
         _ => return Err("Unknown bitwise operation".to_string()),
     };
     
-    Ok(format!("Result: {:#x} ({})", result, result))
+    Ok(Calculated::integer(format!("Result: {:#x} ({})", result, result), result as i64))
 }
 
-fn trig_op(tokens: Vec<&str>) -> Result<String, String> {
+fn trig_op(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() != 2 {
         return Err("Invalid number of arguments for trigonometric operation".to_string());
     }
@@ -137,17 +316,22 @@ fn trig_op(tokens: Vec<&str>) -> Result<String, String> {
         _ => return Err("Unknown trigonometric operation".to_string()),
     };
     
-    Ok(format!("Result: {}", result))
+    Ok(Calculated::text(format!("Result: {}", result)))
 }
 
-fn arithmetic_op(tokens: Vec<&str>) -> Result<String, String> {
+fn arithmetic_op(tokens: Vec<&str>) -> Result<Calculated, String> {
     if tokens.len() != 3 {
         return Err("Invalid number of arguments for arithmetic operation".to_string());
     }
     
     let op = tokens[0];
-    let a: f64 = tokens[1].parse().map_err(|e| format!("Invalid first operand: {}", e))?;
-    let b: f64 = tokens[2].parse().map_err(|e| format!("Invalid second operand: {}", e))?;
+    // Division keeps its fractional result; the other operators wrap like the CPU does
+    if let (true, Some(a), Some(b)) = (op != "/", parse_integer(tokens[1]), parse_integer(tokens[2])) {
+        return integer_arithmetic(op, a, b);
+    }
+
+    let a = parse_float(tokens[1]).map_err(|e| format!("Invalid first operand: {}", e))?;
+    let b = parse_float(tokens[2]).map_err(|e| format!("Invalid second operand: {}", e))?;
     
     let result = match op {
         "+" => a + b,
@@ -162,5 +346,156 @@ fn arithmetic_op(tokens: Vec<&str>) -> Result<String, String> {
         _ => return Err("Unknown arithmetic operation".to_string()),
     };
     
-    Ok(format!("Result: {}", result))
-}
\ No newline at end of file
+    Ok(Calculated::text(format!("Result: {}", result)))
+}
+
+// A decimal number, which may have a fraction, or a `0x`/`0b` integer
+fn parse_float(token: &str) -> Result<f64, String> {
+    match parse_prefixed(token) {
+        Some(bits) => bits.map(|bits| bits as f64),
+        None => token.parse().map_err(|e: std::num::ParseFloatError| e.to_string()),
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Integer {
+    bits: u64,
+    unsigned_only: bool, // Too large for i64, so only meaningful as an unsigned value
+}
+
+fn parse_integer(token: &str) -> Option<Integer> {
+    if let Some(bits) = parse_prefixed(token) {
+        let bits = bits.ok()?;
+        return Some(Integer { bits, unsigned_only: bits > i64::MAX as u64 });
+    }
+    if let Ok(value) = token.parse::<i64>() {
+        Some(Integer { bits: value as u64, unsigned_only: false })
+    } else {
+        token.parse::<u64>().ok().map(|bits| Integer { bits, unsigned_only: true })
+    }
+}
+
+// Works on 64-bit patterns like the CPU does: the wrapped result is shown, and
+// signed (OF) and unsigned (CF) overflow are reported separately.
+fn integer_arithmetic(op: &str, a: Integer, b: Integer) -> Result<Calculated, String> {
+    let unsigned = a.unsigned_only || b.unsigned_only;
+    let (x, y) = (a.bits, b.bits);
+
+    let (result, signed_overflow, unsigned_overflow) = match op {
+        "+" => {
+            let (r, of) = (x as i64).overflowing_add(y as i64);
+            (r as u64, of, x.overflowing_add(y).1)
+        }
+        "-" => {
+            let (r, of) = (x as i64).overflowing_sub(y as i64);
+            (r as u64, of, x.overflowing_sub(y).1)
+        }
+        "*" => {
+            let (r, of) = (x as i64).overflowing_mul(y as i64);
+            (r as u64, of, x.overflowing_mul(y).1)
+        }
+        _ => return Err("Unknown arithmetic operation".to_string()),
+    };
+
+    let (shown, wrapped) = if unsigned {
+        (result.to_string(), unsigned_overflow)
+    } else {
+        ((result as i64).to_string(), signed_overflow)
+    };
+
+    let mut output = format!("Result: {}", shown);
+    if wrapped {
+        output.push_str(&format!(" ({:#x}, wrapped)", result));
+    }
+    if signed_overflow {
+        output.push_str("\nSigned overflow: the result doesn't fit in i64 (OF would be set)");
+    }
+    if unsigned_overflow {
+        output.push_str("\nUnsigned overflow: the result doesn't fit in u64 (CF would be set)");
+    }
+    Ok(Calculated::integer(output, result as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(inputs: &[&str]) -> Vec<Result<String, String>> {
+        let (cpu, mut variables) = (CPU::new(), HashMap::new());
+        inputs.iter().map(|input| calculate(input, &cpu, &mut variables)).collect()
+    }
+
+    fn calc_one(input: &str) -> Result<String, String> {
+        calc(&[input]).remove(0)
+    }
+
+    #[test]
+    fn reports_signed_and_unsigned_overflow() {
+        assert_eq!(calc_one("+ 9223372036854775807 1"), Ok("Result: -9223372036854775808 (0x8000000000000000, wrapped)\n\
+            Signed overflow: the result doesn't fit in i64 (OF would be set)".to_string()));
+        assert_eq!(calc_one("+ 18446744073709551615 1"), Ok("Result: 0 (0x0, wrapped)\n\
+            Unsigned overflow: the result doesn't fit in u64 (CF would be set)".to_string()));
+        // A borrow, but -1 fits in i64
+        assert_eq!(calc_one("- 0 1"), Ok("Result: -1\nUnsigned overflow: the result doesn't fit in u64 (CF would be set)".to_string()));
+        assert_eq!(calc_one("* 3 4"), Ok("Result: 12".to_string()));
+    }
+
+    #[test]
+    fn prefixed_arguments() {
+        assert_eq!(calc_one("and 0xF0 0x3C"), Ok("Result: 0x30 (48)".to_string()));
+        assert_eq!(calc_one("dec 0b101"), Ok("Hex: 0x5\nDecimal: 5\nBinary: 0b101".to_string()));
+        assert!(calc_one("and 0b 1").is_err());
+    }
+
+    #[test]
+    fn shift_counts_are_masked() {
+        assert_eq!(calc_one("shl 1 64"), Ok("Result: 0x1 (1)".to_string()));
+        assert_eq!(calc_one("shr 0x10 68"), Ok("Result: 0x1 (1)".to_string()));
+    }
+
+    #[test]
+    fn expressions_use_c_precedence() {
+        assert_eq!(calc_one("(3 + 4) * 2"), Ok("Result: 14 (0xe)".to_string()));
+        assert_eq!(calc_one("1 + 2 * 3 << 1"), Ok("Result: 14 (0xe)".to_string()));
+        assert_eq!(calc_one("1 / 0"), Err("Division by zero".to_string()));
+    }
+
+    #[test]
+    fn variables_and_ans() {
+        let results = calc(&["x = 3 + 4", "* x 2", "ans + 1", "X * 2"]);
+        assert_eq!(results, vec![
+            Ok("x = 7 (0x7)".to_string()),
+            Ok("Result: 14".to_string()),
+            Ok("Result: 15 (0xf)".to_string()),
+            Ok("Result: 14 (0xe)".to_string()),
+        ]);
+        assert_eq!(calc_one("rax = 5"), Err("Can't assign to 'rax': it's reserved".to_string()));
+        assert_eq!(calc_one("ans = 5"), Err("Can't assign to 'ans': it's reserved".to_string()));
+        assert!(calc_one("1x = 5").is_err());
+        assert_eq!(calc_one("foo + 1"), Err("Unknown variable or register 'foo'".to_string()));
+    }
+
+    #[test]
+    fn ieee754_round_trips() {
+        assert_eq!(calc_one("float_to_ieee 1.5"), Ok("IEEE 754: 0x3fc00000".to_string()));
+        assert_eq!(calc_one("double_to_ieee 1.5"), Ok("IEEE 754: 0x3ff8000000000000".to_string()));
+        assert_eq!(calc_one("ieee_to_float 3fc00000"), Ok("Float: 1.5".to_string()));
+        assert_eq!(calc_one("ieee_to_double 0x3ff8000000000000"), Ok("Double: 1.5".to_string()));
+    }
+
+    #[test]
+    fn reads_registers() {
+        let mut cpu = CPU::new();
+        cpu.rax = 0x1234;
+        let mut variables = HashMap::new();
+        assert_eq!(calculate("reg al", &cpu, &mut variables), Ok("AL value: 0x34 (52)".to_string()));
+        assert_eq!(calculate("rax + 1", &cpu, &mut variables), Ok("Result: 4661 (0x1235)".to_string()));
+    }
+
+    #[test]
+    fn converts_between_any_bases() {
+        assert_eq!(calc_one("base 2 16 1010"), Ok("Base 16: a\nDecimal: 10".to_string()));
+        assert_eq!(calc_one("base 36 10 zz"), Ok("Base 10: 1295\nDecimal: 1295".to_string()));
+        assert_eq!(calc_one("base 37 10 1"), Err("Invalid base '37': use a base from 2 to 36".to_string()));
+    }
+}
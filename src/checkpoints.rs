@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+use crate::cpu::CPU;
+
+/// Named snapshots of the CPU to return to later. Snapshots share memory copy-on-write, so saving
+/// one is cheap until the CPU writes to memory.
+pub struct Checkpoints {
+    saved: BTreeMap<String, CPU>,
+}
+
+impl Checkpoints {
+    pub fn new() -> Self {
+        Checkpoints { saved: BTreeMap::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.saved.is_empty()
+    }
+
+    /// Saves `cpu` as `name`, replacing any checkpoint by that name. Returns whether one was replaced.
+    pub fn save(&mut self, name: &str, cpu: &CPU) -> bool {
+        self.saved.insert(name.to_string(), cpu.clone()).is_some()
+    }
+
+    /// A copy of the CPU saved as `name`; the checkpoint stays, so it can be restored again.
+    pub fn restore(&self, name: &str) -> Result<CPU, String> {
+        self.saved.get(name).cloned().ok_or_else(|| format!("No checkpoint named '{}'", name))
+    }
+
+    /// Every checkpoint by name, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &CPU)> {
+        self.saved.iter().map(|(name, cpu)| (name.as_str(), cpu))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_each_checkpoint() {
+        let (mut checkpoints, mut cpu) = (Checkpoints::new(), CPU::new());
+        cpu.rax = 1;
+        assert!(!checkpoints.save("first", &cpu));
+        cpu.rax = 2;
+        cpu.zf = true;
+        assert!(!checkpoints.save("second", &cpu));
+
+        let first = checkpoints.restore("first").unwrap();
+        assert_eq!((first.rax, first.zf), (1, false));
+        let second = checkpoints.restore("second").unwrap();
+        assert_eq!((second.rax, second.zf), (2, true));
+        // Restoring doesn't use the checkpoint up
+        assert_eq!(checkpoints.restore("first").unwrap().rax, 1);
+    }
+
+    #[test]
+    fn saving_again_replaces_a_checkpoint() {
+        let (mut checkpoints, mut cpu) = (Checkpoints::new(), CPU::new());
+        checkpoints.save("here", &cpu);
+        cpu.rbx = 7;
+        assert!(checkpoints.save("here", &cpu));
+        assert_eq!(checkpoints.restore("here").unwrap().rbx, 7);
+        assert_eq!(checkpoints.iter().map(|(name, _)| name).collect::<Vec<_>>(), ["here"]);
+        assert_eq!(checkpoints.restore("there").unwrap_err(), "No checkpoint named 'there'");
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cpu::FLAG_NAMES;
+use crate::isa::{INSTRUCTIONS, LEVELS};
+use crate::parser::{Register, RegisterSize};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+pub type ReplEditor = Editor<ReplHelper, DefaultHistory>;
+
+// Everything `print_help` lists, by the word that starts it
+const COMMANDS: &[&str] = &[
+    "exit", "quit", "help", "cpu", "state", "signedness", "frame", "xmm", "explain", "find-instr", "disasm",
+    "checkpoint", "restore", "checkpoints", "undo", "set", "save-state", "load-state", "clone", "switch", "sessions",
+    "grade", "load", "load-mem", ":source", ":isa", ":single", ":multi", ":multi-live", ":calc", ":script",
+    "run", "list", "del", "ins", "assemble-out", "export-elf", "break", "unbreak", "continue", "watch", "unwatch", "step", "reset-pc", "stepinto", "stepover", "stepout", "backtrace",
+    ":explain", ":autostate", "colors", "endian", "analyze", "optimize",
+];
+
+/// Tab completion for the REPL prompt.
+pub struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&line[..pos]))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Completes the word the cursor is in, returning where the word starts and the candidates for it.
+/// The first word can be a command, a mnemonic or a register to inspect; after a mnemonic only
+/// registers make sense.
+pub fn complete(line: &str) -> (usize, Vec<String>) {
+    // Operands are split on commas and inside memory references like `[rbx+rcx*4]` as well
+    let start = line.rfind(|c: char| c.is_whitespace() || ",[+-*".contains(c)).map_or(0, |i| i + 1);
+    let prefix = line[start..].to_lowercase();
+    let first_word = line[..start].split_whitespace().next().map(str::to_lowercase);
+
+    let words: Vec<String> = match first_word.as_deref() {
+        None => COMMANDS.iter().map(|c| c.to_string()).chain(mnemonics()).chain(registers()).collect(),
+        Some("explain" | "find-instr") => mnemonics().collect(),
+        Some(":isa") => LEVELS.iter().map(|(level, _)| level.to_string()).chain(["full".to_string()]).collect(),
+        Some("watch" | "unwatch") => Register::ALL.iter()
+            .filter_map(|reg| reg.name(RegisterSize::Qword))
+            .chain(FLAG_NAMES)
+            .map(str::to_lowercase)
+            .collect(),
+        Some("endian") => vec!["le".to_string(), "be".to_string()],
+        Some("colors") => ["gradient", "mono", "colorblind", "blue"].map(String::from).to_vec(),
+        Some(":explain" | ":autostate") => vec!["on".to_string(), "off".to_string()],
+        Some(word) if mnemonics().any(|m| m == word) => registers().collect(),
+        Some(_) => Vec::new(),
+    };
+
+    let mut candidates: Vec<String> = words.into_iter().filter(|w| w.starts_with(&prefix)).collect();
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+fn mnemonics() -> impl Iterator<Item = String> {
+    INSTRUCTIONS.iter().map(|info| info.instruction_type.to_string())
+}
+
+fn registers() -> impl Iterator<Item = String> {
+    RegisterSize::ALL.iter()
+        .flat_map(|&size| Register::ALL.iter().filter_map(move |reg| reg.name(size)))
+        .map(str::to_string)
+        .chain((0..16).map(|i| format!("xmm{}", i)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_commands_and_mnemonics() {
+        assert_eq!(complete("mo"), (0, vec!["mov".to_string(), "movsx".to_string(), "movzx".to_string()]));
+        // The `xmm` command, then the sixteen registers
+        let (_, candidates) = complete("xm");
+        assert_eq!((candidates.len(), candidates[0].as_str(), candidates[1].as_str()), (17, "xmm", "xmm0"));
+    }
+
+    #[test]
+    fn completes_registers_after_a_mnemonic() {
+        assert_eq!(complete("mov rax, r15"), (9, ["r15", "r15b", "r15d", "r15w"].map(String::from).to_vec()));
+        assert_eq!(complete("mov [rbx+rc"), (9, vec!["rcx".to_string()]));
+        assert_eq!(complete("MOV A").1, ["ah", "al", "ax"].map(String::from).to_vec());
+        assert!(complete("run r").1.is_empty());
+    }
+
+    #[test]
+    fn completes_command_arguments() {
+        assert_eq!(complete("watch z").1, ["zf"]);
+        assert_eq!(complete("endian ").1, ["be", "le"]);
+    }
+}
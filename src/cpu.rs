@@ -1,8 +1,90 @@
-use crate::parser::{Instruction, InstructionType, Operand, Register, RegisterDisplayOptions};
-use crate::parser::{MemoryDumpOptions, MemoryDumpFormat};
+use crate::parser::{Instruction, InstructionType, Operand, Register, RegisterDisplayOptions, RegisterSize};
+use crate::parser::{printable_char, MemoryDumpOptions, MemoryDumpFormat};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::ops::{Index, IndexMut};
+use std::rc::Rc;
 
-#[derive(Debug)]
+// Linux x86-64 syscall numbers and error codes for the syscalls that are emulated
+const SYS_WRITE: u64 = 1;
+const SYS_EXIT: u64 = 60;
+const EBADF: i64 = 9;
+const EFAULT: i64 = 14;
+
+pub const MEMORY_SIZE: usize = 1024 * 1024;
+// Where `rsp` starts: the stack is empty when it's here, and grows down from it
+const STACK_TOP: u64 = MEMORY_SIZE as u64 - 8;
+const STACK_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CpuError {
+    UnsupportedOperands(String), // The instruction as written
+    MemoryOutOfBounds { address: u64, size: usize },
+    DivideError(&'static str), // #DE: division by zero, or a quotient too big for the destination
+    StackUnderflow { rsp: u64 }, // A pop or ret with nothing on the stack
+    StackOverflow { rsp: u64 }, // A push or call below the stack limit
+    UnsupportedSyscall(u64), // The syscall number in rax, when it isn't one that's emulated
+}
+
+// The assembler rejects most bad operand combinations before they get here, but not all of them
+fn unsupported(instruction: &Instruction) -> CpuError {
+    CpuError::UnsupportedOperands(instruction.to_string())
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnsupportedOperands(instruction) => write!(f, "Unsupported operands in `{}`", instruction),
+            CpuError::MemoryOutOfBounds { address, size } =>
+                write!(f, "Memory access out of bounds: {} byte(s) at {:#x}", size, address),
+            CpuError::DivideError(reason) => write!(f, "#DE divide error: {}", reason),
+            CpuError::StackUnderflow { rsp } =>
+                write!(f, "Stack underflow: nothing to pop with rsp at {:#x}, the top of the stack", rsp),
+            CpuError::StackOverflow { rsp } =>
+                write!(f, "Stack overflow: pushing with rsp at {:#x} would go past the stack limit", rsp),
+            CpuError::UnsupportedSyscall(number) =>
+                write!(f, "Unsupported syscall {}; only write ({}) and exit ({}) are emulated", number, SYS_WRITE, SYS_EXIT),
+        }
+    }
+}
+
+/// The flags `flag` knows, in the order they're displayed.
+pub const FLAG_NAMES: [&str; 7] = ["CF", "PF", "AF", "ZF", "SF", "DF", "OF"];
+
+/// A general-purpose register or flag whose value an instruction changed. Flags are 0 or 1.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Change {
+    pub name: &'static str, // `rax`, ..., or `CF`, ...
+    pub before: u64,
+    pub after: u64,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if FLAG_NAMES.contains(&self.name) {
+            write!(f, "{} {} → {}", self.name, self.before, self.after)
+        } else {
+            write!(f, "{} {:#x} → {:#x}", self.name, self.before, self.after)
+        }
+    }
+}
+
+/// The registers and flags that differ between two CPU states.
+pub fn diff_registers(before: &CPU, after: &CPU) -> Vec<Change> {
+    diff_values(&before.register_values(), &after.register_values())
+}
+
+/// Like `diff_registers`, for values taken with `register_values`.
+pub fn diff_values(before: &[(&'static str, u64)], after: &[(&'static str, u64)]) -> Vec<Change> {
+    before.iter()
+        .zip(after)
+        .filter(|(before, after)| before.1 != after.1)
+        .map(|(&(name, before), &(_, after))| Change { name, before, after })
+        .collect()
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CPU {
     // General Purpose Registers
     pub rax: u64, pub rbx: u64, pub rcx: u64, pub rdx: u64,
@@ -19,16 +101,31 @@ pub struct CPU {
 
     // Individual Flags
     pub cf: bool, // Carry Flag
+    pub pf: bool, // Parity Flag
+    pub af: bool, // Auxiliary Carry Flag
     pub zf: bool, // Zero Flag
     pub sf: bool, // Sign Flag
+    pub df: bool, // Direction Flag
     pub of: bool, // Overflow Flag
 
     // Memory (simple implementation)
-    pub memory: Vec<u8>,
+    // Shared copy-on-write, so cloning a CPU for a snapshot doesn't copy the whole 1MB
+    // until one of the copies writes to memory.
+    #[serde(serialize_with = "crate::state::serialize_memory", deserialize_with = "crate::state::deserialize_memory")]
+    pub memory: Rc<Vec<u8>>,
 
     // XMM Registers (for SSE/AVX)
     // 128-Bit XMM Registers (holds 4 doublewords):
     pub xmm: [u128; 16], 
+
+    // Left by syscalls for the front end to pick up: text the program wrote, and its status once it exits
+    #[serde(skip)]
+    pub output: String,
+    #[serde(skip)]
+    pub exit_status: Option<u64>,
+
+    // The lowest address a push may write to; the stack grows down from STACK_TOP towards it
+    pub stack_limit: u64,
 }
 
 // #[derive(Debug, Clone, Copy)]
@@ -41,15 +138,18 @@ impl CPU {
         CPU {
             rax: 0, rbx: 0, rcx: 0, rdx: 0,
             rsi: 0, rdi: 0, rbp: 0, 
-            rsp: 1024 * 1024 - 8,
+            rsp: STACK_TOP,
             r8: 0, r9: 0, r10: 0, r11: 0,
             r12: 0, r13: 0, r14: 0, r15: 0,
             rip: 0,
             rflags: 0x0002, // Default value with bit 1 set (reserved bit)
             cs: 0, fs: 0, gs: 0,
             xmm: [0; 16],
-            cf: false, zf: false, sf: false, of: false,
-            memory: vec![0; 1024 * 1024], // 1MB of memory
+            cf: false, pf: false, af: false, zf: false, sf: false, df: false, of: false,
+            memory: Rc::new(vec![0; MEMORY_SIZE]), // 1MB of memory
+            output: String::new(),
+            exit_status: None,
+            stack_limit: STACK_TOP - STACK_SIZE,
         }
     }
 
@@ -67,367 +167,896 @@ impl CPU {
         }
     }
 
-    pub fn dump_memory(&self, options: &MemoryDumpOptions) {
+    /// Formats `options.size` bytes from `options.address` as a dump, one line per row of
+    /// `bytes_per_row` bytes, ending in a newline.
+    pub fn dump_memory(&self, options: &MemoryDumpOptions) -> String {
         let address = options.address;
         let size = options.size;
+        let format = &options.format;
+        let per_row = format.bytes_per_row();
+        let width = format.cell_width();
 
-        println!("Memory Dump at 0x{:x}:", address);
-
-        for i in 0..(size / 16) { // Iterate over rows (16 bytes per row)
-            print!("0x{:08x}:  ", address + (i * 16) as u64);
-            for j in 0..16 { // Iterate over columns
-                let index = (address as usize) + (i * 16) + j;
-                if let Some(byte) = self.memory.get(index) {
-                    match options.format {
-                        MemoryDumpFormat::Hex => print!("{:02x} ", byte),
-                        MemoryDumpFormat::Decimal => print!("{:3} ", byte),
-                    }
+        let mut dump = format!("Memory Dump at 0x{:x}:\n", address);
+
+        for i in 0..size.div_ceil(per_row) { // Iterate over rows
+            // Stop at the end of the address space rather than wrapping around to 0
+            let Some(row_address) = address.checked_add((i * per_row) as u64) else {
+                break;
+            };
+            dump.push_str(&format!("0x{:08x}:  ", row_address));
+            let mut text = String::new(); // The row as ASCII, whatever the number format
+            for j in 0..per_row { // Iterate over columns
+                // Pad out a short last row so its ASCII column lines up with the others
+                if i * per_row + j >= size {
+                    dump.push_str(&" ".repeat(width + 1));
+                    continue;
+                }
+                let byte = row_address.checked_add(j as u64).and_then(|index| self.memory.get(usize::try_from(index).ok()?));
+                if let Some(&byte) = byte {
+                    dump.push_str(&format!("{} ", format.format_byte(byte)));
+                    text.push(printable_char(byte));
                 } else {
-                    print!("?? "); // Out of bounds
+                    dump.push_str(&format!("{} ", "?".repeat(width))); // Out of bounds
+                    text.push(' ');
                 }
             }
-            println!(); // Newline after each row
+            // A char dump is already its own ASCII column
+            if *format == MemoryDumpFormat::Char {
+                dump.push('\n');
+            } else {
+                dump.push_str(&format!(" |{}|\n", text));
+            }
         }
+        dump
     }
 
-    pub fn execute(&mut self, instruction: &Instruction) {
+    /// Executes one instruction and advances `rip`. On a fault the error is returned and `rip`
+    /// stays on the faulting instruction, like the hardware's exceptions.
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
         match instruction.instruction_type {
-            InstructionType::Mov => self.execute_mov(instruction),
-            InstructionType::Add => self.execute_add(instruction),
-            InstructionType::Sub => self.execute_sub(instruction),
-            InstructionType::And => self.execute_and(instruction),
-            InstructionType::Or => self.execute_or(instruction),
-            InstructionType::Xor => self.execute_xor(instruction),
-            InstructionType::Inc => self.execute_inc(instruction),
-            InstructionType::Dec => self.execute_dec(instruction),
-            InstructionType::Neg => self.execute_neg(instruction),
-            InstructionType::Not => self.execute_not(instruction),
-            InstructionType::Shl => self.execute_shl(instruction),
-            InstructionType::Shr => self.execute_shr(instruction),
-            InstructionType::Rol => self.execute_rol(instruction),
-            InstructionType::Ror => self.execute_ror(instruction),
-            InstructionType::Push => self.execute_push(instruction),
-            InstructionType::Pop => self.execute_pop(instruction),
-            InstructionType::Cmp => self.execute_cmp(instruction),
-            InstructionType::Test => self.execute_test(instruction),
-            InstructionType::Jmp => { self.execute_jmp(instruction); }
-            InstructionType::Je => { self.execute_je(instruction); }
-            InstructionType::Jne => { self.execute_jne(instruction); }
-            InstructionType::Jg => { self.execute_jg(instruction); }
-            InstructionType::Jge => { self.execute_jge(instruction); }
-            InstructionType::Jl => { self.execute_jl(instruction); }
-            InstructionType::Jle => { self.execute_jle(instruction); }
-            InstructionType::Call => self.execute_call(instruction),
-            InstructionType::Ret => self.execute_ret(instruction),
+            InstructionType::Mov => self.execute_mov(instruction)?,
+            InstructionType::Add => self.execute_add(instruction)?,
+            InstructionType::Sub => self.execute_sub(instruction)?,
+            InstructionType::Adc => self.execute_adc(instruction)?,
+            InstructionType::Sbb => self.execute_sbb(instruction)?,
+            InstructionType::And => self.execute_and(instruction)?,
+            InstructionType::Or => self.execute_or(instruction)?,
+            InstructionType::Xor => self.execute_xor(instruction)?,
+            InstructionType::Mul => self.execute_mul(instruction)?,
+            InstructionType::Imul => self.execute_imul(instruction)?,
+            InstructionType::Div | InstructionType::Idiv => self.execute_div(instruction)?,
+            InstructionType::Cdq => self.execute_sign_extend_rax(RegisterSize::Dword)?,
+            InstructionType::Cqo => self.execute_sign_extend_rax(RegisterSize::Qword)?,
+            InstructionType::Inc => self.execute_inc(instruction)?,
+            InstructionType::Dec => self.execute_dec(instruction)?,
+            InstructionType::Neg => self.execute_neg(instruction)?,
+            InstructionType::Not => self.execute_not(instruction)?,
+            InstructionType::Shl => self.execute_shl(instruction)?,
+            InstructionType::Shr => self.execute_shr(instruction)?,
+            InstructionType::Rol => self.execute_rol(instruction)?,
+            InstructionType::Ror => self.execute_ror(instruction)?,
+            InstructionType::Push => self.execute_push(instruction)?,
+            InstructionType::Pop => self.execute_pop(instruction)?,
+            InstructionType::Cmp => self.execute_cmp(instruction)?,
+            InstructionType::Test => self.execute_test(instruction)?,
+            InstructionType::Jmp => self.execute_jmp(instruction)?,
+            InstructionType::Je | InstructionType::Jne | InstructionType::Jg |
+            InstructionType::Jge | InstructionType::Jl | InstructionType::Jle => self.execute_conditional_jump(instruction)?,
+            InstructionType::Loop => self.execute_loop(instruction)?,
+            InstructionType::Call => self.execute_call(instruction)?,
+            InstructionType::Ret => self.execute_ret(instruction)?,
+            InstructionType::Syscall => self.execute_syscall()?,
+            InstructionType::Lea => self.execute_lea(instruction)?,
             //Advanced:
-            InstructionType::Paddd => self.execute_paddd(instruction),
+            InstructionType::Paddd => self.execute_paddd(instruction)?,
             // Bit-Scan Forward:
-            InstructionType::Bsf => self.execute_bsf(instruction), 
-            InstructionType::Cmovne => self.execute_cmovne(instruction),
+            InstructionType::Bsf => self.execute_bsf(instruction)?,
+            InstructionType::Bsr => self.execute_bsr(instruction)?,
+            InstructionType::Popcnt | InstructionType::Lzcnt | InstructionType::Tzcnt => self.execute_bit_count(instruction)?,
+            InstructionType::Bswap => self.execute_bswap(instruction)?,
+            InstructionType::Bt | InstructionType::Bts | InstructionType::Btr | InstructionType::Btc => self.execute_bit_test(instruction)?,
+            InstructionType::Cmovne => self.execute_cmovne(instruction)?,
+            InstructionType::Sete | InstructionType::Setne | InstructionType::Setg | InstructionType::Setge |
+            InstructionType::Setl | InstructionType::Setle | InstructionType::Seta | InstructionType::Setae |
+            InstructionType::Setb | InstructionType::Setbe => self.execute_setcc(instruction)?,
+            InstructionType::Movzx => self.execute_extending_move(instruction, false)?,
+            InstructionType::Movsx => self.execute_extending_move(instruction, true)?,
+            InstructionType::Xchg => self.execute_xchg(instruction)?,
+            InstructionType::Nop => {} // Only rip moves, like for every instruction below
+            InstructionType::Cld => self.execute_set_direction(false)?,
+            InstructionType::Std => self.execute_set_direction(true)?,
             //_ => println!("Unsupported instruction: {:?}", instruction.instruction_type),
         }
-        self.rip += 1; // Increment instruction pointer
+        self.rip = self.rip.wrapping_add(1); // Increment instruction pointer
+        Ok(())
     }
 
-    fn execute_mov(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(dest), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] = *imm as u64;
-        } else if let (Operand::Register(dest), Operand::Register(src)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] = self[src];
-        }
+    fn execute_mov(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, _, value) = self.binary_operands(instruction)?;
+        self.set_register(&dest, size, value);
+        Ok(())
     }
 
-    fn execute_add(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(dest), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let (result, overflow) = self[dest].overflowing_add(*imm as u64);
-            self[dest] = result;
-            self.update_flags(result, overflow);
-        } else if let (Operand::Register(dest), Operand::Register(src)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let (result, overflow) = self[dest].overflowing_add(self[src]);
-            self[dest] = result;
-            self.update_flags(result, overflow);
-        }
+    fn execute_add(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, a, b) = self.binary_operands(instruction)?;
+        let (result, carry, overflow) = add_with_carry(a, b, false, size);
+        self.af = auxiliary_carry(a, b, result);
+        self.set_register(&dest, size, result);
+        self.update_flags(result, carry, overflow, size);
+        Ok(())
     }
 
-    fn execute_sub(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(dest), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let (result, overflow) = self[dest].overflowing_sub(*imm as u64);
-            self[dest] = result;
-            self.update_flags(result, overflow);
-        } else if let (Operand::Register(dest), Operand::Register(src)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let (result, overflow) = self[dest].overflowing_sub(self[src]);
-            self[dest] = result;
-            self.update_flags(result, overflow);
-        }
+    fn execute_sub(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, a, b) = self.binary_operands(instruction)?;
+        let (result, borrow, overflow) = sub_with_borrow(a, b, false, size);
+        self.af = auxiliary_carry(a, b, result);
+        self.set_register(&dest, size, result);
+        self.update_flags(result, borrow, overflow, size);
+        Ok(())
     }
 
-    fn execute_and(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(dest), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] &= *imm as u64;
-            self.update_flags(self[dest], false);
-        } else if let (Operand::Register(dest), Operand::Register(src)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] &= self[src];
-            self.update_flags(self[dest], false);
-        }
+    // adc/sbb take CF as the carry (or borrow) in, so a chain of them works through a multi-word number
+    fn execute_adc(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, a, b) = self.binary_operands(instruction)?;
+        let (result, carry, overflow) = add_with_carry(a, b, self.cf, size);
+        self.af = auxiliary_carry(a, b, result);
+        self.set_register(&dest, size, result);
+        self.update_flags(result, carry, overflow, size);
+        Ok(())
     }
 
-    fn execute_or(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(dest), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] |= *imm as u64;
-            self.update_flags(self[dest], false);
-        } else if let (Operand::Register(dest), Operand::Register(src)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] |= self[src];
-            self.update_flags(self[dest], false);
-        }
+    fn execute_sbb(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, a, b) = self.binary_operands(instruction)?;
+        let (result, borrow, overflow) = sub_with_borrow(a, b, self.cf, size);
+        self.af = auxiliary_carry(a, b, result);
+        self.set_register(&dest, size, result);
+        self.update_flags(result, borrow, overflow, size);
+        Ok(())
     }
 
-    fn execute_xor(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(dest), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] ^= *imm as u64;
-            self.update_flags(self[dest], false);
-        } else if let (Operand::Register(dest), Operand::Register(src)) = (&instruction.operands[0], &instruction.operands[1]) {
-            self[dest] ^= self[src];
-            self.update_flags(self[dest], false);
-        }
+    fn execute_and(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, a, b) = self.binary_operands(instruction)?;
+        self.set_register(&dest, size, a & b);
+        self.update_flags(a & b, false, false, size);
+        Ok(())
     }
 
-    fn execute_inc(&mut self, instruction: &Instruction) {
-        if let Operand::Register(reg) = &instruction.operands[0] {
-            let (result, overflow) = self[reg].overflowing_add(1);
-            self[reg] = result;
-            self.update_flags(result, overflow);
-        }
+    fn execute_or(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, a, b) = self.binary_operands(instruction)?;
+        self.set_register(&dest, size, a | b);
+        self.update_flags(a | b, false, false, size);
+        Ok(())
     }
 
-    fn execute_dec(&mut self, instruction: &Instruction) {
-        if let Operand::Register(reg) = &instruction.operands[0] {
-            let (result, overflow) = self[reg].overflowing_sub(1);
-            self[reg] = result;
-            self.update_flags(result, overflow);
-        }
+    fn execute_xor(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, a, b) = self.binary_operands(instruction)?;
+        self.set_register(&dest, size, a ^ b);
+        self.update_flags(a ^ b, false, false, size);
+        Ok(())
     }
 
-    fn execute_neg(&mut self, instruction: &Instruction) {
-        if let Operand::Register(reg) = &instruction.operands[0] {
-            let (result, overflow) = (0u64).overflowing_sub(self[reg]);
-            self[reg] = result;
-            self.update_flags(result, overflow);
+    // rdx:rax = rax * operand, at the operand's width; an 8-bit multiply puts the whole product in ax
+    fn execute_mul(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let factor = self.register(reg, *size);
+        let size = if *size == RegisterSize::HighByte { RegisterSize::Byte } else { *size };
+        let product = self.register(&Register::Rax, size) as u128 * factor as u128;
+        let (low, high) = (product as u64 & size.mask(), (product >> size.bits()) as u64 & size.mask());
+
+        if size == RegisterSize::Byte {
+            self.set_register(&Register::Rax, RegisterSize::Word, product as u64);
+        } else {
+            self.set_register(&Register::Rax, size, low);
+            self.set_register(&Register::Rdx, size, high);
         }
+        // Only CF and OF are defined: they say whether the product needed the high half
+        self.set_carry_and_overflow(high != 0, high != 0);
+        Ok(())
     }
 
-    fn execute_not(&mut self, instruction: &Instruction) {
-        if let Operand::Register(reg) = &instruction.operands[0] {
-            self[reg] = !self[reg];
-            self.update_flags(self[reg], false);
+    // rdx:rax / operand at the operand's width; an 8-bit divide takes ax and leaves the remainder in ah
+    // Like the hardware, a faulting divide leaves the registers as they were
+    fn execute_div(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let divisor = self.register(reg, *size);
+        let size = if *size == RegisterSize::HighByte { RegisterSize::Byte } else { *size };
+        if divisor == 0 {
+            return Err(CpuError::DivideError("division by zero"));
         }
-    }
 
-    fn execute_shl(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let result = self[reg] << shift;
-            self[reg] = result;
-            self.update_flags(result, false);
+        let (low, high) = match size {
+            RegisterSize::Byte => (self.register(&Register::Rax, RegisterSize::Byte), self.register(&Register::Rax, RegisterSize::HighByte)),
+            _ => (self.register(&Register::Rax, size), self.register(&Register::Rdx, size)),
+        };
+        let dividend = (high as u128) << size.bits() | low as u128;
+
+        let (quotient, remainder) = if instruction.instruction_type == InstructionType::Idiv {
+            // Sign-extend the double-width dividend and the divisor, then check the quotient fits the signed range
+            let unused = 128 - 2 * size.bits();
+            let dividend = ((dividend << unused) as i128) >> unused;
+            let divisor = sign_extend(divisor, size) as i64 as i128;
+            let quotient = dividend / divisor;
+            let limit = 1i128 << (size.bits() - 1);
+            if quotient < -limit || quotient >= limit {
+                return Err(CpuError::DivideError("quotient too large for the destination"));
+            }
+            (quotient as u64, (dividend % divisor) as u64)
+        } else {
+            let quotient = dividend / divisor as u128;
+            if quotient > size.mask() as u128 {
+                return Err(CpuError::DivideError("quotient too large for the destination"));
+            }
+            (quotient as u64, (dividend % divisor as u128) as u64)
+        };
+
+        if size == RegisterSize::Byte {
+            self.set_register(&Register::Rax, RegisterSize::Byte, quotient);
+            self.set_register(&Register::Rax, RegisterSize::HighByte, remainder);
+        } else {
+            self.set_register(&Register::Rax, size, quotient);
+            self.set_register(&Register::Rdx, size, remainder);
         }
+        Ok(())
+    }
+
+    fn execute_imul(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        // `imul dest, src` multiplies into dest; `imul dest, src, imm` multiplies src by the immediate
+        let (dest, size, a, b) = match instruction.operands.as_slice() {
+            [Operand::Register(dest, size), Operand::Register(src, src_size), Operand::Immediate(imm)] =>
+                (dest.clone(), *size, self.register(src, *src_size), *imm as u64 & size.mask()),
+            _ => self.binary_operands(instruction)?,
+        };
+
+        let product = sign_extend(a, size) as i64 as i128 * sign_extend(b, size) as i64 as i128;
+        let result = product as u64 & size.mask();
+        // CF and OF both mean the signed product was truncated
+        let overflow = sign_extend(result, size) as i64 as i128 != product;
+        self.set_register(&dest, size, result);
+        self.update_flags(result, overflow, overflow, size);
+        Ok(())
+    }
+
+    // cdq/cqo: rdx becomes all ones if rax is negative at this width, otherwise zero
+    fn execute_sign_extend_rax(&mut self, size: RegisterSize) -> Result<(), CpuError> {
+        let negative = self.register(&Register::Rax, size) >> (size.bits() - 1) == 1;
+        self.set_register(&Register::Rdx, size, if negative { size.mask() } else { 0 });
+        Ok(())
+    }
+
+    fn execute_inc(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let value = self.register(reg, *size);
+        let (result, _, overflow) = add_with_carry(value, 1, false, *size);
+        self.af = auxiliary_carry(value, 1, result);
+        self.set_register(reg, *size, result);
+        self.update_flags(result, self.cf, overflow, *size);
+        Ok(())
+    }
+
+    fn execute_dec(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let value = self.register(reg, *size);
+        let (result, _, overflow) = sub_with_borrow(value, 1, false, *size);
+        self.af = auxiliary_carry(value, 1, result);
+        self.set_register(reg, *size, result);
+        self.update_flags(result, self.cf, overflow, *size);
+        Ok(())
+    }
+
+    fn execute_neg(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        // CF is set unless the operand was zero; OF only for the most negative value, which has no positive twin
+        let value = self.register(reg, *size);
+        let (result, borrow, overflow) = sub_with_borrow(0, value, false, *size);
+        self.af = auxiliary_carry(0, value, result);
+        self.set_register(reg, *size, result);
+        self.update_flags(result, borrow, overflow, *size);
+        Ok(())
+    }
+
+    // Unlike the other logic instructions, `not` doesn't touch the flags
+    fn execute_not(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let result = !self.register(reg, *size) & size.mask();
+        self.set_register(reg, *size, result);
+        Ok(())
     }
 
-    fn execute_shr(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let result = self[reg] >> shift;
-            self[reg] = result;
-            self.update_flags(result, false);
+    // CF gets the last bit shifted out. OF is only defined for 1-bit shifts, so other counts leave it as it
+    // was, and a count of 0 changes nothing at all
+    fn execute_shl(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size), Operand::Immediate(shift)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let (value, count, bits) = (self.register(reg, *size), shift_count(*shift, *size), size.bits());
+        if count == 0 {
+            return Ok(());
         }
+        let result = value.wrapping_shl(count) & size.mask();
+        let carry = count <= bits && (value >> (bits - count)) & 1 == 1;
+        // A 1-bit shl overflows when the sign changes, i.e. the top two bits differed
+        let overflow = if count == 1 { sign_bit(result, *size) != carry } else { self.of };
+        self.set_register(reg, *size, result);
+        self.update_flags(result, carry, overflow, *size);
+        Ok(())
     }
 
-    fn execute_rol(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let result = self[reg].rotate_left(*shift as u32);
-            self[reg] = result;
-            self.update_flags(result, false);
+    fn execute_shr(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size), Operand::Immediate(shift)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let (value, count, bits) = (self.register(reg, *size), shift_count(*shift, *size), size.bits());
+        if count == 0 {
+            return Ok(());
         }
+        let result = value.wrapping_shr(count);
+        let carry = count <= bits && (value >> (count - 1)) & 1 == 1;
+        // A 1-bit shr of a negative number makes it positive, so OF is the original sign bit
+        let overflow = if count == 1 { sign_bit(value, *size) } else { self.of };
+        self.set_register(reg, *size, result);
+        self.update_flags(result, carry, overflow, *size);
+        Ok(())
     }
 
-    fn execute_ror(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(reg), Operand::Immediate(shift)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let result = self[reg].rotate_right(*shift as u32);
-            self[reg] = result;
-            self.update_flags(result, false);
+    // Rotates only touch CF and OF: CF gets the bit that wrapped around, and OF (for 1-bit rotates) whether the sign changed
+    fn execute_rol(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size), Operand::Immediate(shift)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let count = shift_count(*shift, *size);
+        if count == 0 {
+            return Ok(());
         }
+        let result = rotate_left(self.register(reg, *size), count, *size);
+        let carry = result & 1 == 1;
+        let overflow = if count == 1 { sign_bit(result, *size) != carry } else { self.of };
+        self.set_register(reg, *size, result);
+        self.set_carry_and_overflow(carry, overflow);
+        Ok(())
     }
 
-    fn execute_push(&mut self, instruction: &Instruction) {
-        if let Operand::Register(reg) = &instruction.operands[0] {
-            self.rsp -= 8;
-            let value = self[reg];
-            self.write_memory(self.rsp, value);
+    fn execute_ror(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size), Operand::Immediate(shift)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let (count, bits) = (shift_count(*shift, *size), size.bits());
+        if count == 0 {
+            return Ok(());
         }
+        let result = rotate_left(self.register(reg, *size), bits - (count % bits), *size);
+        let carry = sign_bit(result, *size);
+        let overflow = if count == 1 { carry != ((result >> (bits - 2)) & 1 == 1) } else { self.of };
+        self.set_register(reg, *size, result);
+        self.set_carry_and_overflow(carry, overflow);
+        Ok(())
+    }
+
+    fn execute_push(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, _)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        // rsp only moves once the write has succeeded, so a fault leaves the stack as it was
+        let top = self.stack_push_slot()?;
+        self.write_memory(top, self[reg])?;
+        self.rsp = top;
+        Ok(())
+    }
+
+    fn execute_pop(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, _)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        self.check_stack_not_empty()?;
+        let value = self.read_memory(self.rsp)?;
+        self.rsp = self.rsp.wrapping_add(8);
+        self[reg] = value; // After the increment, so `pop rsp` loads the popped value
+        Ok(())
+    }
+
+    fn execute_cmp(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (_, size, a, b) = self.binary_operands(instruction)?;
+        let (result, borrow, overflow) = sub_with_borrow(a, b, false, size);
+        self.af = auxiliary_carry(a, b, result);
+        self.update_flags(result, borrow, overflow, size);
+        Ok(())
     }
 
-    fn execute_pop(&mut self, instruction: &Instruction) {
-        if let Operand::Register(reg) = &instruction.operands[0] {
-            let value = self.read_memory(self.rsp);
-            self[reg] = value;
-            self.rsp += 8;
+    fn execute_test(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (_, size, a, b) = self.binary_operands(instruction)?;
+        self.update_flags(a & b, false, false, size);
+        Ok(())
+    }
+
+    fn execute_jmp(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Immediate(target)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        self.rip = (*target as u64).wrapping_sub(1); // -1 because rip is incremented after execution
+        Ok(())
+    }
+    
+    fn execute_conditional_jump(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        if self.condition_met(&instruction.instruction_type) == Some(true) {
+            self.execute_jmp(instruction)?;
         }
+        Ok(())
     }
 
-    fn execute_cmp(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(reg), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let (result, overflow) = self[reg].overflowing_sub(*imm as u64);
-            self.update_flags(result, overflow);
-        } else if let (Operand::Register(reg1), Operand::Register(reg2)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let (result, overflow) = self[reg1].overflowing_sub(self[reg2]);
-            self.update_flags(result, overflow);
+    // The count is the whole of rcx, and the decrement leaves the flags alone
+    fn execute_loop(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        self.rcx = self.rcx.wrapping_sub(1);
+        if self.rcx != 0 {
+            self.execute_jmp(instruction)?;
         }
+        Ok(())
     }
 
-    fn execute_test(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(reg), Operand::Immediate(imm)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let result = self[reg] & (*imm as u64);
-            self.update_flags(result, false);
-        } else if let (Operand::Register(reg1), Operand::Register(reg2)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let result = self[reg1] & self[reg2];
-            self.update_flags(result, false);
+    /// Whether a conditional instruction's condition holds for the current flags.
+    pub fn condition_met(&self, instruction_type: &InstructionType) -> Option<bool> {
+        match instruction_type {
+            InstructionType::Je | InstructionType::Sete => Some(self.zf),
+            InstructionType::Jne | InstructionType::Cmovne | InstructionType::Setne => Some(!self.zf),
+            InstructionType::Jg | InstructionType::Setg => Some(!self.zf && self.sf == self.of),
+            InstructionType::Jge | InstructionType::Setge => Some(self.sf == self.of),
+            InstructionType::Jl | InstructionType::Setl => Some(self.sf != self.of),
+            InstructionType::Jle | InstructionType::Setle => Some(self.zf || self.sf != self.of),
+            InstructionType::Seta => Some(!self.cf && !self.zf),
+            InstructionType::Setae => Some(!self.cf),
+            InstructionType::Setb => Some(self.cf),
+            InstructionType::Setbe => Some(self.cf || self.zf),
+            _ => None,
         }
     }
 
-    fn execute_jmp(&mut self, instruction: &Instruction) {
-        if let Operand::Immediate(target) = instruction.operands[0] {
-            self.rip = target as u64 - 1; // -1 because rip is incremented after execution
+    /// The value of a flag by name (`CF`, `PF`, `AF`, `ZF`, `SF`, `DF` or `OF`).
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        match name {
+            "CF" => Some(self.cf),
+            "PF" => Some(self.pf),
+            "AF" => Some(self.af),
+            "ZF" => Some(self.zf),
+            "SF" => Some(self.sf),
+            "DF" => Some(self.df),
+            "OF" => Some(self.of),
+            _ => None,
         }
     }
-    
-    fn execute_je(&mut self, instruction: &Instruction) {
-        if self.zf {
-            self.execute_jmp(instruction);
-        } else {
-            // Do nothing if condition is not met
+
+    /// Every general-purpose register, then every flag, by name. Cheaper to keep around than a whole CPU,
+    /// whose memory would have to be copied on the next write.
+    pub fn register_values(&self) -> Vec<(&'static str, u64)> {
+        Register::ALL.iter()
+            .map(|reg| (reg.name(RegisterSize::Qword).unwrap_or_default(), self[reg]))
+            .chain(FLAG_NAMES.iter().map(|&flag| (flag, self.flag(flag).unwrap_or_default() as u64)))
+            .collect()
+    }
+
+    fn execute_call(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let top = self.stack_push_slot()?;
+        self.write_memory(top, self.rip + 1)?;
+        self.execute_jmp(instruction)?;
+        self.rsp = top;
+        Ok(())
+    }
+
+    fn execute_ret(&mut self, _instruction: &Instruction) -> Result<(), CpuError> {
+        self.check_stack_not_empty()?;
+        self.rip = self.read_memory(self.rsp)?.wrapping_sub(1); // -1 because rip is incremented after execution
+        self.rsp = self.rsp.wrapping_add(8);
+        Ok(())
+    }
+
+    // A tiny slice of the Linux x86-64 ABI: write and exit. Errors come back as -errno in rax, like the kernel's
+    fn execute_syscall(&mut self) -> Result<(), CpuError> {
+        if self.rax != SYS_WRITE && self.rax != SYS_EXIT {
+            return Err(CpuError::UnsupportedSyscall(self.rax));
         }
+        // The instruction itself saves the return address in rcx and the flags in r11
+        self.rcx = self.rip.wrapping_add(1);
+        self.r11 = self.rflags;
+
+        self.rax = match self.rax {
+            SYS_WRITE => match (self.rdi, self.memory_slice(self.rsi, self.rdx).map(String::from_utf8_lossy)) {
+                (1 | 2, Some(text)) => {
+                    let text = text.into_owned();
+                    self.output.push_str(&text);
+                    self.rdx
+                }
+                (1 | 2, None) => -EFAULT as u64,
+                _ => -EBADF as u64,
+            },
+            _ => {
+                self.exit_status = Some(self.rdi & 0xff); // Only the low byte reaches the parent process
+                self.rax
+            }
+        };
+        Ok(())
+    }
+
+    // Only DF changes; arithmetic never touches it, so it stays put until the next cld/std
+    fn execute_set_direction(&mut self, backward: bool) -> Result<(), CpuError> {
+        self.df = backward;
+        self.recompute_rflags();
+        Ok(())
     }
 
-    fn execute_jne(&mut self, instruction: &Instruction) {
-        if !self.zf {
-            self.execute_jmp(instruction);
+    fn execute_lea(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(dest, size), source] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let address = self.effective_address(source).ok_or_else(|| unsupported(instruction))?;
+        self.set_register(dest, *size, address); // Only the address is computed; memory and flags are untouched
+        Ok(())
+    }
+
+    /// Computes `base + index*scale + disp` for a memory operand, wrapping like the hardware does.
+    pub fn effective_address(&self, operand: &Operand) -> Option<u64> {
+        if let Operand::Memory { base, index, scale, disp, .. } = operand {
+            let base = base.as_ref().map_or(0, |reg| self[reg]);
+            let index = index.as_ref().map_or(0, |reg| self[reg].wrapping_mul(*scale as u64));
+            Some(base.wrapping_add(index).wrapping_add_signed(*disp))
         } else {
-            // Do nothing if condition is not met
+            None
         }
     }
 
-    fn execute_jg(&mut self, instruction: &Instruction) {
-        if !self.zf && self.sf == self.of {
-            self.execute_jmp(instruction);
+    fn execute_bsf(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(dest, size), Operand::Register(src, src_size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let source_value = self.register(src, *src_size);
+        if source_value == 0 {
+            self.zf = true; // Set ZF if source is zero
         } else {
-            // Do nothing if condition is not met
+            self.zf = false;
+            let mut index = 0;
+            while (source_value & (1 << index)) == 0 { // Find the index of the first set bit
+                index += 1;
+            }
+            self.set_register(dest, *size, index);
         }
+        self.recompute_rflags();
+        Ok(())
     }
 
-    fn execute_jge(&mut self, instruction: &Instruction) {
-        if self.sf == self.of {
-            self.execute_jmp(instruction);
+    fn execute_bsr(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(dest, size), Operand::Register(src, src_size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let source_value = self.register(src, *src_size);
+        if source_value == 0 {
+            self.zf = true; // The destination is undefined; like most CPUs, leave it as it was
         } else {
-            // Do nothing if condition is not met
+            self.zf = false;
+            self.set_register(dest, *size, 63 - source_value.leading_zeros() as u64); // Index of the highest set bit
         }
+        self.recompute_rflags();
+        Ok(())
     }
 
-    fn execute_jl(&mut self, instruction: &Instruction) {
-        if self.sf != self.of {
-            self.execute_jmp(instruction);
+    fn execute_bswap(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(reg, size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        // Reverse all 8 bytes, then shift the ones that belong to a narrower register back down
+        let swapped = self.register(reg, *size).swap_bytes() >> (64 - size.bits());
+        self.set_register(reg, *size, swapped);
+        Ok(())
+    }
+
+    // Counts at the operand's width, so `lzcnt eax, ecx` with ecx = 1 gives 31
+    fn execute_bit_count(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(dest, size), Operand::Register(src, src_size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let value = self.register(src, *src_size);
+        let bits = size.bits();
+        let count = match instruction.instruction_type {
+            InstructionType::Popcnt => value.count_ones(),
+            InstructionType::Lzcnt => value.leading_zeros() - (64 - bits),
+            _ => value.trailing_zeros().min(bits),
+        };
+        self.set_register(dest, *size, count as u64);
+
+        if instruction.instruction_type == InstructionType::Popcnt {
+            // Every other flag is cleared
+            self.zf = value == 0;
+            (self.cf, self.pf, self.af, self.sf, self.of) = (false, false, false, false, false);
         } else {
-            // Do nothing if condition is not met
+            // CF flags a zero source, and ZF a count of 0 (the top or bottom bit is set)
+            self.cf = value == 0;
+            self.zf = count == 0;
         }
+        self.recompute_rflags();
+        Ok(())
     }
 
-    fn execute_jle(&mut self, instruction: &Instruction) {
-        if self.zf || self.sf != self.of {
-            self.execute_jmp(instruction);
-        } else {
-            // Do nothing if condition is not met
+    // Writes only the byte register, so `sete al` keeps the rest of rax; zero it first with xor or movzx after
+    fn execute_setcc(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let ([Operand::Register(dest, size)], Some(met)) = (instruction.operands.as_slice(), self.condition_met(&instruction.instruction_type)) else {
+            return Err(unsupported(instruction));
+        };
+        self.set_register(dest, *size, met as u64);
+        Ok(())
+    }
+
+    // CF gets the bit as it was before bts/btr/btc change it; the index wraps at the operand's width
+    fn execute_bit_test(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let (dest, size, value, index) = self.binary_operands(instruction)?;
+        let bit = 1u64 << (index % size.bits() as u64);
+        self.cf = value & bit != 0;
+        self.recompute_rflags();
+        match instruction.instruction_type {
+            InstructionType::Bts => self.set_register(&dest, size, value | bit),
+            InstructionType::Btr => self.set_register(&dest, size, value & !bit),
+            InstructionType::Btc => self.set_register(&dest, size, value ^ bit),
+            _ => {}
         }
+        Ok(())
     }
 
-    fn execute_call(&mut self, instruction: &Instruction) {
-        self.rsp -= 8;
-        self.write_memory(self.rsp, self.rip + 1);
-        self.execute_jmp(instruction);
+    fn execute_cmovne(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::Register(dest, size), Operand::Register(src, src_size)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        if self.condition_met(&instruction.instruction_type) == Some(true) { // Execute only if ZF is not set (not equal)
+            let value = self.register(src, *src_size);
+            self.set_register(dest, *size, value);
+        }
+        Ok(())
     }
 
-    fn execute_ret(&mut self, _instruction: &Instruction) {
-        self.rip = self.read_memory(self.rsp) - 1; // -1 because rip is incremented after execution
-        self.rsp += 8;
+    // movzx/movsx: reads the narrower source, then zero- or sign-extends it to the destination's width
+    fn execute_extending_move(&mut self, instruction: &Instruction, signed: bool) -> Result<(), CpuError> {
+        let [Operand::Register(dest, dest_size), source] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let (value, source_size) = match (source, self.effective_address(source)) {
+            (Operand::Memory { size: Some(size), .. }, Some(address)) => (self.read_memory_sized(address, size.bits() as usize / 8)?, *size),
+            (Operand::Register(reg, size), _) => (self.register(reg, *size), *size),
+            _ => return Err(unsupported(instruction)),
+        };
+        let value = if signed { sign_extend(value, source_size) } else { value };
+        self.set_register(dest, *dest_size, value);
+        Ok(())
     }
 
-    fn execute_bsf(&mut self, instruction: &Instruction) {
-        if let (Operand::Register(dest), Operand::Register(src)) = (&instruction.operands[0], &instruction.operands[1]) {
-            let source_value = self[src];
-            if source_value == 0 {
-                self.zf = true; // Set ZF if source is zero
-            } else {
-                self.zf = false;
-                let mut index = 0;
-                while (source_value & (1 << index)) == 0 { // Find the index of the first set bit
-                    index += 1;
-                }
-                self[dest] = index; 
+    fn execute_xchg(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        match instruction.operands.as_slice() {
+            [Operand::Register(a, a_size), Operand::Register(b, b_size)] => {
+                let (a_value, b_value) = (self.register(a, *a_size), self.register(b, *b_size));
+                // Both writes follow the usual rules, so `xchg eax, eax` still zeroes the upper half of rax
+                self.set_register(a, *a_size, b_value);
+                self.set_register(b, *b_size, a_value);
             }
-        } else {
-            println!("Invalid operands for BSF instruction");
+            [Operand::Register(reg, size), memory @ Operand::Memory { .. }] |
+            [memory @ Operand::Memory { .. }, Operand::Register(reg, size)] => {
+                let address = self.effective_address(memory).expect("a memory operand always has an address");
+                // The width comes from the register; the read happens first so a fault changes nothing
+                let bytes = size.bits() as usize / 8;
+                let memory_value = self.read_memory_sized(address, bytes)?;
+                self.write_memory_sized(address, self.register(reg, *size), bytes)?;
+                self.set_register(reg, *size, memory_value);
+            }
+            _ => return Err(unsupported(instruction)),
         }
+        Ok(())
     }
 
-    fn execute_cmovne(&mut self, instruction: &Instruction) {
-        if !self.zf { // Execute only if ZF is not set (not equal)
-            if let (Operand::Register(dest), Operand::Register(src)) = 
-                (&instruction.operands[0], &instruction.operands[1]) 
-            {
-                self[dest] = self[src];
-            } else {
-                println!("Invalid operands for CMOVNE instruction");
-            }
+    fn execute_paddd(&mut self, instruction: &Instruction) -> Result<(), CpuError> {
+        let [Operand::XmmRegister(dest), Operand::XmmRegister(src)] = instruction.operands.as_slice() else {
+            return Err(unsupported(instruction));
+        };
+        let dest_val = self.xmm[*dest as usize];
+        let src_val = self.xmm[*src as usize];
+        let result = (0..4).map(|i| {
+            let dest_part = (dest_val >> (i * 32)) & 0xFFFFFFFF;
+            let src_part = (src_val >> (i * 32)) & 0xFFFFFFFF;
+            (dest_part.wrapping_add(src_part) & 0xFFFFFFFF) << (i * 32)
+        }).fold(0, |acc, x| acc | x);
+        self.xmm[*dest as usize] = result;
+        Ok(())
+    }
+
+    // Where a push or call would write, if that's still inside the stack
+    fn stack_push_slot(&self) -> Result<u64, CpuError> {
+        match self.rsp.checked_sub(8) {
+            Some(top) if top >= self.stack_limit => Ok(top),
+            _ => Err(CpuError::StackOverflow { rsp: self.rsp }),
         }
     }
 
-    fn execute_paddd(&mut self, instruction: &Instruction) {
-        if let (Operand::XmmRegister(dest), Operand::XmmRegister(src)) = 
-            (&instruction.operands[0], &instruction.operands[1])
-        {
-            let dest_val = self.xmm[*dest as usize];
-            let src_val = self.xmm[*src as usize];
-            let result = (0..4).map(|i| {
-                let dest_part = (dest_val >> (i * 32)) & 0xFFFFFFFF;
-                let src_part = (src_val >> (i * 32)) & 0xFFFFFFFF;
-                (dest_part.wrapping_add(src_part) & 0xFFFFFFFF) << (i * 32)
-            }).fold(0, |acc, x| acc | x);
-            self.xmm[*dest as usize] = result;
-        } else {
-            println!("Invalid operands for paddd instruction");
+    fn check_stack_not_empty(&self) -> Result<(), CpuError> {
+        if self.rsp >= STACK_TOP {
+            return Err(CpuError::StackUnderflow { rsp: self.rsp });
         }
+        Ok(())
+    }
+
+    // The stack's 8-byte accesses
+    fn read_memory(&self, address: u64) -> Result<u64, CpuError> {
+        self.read_memory_sized(address, 8)
+    }
+
+    fn write_memory(&mut self, address: u64, value: u64) -> Result<(), CpuError> {
+        self.write_memory_sized(address, value, 8)
+    }
+
+    /// Reads a little-endian value of `size` bytes (1, 2, 4 or 8), checking the bounds first.
+    pub fn read_memory_sized(&self, address: u64, size: usize) -> Result<u64, CpuError> {
+        let bytes = self.memory_range(address, size)
+            .map(|range| &self.memory[range])
+            .ok_or(CpuError::MemoryOutOfBounds { address, size })?;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(buffer))
+    }
+
+    /// Writes the low `size` bytes of `value` in little-endian order, checking the bounds first.
+    pub fn write_memory_sized(&mut self, address: u64, value: u64, size: usize) -> Result<(), CpuError> {
+        let range = self.memory_range(address, size)
+            .ok_or(CpuError::MemoryOutOfBounds { address, size })?;
+        Rc::make_mut(&mut self.memory)[range].copy_from_slice(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    /// Copies `bytes` into memory starting at `address`, or changes nothing if they don't all fit.
+    pub fn load_into_memory(&mut self, address: u64, bytes: &[u8]) -> Result<(), CpuError> {
+        let range = usize::try_from(address).ok()
+            .and_then(|start| Some(start..start.checked_add(bytes.len())?))
+            .filter(|range| range.end <= self.memory.len())
+            .ok_or(CpuError::MemoryOutOfBounds { address, size: bytes.len() })?;
+        Rc::make_mut(&mut self.memory)[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn memory_slice(&self, address: u64, len: u64) -> Option<&[u8]> {
+        let start = usize::try_from(address).ok()?;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        self.memory.get(start..end)
     }
 
-    fn read_memory(&self, address: u64) -> u64 {
-        let bytes = &self.memory[address as usize..address as usize + 8];
-        u64::from_le_bytes(bytes.try_into().unwrap())
+    fn memory_range(&self, address: u64, size: usize) -> Option<std::ops::Range<usize>> {
+        let start = usize::try_from(address).ok()?;
+        let end = start.checked_add(size)?;
+        (size <= 8 && end <= self.memory.len()).then_some(start..end)
     }
 
-    fn write_memory(&mut self, address: u64, value: u64) {
-        let bytes = value.to_le_bytes();
-        self.memory[address as usize..address as usize + 8].copy_from_slice(&bytes);
+    /// Reads the part of a register named at the given width, e.g. bits 8-15 for `ah`.
+    pub fn register(&self, register: &Register, size: RegisterSize) -> u64 {
+        (self[register] >> size.shift()) & size.mask()
+    }
+
+    /// Writes a register at the given width. Like x86-64, a 32-bit write zeroes the upper half,
+    /// while 8- and 16-bit writes leave the other bits alone.
+    pub fn set_register(&mut self, register: &Register, size: RegisterSize, value: u64) {
+        let value = value & size.mask();
+        self[register] = match size {
+            RegisterSize::Dword | RegisterSize::Qword => value,
+            _ => (self[register] & !(size.mask() << size.shift())) | (value << size.shift()),
+        };
+    }
+
+    // `dest, src` where dest is a register: the destination's width and both values at that width
+    fn binary_operands(&self, instruction: &Instruction) -> Result<(Register, RegisterSize, u64, u64), CpuError> {
+        let (dest, size, source) = match instruction.operands.as_slice() {
+            [Operand::Register(dest, size), Operand::Register(src, src_size)] => (dest, size, self.register(src, *src_size)),
+            [Operand::Register(dest, size), Operand::Immediate(imm)] => (dest, size, *imm as u64 & size.mask()),
+            _ => return Err(unsupported(instruction)),
+        };
+        Ok((dest.clone(), *size, self.register(dest, *size), source))
     }
 
     // Implement other instruction executions (or, xor, inc, dec, etc.) similarly...
 
-    fn update_flags(&mut self, result: u64, overflow: bool) {
-        self.zf = result == 0;
-        self.sf = (result as i64) < 0;
-        self.cf = result < self[&Register::Rax]; // This is a simplification, carry should be set based on the operation
+    fn update_flags(&mut self, result: u64, carry: bool, overflow: bool, size: RegisterSize) {
+        self.zf = result & size.mask() == 0;
+        self.sf = sign_bit(result, size);
+        self.pf = (result as u8).count_ones().is_multiple_of(2); // Even parity of the low byte only, whatever the width
+        self.cf = carry;
         self.of = overflow;
-        
-        // Update rflags register
+        self.recompute_rflags();
+    }
+
+    // For instructions that define CF and OF but leave the other flags alone
+    fn set_carry_and_overflow(&mut self, carry: bool, overflow: bool) {
+        self.cf = carry;
+        self.of = overflow;
+        self.recompute_rflags();
+    }
+
+    /// Rebuilds `rflags` from the individual flags. Bit 1 is reserved and always reads as 1.
+    pub fn recompute_rflags(&mut self) {
         self.rflags = (self.cf as u64) |
+                      (1 << 1) |
+                      ((self.pf as u64) << 2) |
+                      ((self.af as u64) << 4) |
                       ((self.zf as u64) << 6) |
                       ((self.sf as u64) << 7) |
+                      ((self.df as u64) << 10) |
                       ((self.of as u64) << 11);
     }
 }
 
+/// The predicate a conditional instruction tests, and the flags it reads.
+pub fn condition_predicate(instruction_type: &InstructionType) -> Option<(&'static str, &'static [&'static str])> {
+    match instruction_type {
+        InstructionType::Je | InstructionType::Sete => Some(("ZF=1", &["ZF"])),
+        InstructionType::Jne | InstructionType::Cmovne | InstructionType::Setne => Some(("ZF=0", &["ZF"])),
+        InstructionType::Jg | InstructionType::Setg => Some(("ZF=0 and SF=OF", &["ZF", "SF", "OF"])),
+        InstructionType::Jge | InstructionType::Setge => Some(("SF=OF", &["SF", "OF"])),
+        InstructionType::Jl | InstructionType::Setl => Some(("SF≠OF", &["SF", "OF"])),
+        InstructionType::Jle | InstructionType::Setle => Some(("ZF=1 or SF≠OF", &["ZF", "SF", "OF"])),
+        InstructionType::Seta => Some(("CF=0 and ZF=0", &["CF", "ZF"])),
+        InstructionType::Setae => Some(("CF=0", &["CF"])),
+        InstructionType::Setb => Some(("CF=1", &["CF"])),
+        InstructionType::Setbe => Some(("CF=1 or ZF=1", &["CF", "ZF"])),
+        _ => None,
+    }
+}
+
+// a + b + carry_in at the given width: (result, carry out for CF, signed overflow for OF)
+fn add_with_carry(a: u64, b: u64, carry_in: bool, size: RegisterSize) -> (u64, bool, bool) {
+    let sum = a as u128 + b as u128 + carry_in as u128;
+    let result = sum as u64 & size.mask();
+    // Signed overflow: both operands have the same sign and the result's sign differs
+    let overflow = (!(a ^ b) & (a ^ result)) >> (size.bits() - 1) & 1 == 1;
+    (result, sum > size.mask() as u128, overflow)
+}
+
+// a - b - borrow_in at the given width: (result, borrow out for CF, signed overflow for OF)
+fn sub_with_borrow(a: u64, b: u64, borrow_in: bool, size: RegisterSize) -> (u64, bool, bool) {
+    let result = a.wrapping_sub(b).wrapping_sub(borrow_in as u64) & size.mask();
+    // Signed overflow: the operands have different signs and the result's sign differs from a's
+    let overflow = ((a ^ b) & (a ^ result)) >> (size.bits() - 1) & 1 == 1;
+    (result, b as u128 + borrow_in as u128 > a as u128, overflow)
+}
+
+// AF: a carry or borrow between bits 3 and 4, for BCD arithmetic. Bit 4 of the result differs from
+// the sum (or difference) of the operands' bit 4s exactly when one happened, at any width
+fn auxiliary_carry(a: u64, b: u64, result: u64) -> bool {
+    (a ^ b ^ result) & 0x10 != 0
+}
+
+// Like the hardware, only the low 6 bits of the count are used for 64-bit operands and the low 5 bits
+// otherwise, so `shl rax, 64` is a shift by 0 and never runs past the width of a u64
+fn shift_count(shift: i64, size: RegisterSize) -> u32 {
+    let mask = if size == RegisterSize::Qword { 0x3F } else { 0x1F };
+    shift as u32 & mask
+}
+
+fn sign_bit(value: u64, size: RegisterSize) -> bool {
+    (value >> (size.bits() - 1)) & 1 == 1
+}
+
+// Copies the sign bit of a `size`-wide value into all the bits above it
+fn sign_extend(value: u64, size: RegisterSize) -> u64 {
+    let unused = 64 - size.bits();
+    (((value << unused) as i64) >> unused) as u64
+}
+
+fn rotate_left(value: u64, count: u32, size: RegisterSize) -> u64 {
+    let bits = size.bits();
+    let count = count % bits;
+    if count == 0 {
+        value
+    } else {
+        ((value << count) | (value >> (bits - count))) & size.mask()
+    }
+}
+
 impl Index<&Register> for CPU {
     type Output = u64;
 
@@ -474,4 +1103,376 @@ impl IndexMut<&Register> for CPU {
             Register::R15 => &mut self.r15,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_complete_instruction;
+
+    fn run(cpu: &mut CPU, lines: &[&str]) {
+        for line in lines {
+            let instruction = parse_complete_instruction(line).unwrap();
+            cpu.execute(&instruction).unwrap();
+        }
+    }
+
+    #[test]
+    fn not_leaves_flags_alone() {
+        let mut cpu = CPU::new();
+        run(&mut cpu, &["mov rax, 1", "sub rax, 2"]);
+        let rflags = cpu.rflags;
+        run(&mut cpu, &["not rax"]);
+        assert_eq!(cpu.rax, 0);
+        assert_eq!(cpu.rflags, rflags);
+        assert!(cpu.cf && cpu.sf && !cpu.zf);
+    }
+
+    #[test]
+    fn unsupported_syscall_is_an_error() {
+        let mut cpu = CPU::new();
+        run(&mut cpu, &["mov rax, 39"]);
+        let result = cpu.execute(&parse_complete_instruction("syscall").unwrap());
+        assert_eq!(result, Err(CpuError::UnsupportedSyscall(39)));
+        assert_eq!((cpu.rax, cpu.rcx, cpu.output.as_str()), (39, 0, ""));
+    }
+
+    fn run_new(lines: &[&str]) -> CPU {
+        let mut cpu = CPU::new();
+        run(&mut cpu, lines);
+        cpu
+    }
+
+    #[test]
+    fn dword_writes_zero_the_upper_half() {
+        let cpu = run_new(&["mov rax, -1", "mov eax, 1"]);
+        assert_eq!(cpu.rax, 1);
+        let cpu = run_new(&["mov rbx, -1", "mov ecx, ebx"]);
+        assert_eq!(cpu.rcx, 0xffff_ffff);
+    }
+
+    #[test]
+    fn word_and_byte_writes_keep_the_rest() {
+        let cpu = run_new(&["mov rax, -1", "mov ax, 2", "mov ah, 0x12"]);
+        assert_eq!(cpu.rax, 0xffff_ffff_ffff_1202);
+        let cpu = run_new(&["mov rbx, 0x1234", "mov al, bh"]);
+        assert_eq!(cpu.register(&Register::Rax, RegisterSize::Byte), 0x12);
+    }
+
+    #[test]
+    fn signed_multiply() {
+        let cpu = run_new(&["mov rax, 0x4000000000000000", "imul rax, rax, 2"]);
+        assert!(cpu.of && cpu.cf);
+        let cpu = run_new(&["mov rax, 3", "imul rax, rax, -2"]);
+        assert_eq!((cpu.rax as i64, cpu.of), (-6, false));
+    }
+
+    #[test]
+    fn unsigned_multiply_fills_rdx() {
+        let cpu = run_new(&["mov rax, 0x8000000000000000", "mov rbx, 2", "mul rbx"]);
+        assert_eq!((cpu.rax, cpu.rdx, cpu.cf), (0, 1, true));
+        let cpu = run_new(&["mov rax, 6", "mov rdx, 9", "mov rbx, 7", "mul rbx"]);
+        assert_eq!((cpu.rax, cpu.rdx, cpu.cf, cpu.of), (42, 0, false, false));
+    }
+
+    fn fault(cpu: &mut CPU, line: &str) -> CpuError {
+        cpu.execute(&parse_complete_instruction(line).unwrap()).unwrap_err()
+    }
+
+    #[test]
+    fn division_and_its_faults() {
+        let cpu = run_new(&["mov rdx, 0", "mov rax, 17", "mov rbx, 5", "div rbx"]);
+        assert_eq!((cpu.rax, cpu.rdx), (3, 2));
+        let cpu = run_new(&["mov rdx, -1", "mov rax, -17", "mov rbx, 5", "idiv rbx"]);
+        assert_eq!((cpu.rax as i64, cpu.rdx as i64), (-3, -2));
+
+        let mut cpu = run_new(&["mov rax, 1", "mov rbx, 0"]);
+        assert_eq!(fault(&mut cpu, "div rbx"), CpuError::DivideError("division by zero"));
+        let mut cpu = run_new(&["mov rdx, 1", "mov rbx, 1"]);
+        assert_eq!(fault(&mut cpu, "div rbx"), CpuError::DivideError("quotient too large for the destination"));
+    }
+
+    #[test]
+    fn lea_computes_without_reading_memory() {
+        let cpu = run_new(&["mov rbx, 40", "mov rcx, 8", "lea rax, [rbx+rcx*8+4]"]);
+        assert_eq!(cpu.rax, 108);
+    }
+
+    #[test]
+    fn zero_and_sign_extension() {
+        let cpu = run_new(&["mov rbx, 0x80", "movzx rax, bl", "movsx rcx, bl"]);
+        assert_eq!((cpu.rax, cpu.rcx), (0x80, 0xffff_ffff_ffff_ff80));
+    }
+
+    #[test]
+    fn exchange() {
+        let cpu = run_new(&["mov rax, 1", "mov rbx, 2", "xchg rax, rbx"]);
+        assert_eq!((cpu.rax, cpu.rbx), (2, 1));
+        let cpu = run_new(&["mov rax, -1", "xchg eax, eax"]);
+        assert_eq!(cpu.rax, 0xffff_ffff);
+        let mut cpu = run_new(&["mov rbx, 0x100", "mov rcx, 7", "xchg [rbx], rcx"]);
+        assert_eq!(cpu.rcx, 0);
+        run(&mut cpu, &["xchg rcx, [rbx]"]);
+        assert_eq!(cpu.rcx, 7);
+    }
+
+    #[test]
+    fn add_with_carry() {
+        let cpu = run_new(&["mov rax, -1", "mov rdx, 0", "add rax, 1", "adc rdx, 0"]);
+        assert_eq!((cpu.rax, cpu.rdx, cpu.cf), (0, 1, false));
+        let cpu = run_new(&["mov rax, 0", "mov rdx, 1", "sub rax, 1", "sbb rdx, 0"]);
+        assert_eq!((cpu.rax, cpu.rdx), (u64::MAX, 0));
+    }
+
+    #[test]
+    fn nop_only_advances_rip() {
+        let before = run_new(&["mov rax, 1", "cmp rax, 2"]);
+        let mut cpu = before.clone();
+        run(&mut cpu, &["nop"]);
+        assert!(diff_registers(&before, &cpu).is_empty());
+        assert_eq!((cpu.rip, cpu.rflags), (before.rip + 1, before.rflags));
+    }
+
+    #[test]
+    fn set_on_condition() {
+        let cpu = run_new(&["mov rax, 1", "cmp rax, 2", "setl bl", "setb cl", "setg dl", "sete sil"]);
+        assert_eq!((cpu.rbx, cpu.rcx, cpu.rdx, cpu.rsi), (1, 1, 0, 0));
+    }
+
+    #[test]
+    fn loop_counts_rcx_down() {
+        let mut cpu = run_new(&["mov rcx, 2"]);
+        run(&mut cpu, &["loop 0"]);
+        assert_eq!((cpu.rcx, cpu.rip), (1, 0));
+        cpu.rip = 1;
+        run(&mut cpu, &["loop 0"]);
+        assert_eq!((cpu.rcx, cpu.rip), (0, 2));
+    }
+
+    #[test]
+    fn bit_test_instructions() {
+        let cpu = run_new(&["mov rax, 0b1010", "bt rax, 1", "bts rax, 0", "btr rax, 3", "btc rax, 4"]);
+        assert_eq!((cpu.rax, cpu.cf), (0b10011, false));
+        let cpu = run_new(&["mov rax, 0b1010", "bt rax, 3"]);
+        assert_eq!((cpu.rax, cpu.cf), (0b1010, true));
+    }
+
+    #[test]
+    fn bit_scans() {
+        let cpu = run_new(&["mov rbx, 0x90", "bsr rax, rbx", "bsf rcx, rbx"]);
+        assert_eq!((cpu.rax, cpu.rcx), (7, 4));
+        let cpu = run_new(&["mov rbx, 0", "mov rax, 5", "bsr rax, rbx"]);
+        assert_eq!((cpu.rax, cpu.zf), (5, true)); // The destination is left alone
+    }
+
+    #[test]
+    fn bit_counts() {
+        let cpu = run_new(&["mov rbx, 0xf0", "popcnt rax, rbx", "lzcnt rcx, rbx", "tzcnt rdx, rbx"]);
+        assert_eq!((cpu.rax, cpu.rcx, cpu.rdx), (4, 56, 4));
+        let cpu = run_new(&["mov rbx, 0", "lzcnt rcx, rbx", "tzcnt rdx, rbx"]);
+        assert_eq!((cpu.rcx, cpu.rdx, cpu.cf), (64, 64, true));
+    }
+
+    #[test]
+    fn byte_swaps() {
+        let cpu = run_new(&["mov rax, 0x0102030405060708", "bswap rax", "mov ebx, 0x11223344", "bswap ebx"]);
+        assert_eq!((cpu.rax, cpu.rbx), (0x0807060504030201, 0x44332211));
+    }
+
+    #[test]
+    fn sign_extension_into_rdx() {
+        let cpu = run_new(&["mov rax, -7", "cqo", "mov rbx, 2", "idiv rbx"]);
+        assert_eq!((cpu.rax as i64, cpu.rdx as i64), (-3, -1));
+        let cpu = run_new(&["mov rdx, -1", "mov rax, 7", "cqo"]);
+        assert_eq!(cpu.rdx, 0);
+        let cpu = run_new(&["mov eax, 0x80000000", "cdq"]);
+        assert_eq!(cpu.rdx, 0xffff_ffff);
+    }
+
+    #[test]
+    fn write_syscall_collects_output() {
+        let cpu = run_new(&["mov rax, 0x0a6948", "push rax", "mov rax, 1", "mov rdi, 1", "mov rsi, rsp", "mov rdx, 3", "syscall"]);
+        assert_eq!((cpu.output.as_str(), cpu.rax), ("Hi\n", 3));
+        let cpu = run_new(&["mov rax, 1", "mov rdi, 7", "syscall"]);
+        assert_eq!(cpu.rax as i64, -EBADF);
+    }
+
+    #[test]
+    fn faults_leave_the_cpu_on_the_instruction() {
+        let mut cpu = run_new(&["mov rax, 1", "mov rbx, 0"]);
+        assert!(matches!(fault(&mut cpu, "div rbx"), CpuError::DivideError(_)));
+        assert_eq!((cpu.rip, cpu.rax), (2, 1));
+    }
+
+    #[test]
+    fn overflow_flag() {
+        let cpu = run_new(&["mov rax, 0x7fffffffffffffff", "add rax, 1"]);
+        assert!(cpu.of && cpu.sf && !cpu.cf);
+        let cpu = run_new(&["mov rax, 0", "sub rax, 1"]);
+        assert!(cpu.cf && !cpu.of);
+        let cpu = run_new(&["mov rax, 0x8000000000000000", "sub rax, 1"]);
+        assert!(cpu.of && !cpu.cf);
+        let cpu = run_new(&["mov rax, 1", "cmp rax, 0x8000000000000000"]);
+        assert!(cpu.of && cpu.cf);
+    }
+
+    #[test]
+    fn parity_flag() {
+        let cpu = run_new(&["mov rax, 2", "add rax, 1"]);
+        assert!(cpu.pf); // 0b11 has even parity
+        let cpu = run_new(&["mov rax, 0", "add rax, 1"]);
+        assert!(!cpu.pf);
+        // Only the low byte counts
+        let cpu = run_new(&["mov rax, 0x100", "add rax, 1"]);
+        assert!(!cpu.pf);
+    }
+
+    #[test]
+    fn auxiliary_carry_flag() {
+        let cpu = run_new(&["mov rax, 0x0f", "add rax, 1"]);
+        assert!(cpu.af && !cpu.pf);
+        let cpu = run_new(&["mov rax, 0x10", "sub rax, 1"]);
+        assert!(cpu.af);
+        let cpu = run_new(&["mov rax, 0x11", "sub rax, 1"]);
+        assert!(!cpu.af);
+    }
+
+    #[test]
+    fn direction_flag() {
+        let mut cpu = run_new(&["std"]);
+        assert!(cpu.df && cpu.rflags & (1 << 10) != 0);
+        run(&mut cpu, &["mov rax, 1", "add rax, 1"]);
+        assert!(cpu.df); // Arithmetic leaves it alone
+        run(&mut cpu, &["cld"]);
+        assert!(!cpu.df && cpu.rflags & (1 << 10) == 0);
+    }
+
+    #[test]
+    fn shift_flags() {
+        let cpu = run_new(&["mov rax, 0x8000000000000001", "shl rax, 1"]);
+        assert_eq!((cpu.rax, cpu.cf, cpu.of), (2, true, true));
+        let cpu = run_new(&["mov rax, 3", "shr rax, 1"]);
+        assert_eq!((cpu.rax, cpu.cf), (1, true));
+        let cpu = run_new(&["mov rax, 0x8000000000000000", "rol rax, 1"]);
+        assert_eq!((cpu.rax, cpu.cf), (1, true));
+    }
+
+    #[test]
+    fn shift_counts_are_masked() {
+        // Counts are masked to 6 bits, so shifting by 65 shifts by 1
+        let cpu = run_new(&["mov rax, 1", "shl rax, 65"]);
+        assert_eq!(cpu.rax, 2);
+        // A count of 0 changes nothing, flags included
+        let cpu = run_new(&["mov rax, 0", "sub rax, 1", "shl rax, 64"]);
+        assert!(cpu.cf && cpu.sf);
+        let cpu = run_new(&["mov rax, -1", "shr rax, 255"]);
+        assert_eq!(cpu.rax, 1);
+    }
+
+    #[test]
+    fn memory_bounds() {
+        let mut cpu = CPU::new();
+        let last = MEMORY_SIZE as u64 - 8;
+        cpu.write_memory_sized(last, 0x1122334455667788, 8).unwrap();
+        assert_eq!(cpu.read_memory_sized(last, 8), Ok(0x1122334455667788));
+        assert_eq!(cpu.read_memory_sized(last + 1, 8), Err(CpuError::MemoryOutOfBounds { address: last + 1, size: 8 }));
+        assert!(cpu.write_memory_sized(u64::MAX, 0, 2).is_err());
+        run(&mut cpu, &["mov rbx, 0xffffc"]);
+        assert_eq!(fault(&mut cpu, "xchg [rbx], rcx"), CpuError::MemoryOutOfBounds { address: 0xffffc, size: 8 });
+    }
+
+    #[test]
+    fn stack_guards() {
+        let mut cpu = CPU::new();
+        assert!(matches!(fault(&mut cpu, "pop rax"), CpuError::StackUnderflow { .. }));
+        run(&mut cpu, &["mov rax, 7", "push rax", "pop rbx"]);
+        assert_eq!((cpu.rbx, cpu.rsp), (7, STACK_TOP));
+        cpu.rsp = cpu.stack_limit;
+        assert!(matches!(fault(&mut cpu, "push rax"), CpuError::StackOverflow { .. }));
+    }
+
+    #[test]
+    fn rflags_layout() {
+        let cpu = run_new(&["mov rax, 0", "add rax, 0"]);
+        assert_eq!(cpu.rflags & 0b1000_1101_0101, (1 << 6) | (1 << 2)); // ZF and PF
+        assert_eq!(cpu.rflags & 0b10, 0b10); // Bit 1 is always set
+        assert_eq!(cpu.flag("ZF"), Some(true));
+        assert_eq!(cpu.flag("XF"), None);
+    }
+
+    #[test]
+    fn clones_share_memory_until_written() {
+        let mut cpu = CPU::new();
+        let snapshot = cpu.clone();
+        assert!(Rc::ptr_eq(&cpu.memory, &snapshot.memory));
+        cpu.write_memory_sized(0, 1, 1).unwrap();
+        assert!(!Rc::ptr_eq(&cpu.memory, &snapshot.memory));
+        assert_eq!(snapshot.memory[0], 0);
+    }
+
+    #[test]
+    fn diffs_registers_and_flags() {
+        let before = CPU::new();
+        let after = run_new(&["mov rax, 0", "sub rax, 1"]);
+        let changed: Vec<_> = diff_registers(&before, &after).iter().map(|change| change.name).collect();
+        assert!(changed.contains(&"rax") && changed.contains(&"CF") && !changed.contains(&"rbx"));
+    }
+
+    #[test]
+    fn load_into_memory_checks_the_whole_range() {
+        let mut cpu = CPU::new();
+        cpu.load_into_memory(0x200, b"Hi").unwrap();
+        assert_eq!(cpu.read_memory_sized(0x200, 2), Ok(0x6948));
+        assert!(cpu.load_into_memory(MEMORY_SIZE as u64 - 1, b"Hi").is_err());
+        assert!(cpu.load_into_memory(u64::MAX, b"Hi").is_err());
+        assert_eq!(cpu.memory[MEMORY_SIZE - 1], 0);
+    }
+
+    #[test]
+    fn dumps_show_printable_bytes_as_text() {
+        let mut cpu = CPU::new();
+        cpu.load_into_memory(0x100, b"Hi\n~\x7f").unwrap();
+        let dump = cpu.dump_memory(&MemoryDumpOptions { address: 0x100, size: 16, format: MemoryDumpFormat::Hex });
+        assert!(dump.lines().nth(1).unwrap().ends_with("|Hi.~............|"), "{}", dump);
+        assert_eq!((printable_char(b' '), printable_char(b'A'), printable_char(0x80)), (' ', 'A', '.'));
+    }
+
+    #[test]
+    fn dumps_memory_rows() {
+        let mut cpu = CPU::new();
+        cpu.write_memory_sized(0x100, 0x1122334455667788, 8).unwrap();
+        let dump = cpu.dump_memory(&MemoryDumpOptions { address: 0x100, size: 32, format: MemoryDumpFormat::Hex });
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Memory Dump at 0x100:");
+        assert!(lines[1].starts_with("0x00000100:  88 77 66 55 44 33 22 11 00 "), "{}", lines[1]);
+        assert!(lines[2].starts_with("0x00000110:  00 "), "{}", lines[2]);
+    }
+
+    #[test]
+    fn dumps_pad_a_partial_last_row() {
+        let mut cpu = CPU::new();
+        cpu.load_into_memory(0x100, b"Hi").unwrap();
+        let dump = cpu.dump_memory(&MemoryDumpOptions { address: 0x100, size: 4, format: MemoryDumpFormat::Hex });
+        assert_eq!(dump, format!("Memory Dump at 0x100:\n0x00000100:  48 69 00 00 {} |Hi..|\n", " ".repeat(36)));
+        let dump = cpu.dump_memory(&MemoryDumpOptions { address: 0x100, size: 20, format: MemoryDumpFormat::Hex });
+        assert_eq!(dump.lines().count(), 3);
+    }
+
+    #[test]
+    fn dumps_stop_at_the_end_of_memory() {
+        let cpu = CPU::new();
+        let dump = cpu.dump_memory(&MemoryDumpOptions { address: u64::MAX - 3, size: 32, format: MemoryDumpFormat::Hex });
+        assert_eq!(dump.lines().count(), 2);
+        assert!(dump.contains("?? ?? ?? ??"));
+    }
+
+    #[test]
+    fn dumps_in_binary_and_as_characters() {
+        let mut cpu = CPU::new();
+        cpu.load_into_memory(0x100, b"Hi").unwrap();
+        let dump = cpu.dump_memory(&MemoryDumpOptions { address: 0x100, size: 16, format: MemoryDumpFormat::Binary });
+        assert_eq!(dump.lines().count(), 3);
+        let dump = cpu.dump_memory(&MemoryDumpOptions { address: 0x100, size: 2, format: MemoryDumpFormat::Char });
+        assert_eq!(dump.lines().nth(1).map(str::trim_end), Some("0x00000100:  H i"));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::assembler::assemble_instruction;
+use crate::cpu::{diff_values, Change, CpuError, CPU};
+use crate::isa::Isa;
+use crate::parser::{instruction_lines, parse_label_definition, parse_program, Instruction, InstructionType};
+use std::collections::HashSet;
+
+// Guards `run` against programs that loop forever
+pub const MAX_STEPS: usize = 100_000;
+
+/// A buffered program loaded for execution, driven by `rip` as the instruction index.
+pub struct Debugger {
+    pub source: Vec<String>, // One line per instruction; label definitions are left out
+    pub line_numbers: Vec<usize>, // Where each instruction is in the buffer, counting from 1 like the parser's errors
+    pub program: Vec<Instruction>,
+    pub bytes: Vec<Vec<u8>>,
+    // Address of the return-address slot pushed by each active `call`, innermost last
+    call_frames: Vec<u64>,
+    // Under `--strict` a `ret` always pops the stack, even with no `call` to return from
+    strict: bool,
+}
+
+/// Active frames innermost first: the current instruction, then each pending call site.
+pub struct Backtrace {
+    pub frames: Vec<usize>,
+    // Why the walk stopped early, if a saved return address didn't lead back to a `call`
+    pub truncated: Option<String>,
+}
+
+pub struct Executed {
+    pub index: usize,
+    pub instruction: Instruction,
+    // Set when the instruction faulted; `rip` is then left pointing at it
+    pub fault: Option<CpuError>,
+    // The registers and flags it changed
+    pub changes: Vec<Change>,
+}
+
+impl Debugger {
+    /// Parses, level-checks and assembles every line up front so errors are reported before anything runs.
+    pub fn load(source: &[String], isa: &Isa, cpu: &mut CPU) -> Result<Self, String> {
+        let program = parse_program(source)?;
+        let line_numbers: Vec<usize> = source.iter()
+            .enumerate()
+            .filter(|(_, line)| parse_label_definition(line).is_none())
+            .map(|(i, _)| i + 1)
+            .collect();
+
+        let bytes = program.iter()
+            .enumerate()
+            .map(|(i, instruction)| {
+                isa.check(&instruction.instruction_type)
+                    .and_then(|_| assemble_instruction(instruction))
+                    .map_err(|e| format!("Error on line {}: {}", line_numbers[i], e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        cpu.rip = 0;
+        cpu.exit_status = None;
+        Ok(Debugger { source: instruction_lines(source), line_numbers, program, bytes, call_frames: Vec::new(), strict: isa.strict })
+    }
+
+    pub fn current_index(&self, cpu: &CPU) -> Option<usize> {
+        let index = cpu.rip as usize;
+        (index < self.program.len()).then_some(index)
+    }
+
+    pub fn is_finished(&self, cpu: &CPU) -> bool {
+        self.current_index(cpu).is_none()
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_frames.len()
+    }
+
+    /// The buffer line of the next instruction, if it has a breakpoint. A breakpoint on a label
+    /// line counts for the instruction the label points to.
+    pub fn breakpoint_at(&self, cpu: &CPU, breakpoints: &HashSet<usize>) -> Option<usize> {
+        let index = self.current_index(cpu)?;
+        let line = self.line_numbers[index];
+        let first = index.checked_sub(1).map_or(1, |previous| self.line_numbers[previous] + 1);
+        (first..=line).any(|l| breakpoints.contains(&l)).then_some(line)
+    }
+
+    /// Executes the instruction at `rip`, or returns `None` once the program has finished.
+    pub fn step(&mut self, cpu: &mut CPU) -> Option<Executed> {
+        let index = self.current_index(cpu)?;
+        let instruction = self.program[index].clone();
+
+        // A `ret` with no matching `call` returns from the program itself. This is a convenience:
+        // the hardware would jump to whatever address is on top of the stack
+        if instruction.instruction_type == InstructionType::Ret && self.call_frames.is_empty() && !self.strict {
+            cpu.rip = self.program.len() as u64;
+            return Some(Executed { index, instruction, fault: None, changes: Vec::new() });
+        }
+
+        let return_slot = cpu.rsp.wrapping_sub(8);
+        let before = cpu.register_values();
+        if let Err(fault) = cpu.execute(&instruction) {
+            let changes = diff_values(&before, &cpu.register_values());
+            return Some(Executed { index, instruction, fault: Some(fault), changes });
+        }
+        match instruction.instruction_type {
+            InstructionType::Call => self.call_frames.push(return_slot),
+            InstructionType::Ret => { self.call_frames.pop(); }
+            _ => {}
+        }
+        if cpu.exit_status.is_some() {
+            cpu.rip = self.program.len() as u64; // The exit syscall ends the program where it stands
+        }
+        let changes = diff_values(&before, &cpu.register_values());
+        Some(Executed { index, instruction, fault: None, changes })
+    }
+
+    /// Like `step`, but runs a `call` through to its matching `ret`.
+    pub fn step_over(&mut self, cpu: &mut CPU) -> Vec<Executed> {
+        let depth = self.call_depth();
+        self.step_while(cpu, |debugger, _| debugger.call_depth() > depth)
+    }
+
+    /// Runs until the current subroutine returns to its caller.
+    pub fn step_out(&mut self, cpu: &mut CPU) -> Result<Vec<Executed>, String> {
+        let depth = self.call_depth();
+        if depth == 0 {
+            return Err("Not inside a call; nothing to step out of".to_string());
+        }
+        Ok(self.step_while(cpu, |debugger, _| debugger.call_depth() >= depth))
+    }
+
+    /// Runs until the program finishes.
+    pub fn run(&mut self, cpu: &mut CPU) -> Vec<Executed> {
+        self.step_while(cpu, |_, _| true)
+    }
+
+    /// Runs until the program finishes or the next instruction is on a breakpoint line. The instruction
+    /// at `rip` always runs, so continuing from a breakpoint gets past it.
+    pub fn run_to_breakpoint(&mut self, cpu: &mut CPU, breakpoints: &HashSet<usize>) -> Vec<Executed> {
+        self.step_while(cpu, |debugger, cpu| debugger.breakpoint_at(cpu, breakpoints).is_none())
+    }
+
+    /// Walks the return addresses saved on the stack by each active `call`.
+    pub fn backtrace(&self, cpu: &CPU) -> Backtrace {
+        let mut frames: Vec<usize> = self.current_index(cpu).into_iter().collect();
+        let mut truncated = None;
+
+        for &slot in self.call_frames.iter().rev() {
+            let call_site = match cpu.read_memory_sized(slot, 8) {
+                // `call` saves the index of the instruction after it
+                Ok(return_address) => return_address.checked_sub(1).map(|index| index as usize),
+                Err(e) => {
+                    truncated = Some(format!("frame at {:#x} is unreadable: {}", slot, e));
+                    break;
+                }
+            };
+            match call_site {
+                Some(index) if matches!(self.program.get(index), Some(i) if i.instruction_type == InstructionType::Call) => {
+                    frames.push(index);
+                }
+                _ => {
+                    truncated = Some(format!("frame at {:#x} does not return to a call (stack corrupted?)", slot));
+                    break;
+                }
+            }
+        }
+
+        Backtrace { frames, truncated }
+    }
+
+    // Always executes at least one instruction, then keeps going while `keep_going` holds and nothing faults
+    fn step_while(&mut self, cpu: &mut CPU, keep_going: impl Fn(&Self, &CPU) -> bool) -> Vec<Executed> {
+        let mut executed = Vec::new();
+        while let Some(step) = self.step(cpu) {
+            let faulted = step.fault.is_some();
+            executed.push(step);
+            if faulted || !keep_going(self, cpu) || executed.len() >= MAX_STEPS {
+                break;
+            }
+        }
+        executed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(lines: &[&str], cpu: &mut CPU) -> Debugger {
+        let source: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        Debugger::load(&source, &Isa::full(), cpu).unwrap()
+    }
+
+    #[test]
+    fn steps_over_and_out_of_calls() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["call f", "mov rbx, 1", "ret", "f:", "mov rax, 7", "call g", "ret", "g:", "ret"], &mut cpu);
+        assert_eq!(debugger.step_over(&mut cpu).len(), 5);
+        assert_eq!((cpu.rax, debugger.current_index(&cpu)), (7, Some(1)));
+
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["call f", "ret", "f:", "call g", "ret", "g:", "ret"], &mut cpu);
+        debugger.step(&mut cpu);
+        debugger.step(&mut cpu);
+        assert_eq!(debugger.call_depth(), 2);
+        debugger.step_out(&mut cpu).unwrap();
+        assert_eq!(debugger.current_index(&cpu), Some(3));
+        debugger.step_out(&mut cpu).unwrap();
+        assert!(debugger.step_out(&mut cpu).is_err());
+    }
+
+    #[test]
+    fn backtrace_lists_each_pending_call() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["call f", "ret", "f:", "call g", "ret", "g:", "ret"], &mut cpu);
+        debugger.step(&mut cpu);
+        debugger.step(&mut cpu);
+        let backtrace = debugger.backtrace(&cpu);
+        assert_eq!((backtrace.frames, backtrace.truncated), (vec![4, 2, 0], None));
+    }
+
+    #[test]
+    fn backtrace_stops_at_a_corrupted_frame() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["call f", "ret", "f:", "mov rax, 9", "push rax", "pop rbx"], &mut cpu);
+        debugger.step(&mut cpu);
+        // Overwrite the saved return address
+        cpu.write_memory_sized(cpu.rsp, 9, 8).unwrap();
+        let backtrace = debugger.backtrace(&cpu);
+        assert_eq!(backtrace.frames, vec![2]);
+        assert!(backtrace.truncated.unwrap().contains("does not return to a call"));
+    }
+
+    #[test]
+    fn errors_name_the_buffer_line() {
+        let source: Vec<String> = ["start:", "mov rax, 1", "again:", "push rax"].map(String::from).to_vec();
+        let intro = Isa::from_spec("intro").unwrap();
+        let error = Debugger::load(&source, &intro, &mut CPU::new()).err().unwrap();
+        assert!(error.starts_with("Error on line 4: 'push' is not allowed"), "{}", error);
+    }
+
+    #[test]
+    fn bare_ret_ends_the_program_unless_strict() {
+        let source: Vec<String> = ["mov rax, 3", "push rax", "ret", "mov rbx, 1"].map(String::from).to_vec();
+        let mut cpu = CPU::new();
+        Debugger::load(&source, &Isa::full(), &mut cpu).unwrap().run(&mut cpu);
+        assert_eq!((cpu.rbx, cpu.rip), (0, 4));
+
+        // Strict mode pops the pushed 3 and jumps there, like the hardware
+        let mut strict = Isa::full();
+        strict.strict = true;
+        let mut cpu = CPU::new();
+        Debugger::load(&source, &strict, &mut cpu).unwrap().run(&mut cpu);
+        assert_eq!(cpu.rbx, 1);
+
+        let mut cpu = CPU::new();
+        let executed = Debugger::load(&["ret".to_string()], &strict, &mut cpu).unwrap().run(&mut cpu);
+        assert!(executed[0].fault.is_some());
+    }
+
+    #[test]
+    fn runs_loops_to_the_end() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["mov rcx, 3", "mov rax, 0", "top:", "add rax, 2", "loop top"], &mut cpu);
+        assert_eq!(debugger.run(&mut cpu).len(), 8);
+        assert_eq!((cpu.rax, cpu.rcx), (6, 0));
+    }
+
+    #[test]
+    fn exit_syscall_ends_the_program() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["mov rax, 60", "mov rdi, 3", "syscall", "mov rbx, 1"], &mut cpu);
+        debugger.run(&mut cpu);
+        assert_eq!((cpu.exit_status, cpu.rbx), (Some(3), 0));
+    }
+
+    #[test]
+    fn faults_leave_rip_on_the_instruction() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["mov rbx, 0", "div rbx", "nop"], &mut cpu);
+        let executed = debugger.run(&mut cpu);
+        assert!(matches!(executed.last().unwrap().fault, Some(CpuError::DivideError(_))));
+        assert_eq!(debugger.current_index(&cpu), Some(1));
+    }
+
+    #[test]
+    fn steps_and_reports_changes() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["mov rax, 5", "add rax, 1"], &mut cpu);
+        let first = debugger.step(&mut cpu).unwrap();
+        assert_eq!((first.index, first.changes[0].name, first.changes[0].after), (0, "rax", 5));
+        debugger.step(&mut cpu).unwrap();
+        assert_eq!(cpu.rax, 6);
+        assert!(debugger.is_finished(&cpu) && debugger.step(&mut cpu).is_none());
+    }
+
+    #[test]
+    fn stops_at_breakpoints_including_on_labels() {
+        let mut cpu = CPU::new();
+        let mut debugger = load(&["mov rax, 1", "here:", "mov rbx, 2", "mov rcx, 3"], &mut cpu);
+        let breakpoints = HashSet::from([2]);
+        assert_eq!(debugger.run_to_breakpoint(&mut cpu, &breakpoints).len(), 1);
+        assert_eq!(debugger.breakpoint_at(&cpu, &breakpoints), Some(3));
+        // Continuing runs the instruction on the breakpoint
+        assert_eq!(debugger.run_to_breakpoint(&mut cpu, &breakpoints).len(), 2);
+        assert!(debugger.is_finished(&cpu));
+    }
+}
@@ -0,0 +1,61 @@
+use iced_x86::{Decoder, DecoderError, DecoderOptions, Formatter, IntelFormatter};
+
+use crate::encodings::format_bytes;
+
+/// Decodes 64-bit machine code into one line of Intel syntax per instruction, spelled the way
+/// the REPL's parser reads it (`mov rax, 0x10`). Bytes that don't decode are shown as `(bad: ..)`,
+/// or `(incomplete: ..)` when the input stops partway through an instruction. Branch targets are
+/// byte offsets from the first byte.
+pub fn disassemble(bytes: &[u8]) -> Vec<String> {
+    let mut decoder = Decoder::with_ip(64, bytes, 0, DecoderOptions::NONE);
+    let mut formatter = IntelFormatter::new();
+    let options = formatter.options_mut();
+    options.set_hex_prefix("0x");
+    options.set_hex_suffix("");
+    options.set_uppercase_hex(false);
+    options.set_space_after_operand_separator(true);
+    options.set_branch_leading_zeros(false);
+    options.set_show_branch_size(false);
+    options.set_signed_immediate_operands(true);
+
+    let mut lines = Vec::new();
+    while decoder.can_decode() {
+        let start = decoder.position();
+        let instruction = decoder.decode();
+        match decoder.last_error() {
+            DecoderError::None => {
+                let mut text = String::new();
+                formatter.format(&instruction, &mut text);
+                lines.push(text);
+            }
+            DecoderError::NoMoreBytes => {
+                lines.push(format!("(incomplete: {})", format_bytes(&bytes[start..])));
+                break;
+            }
+            // Skip just the first byte, so the instructions after it still line up
+            _ => {
+                lines.push(format!("(bad: {:02x})", bytes[start]));
+                decoder.set_position(start + 1).expect("a position inside the input");
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_in_the_parsers_syntax() {
+        assert_eq!(disassemble(&[0x90]), ["nop"]);
+        assert_eq!(disassemble(&[0x48, 0xff, 0xc0]), ["inc rax"]);
+        assert_eq!(disassemble(&[0x48, 0xc7, 0xc0, 0x10, 0x00, 0x00, 0x00]), ["mov rax, 0x10"]);
+    }
+
+    #[test]
+    fn marks_bad_and_incomplete_bytes() {
+        assert_eq!(disassemble(&[0x06, 0x90]), ["(bad: 06)", "nop"]);
+        assert_eq!(disassemble(&[0x90, 0x48]), ["nop", "(incomplete: 48)"]);
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs;
+
+// Where Linux maps the program; the usual base address for static x86-64 executables
+const LOAD_ADDRESS: u64 = 0x400000;
+const ELF_HEADER_SIZE: u16 = 64;
+const PROGRAM_HEADER_SIZE: u16 = 56;
+// The code follows the two headers directly
+const CODE_OFFSET: u64 = ELF_HEADER_SIZE as u64 + PROGRAM_HEADER_SIZE as u64;
+
+// `mov eax, 60` / `xor edi, edi` / `syscall`: exit(0) for a program that runs off its end, which the
+// emulator allows but a real process would follow into whatever bytes come next
+const EXIT_STUB: &[u8] = &[0xb8, 0x3c, 0x00, 0x00, 0x00, 0x31, 0xff, 0x0f, 0x05];
+
+/// Wraps machine code assembled at address 0 in a minimal static x86-64 Linux executable: an ELF
+/// header, one loadable read+execute segment and the code, followed by an exit(0) stub. Branches are
+/// relative, so the code runs unchanged at the load address.
+pub fn build_executable(code: &[u8]) -> Vec<u8> {
+    let file_size = CODE_OFFSET + (code.len() + EXIT_STUB.len()) as u64;
+    let mut elf = Vec::with_capacity(file_size as usize);
+
+    // ELF header
+    elf.extend_from_slice(b"\x7fELF");
+    elf.extend_from_slice(&[2, 1, 1, 0]); // 64-bit, little-endian, version 1, System V ABI
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // Executable
+    elf.extend_from_slice(&0x3eu16.to_le_bytes()); // x86-64
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&(LOAD_ADDRESS + CODE_OFFSET).to_le_bytes()); // Entry point: the first instruction
+    elf.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // Program headers start right after
+    elf.extend_from_slice(&0u64.to_le_bytes()); // No section headers
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    elf.extend_from_slice(&PROGRAM_HEADER_SIZE.to_le_bytes());
+    elf.extend_from_slice(&1u16.to_le_bytes());
+    elf.extend_from_slice(&[0; 6]); // Section header size, count and name index
+
+    // Program header: load the whole file, headers included, so the code lands at its entry point
+    elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    elf.extend_from_slice(&5u32.to_le_bytes()); // Readable and executable
+    elf.extend_from_slice(&0u64.to_le_bytes());
+    elf.extend_from_slice(&LOAD_ADDRESS.to_le_bytes());
+    elf.extend_from_slice(&LOAD_ADDRESS.to_le_bytes());
+    elf.extend_from_slice(&file_size.to_le_bytes());
+    elf.extend_from_slice(&file_size.to_le_bytes());
+    elf.extend_from_slice(&0x1000u64.to_le_bytes());
+
+    elf.extend_from_slice(code);
+    elf.extend_from_slice(EXIT_STUB);
+    elf
+}
+
+/// Writes `build_executable(code)` to `path` and marks it executable.
+pub fn write_executable(path: &str, code: &[u8]) -> Result<(), String> {
+    fs::write(path, build_executable(code)).map_err(|e| format!("Can't write {}: {}", path, e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("Can't make {} executable: {}", path, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_points_at_the_code() {
+        let code = [0x90, 0xc3];
+        let elf = build_executable(&code);
+        assert_eq!(&elf[..4], b"\x7fELF");
+        assert_eq!(u16::from_le_bytes([elf[18], elf[19]]), 0x3e);
+        let entry = u64::from_le_bytes(elf[24..32].try_into().unwrap());
+        assert_eq!(entry, LOAD_ADDRESS + CODE_OFFSET);
+        let code_start = CODE_OFFSET as usize;
+        assert_eq!(&elf[code_start..code_start + code.len()], code);
+        assert!(elf.ends_with(EXIT_STUB));
+    }
+}
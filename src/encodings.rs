@@ -0,0 +1,69 @@
+use std::fs;
+
+use crate::assembler::assemble_instruction;
+use crate::parser::parse_complete_instruction;
+
+/// A corpus entry whose assembled bytes differ from the pinned ones.
+pub struct Mismatch {
+    pub line: usize,
+    pub instruction: String,
+    pub expected: Vec<u8>,
+    // What the assembler produced now, or why it couldn't assemble the instruction
+    pub actual: Result<Vec<u8>, String>,
+}
+
+pub struct Report {
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Assembles every instruction in a corpus file and compares it with the bytes pinned next to it.
+///
+/// Lines look like `lea rax, [rbx+rcx*4+8] => 48 8d 44 8b 08`, with the expected encoding in hex.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn check(path: &str) -> Result<Report, String> {
+    let corpus = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let mut report = Report { checked: 0, mismatches: Vec::new() };
+
+    for (i, line) in corpus.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (instruction, expected) = line.split_once("=>")
+            .ok_or_else(|| format!("Line {}: expected `<instruction> => <hex bytes>`", i))?;
+        let expected = parse_hex_bytes(expected).map_err(|e| format!("Line {}: {}", i, e))?;
+
+        let actual = parse_complete_instruction(instruction).and_then(|parsed| assemble_instruction(&parsed));
+        if actual.as_ref() != Ok(&expected) {
+            report.mismatches.push(Mismatch { line: i, instruction: instruction.trim().to_string(), expected, actual });
+        }
+        report.checked += 1;
+    }
+
+    Ok(report)
+}
+
+pub fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    text.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("'{}' is not a hex byte", byte)))
+        .collect()
+}
+
+pub fn format_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_matches_the_reference_assembler() {
+        let report = check(concat!(env!("CARGO_MANIFEST_DIR"), "/corpus/encodings.txt")).unwrap();
+        let mismatched: Vec<_> = report.mismatches.iter()
+            .map(|mismatch| format!("line {}: {}", mismatch.line, mismatch.instruction))
+            .collect();
+        assert!(report.checked > 0);
+        assert!(mismatched.is_empty(), "{:?}", mismatched);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::calculator::parse_number;
+use crate::cpu::{condition_predicate, CPU};
+use crate::isa;
+use crate::parser::{Instruction, InstructionType, Operand, Register, RegisterSize};
+
+/// An lea whose address is a scaled copy of one register, e.g. `[rbx+rbx*2]` = rbx*3.
+pub struct LeaMultiply {
+    pub register: Register,
+    pub factor: u64,
+    pub addend: i64,
+}
+
+pub fn lea_multiply_idiom(instruction: &Instruction) -> Option<LeaMultiply> {
+    if instruction.instruction_type != InstructionType::Lea {
+        return None;
+    }
+    match instruction.operands.get(1)? {
+        Operand::Memory { base: Some(base), index: Some(index), scale, disp, .. } if base == index => Some(LeaMultiply {
+            register: base.clone(),
+            factor: *scale as u64 + 1,
+            addend: *disp,
+        }),
+        Operand::Memory { base: None, index: Some(index), scale, disp, .. } if *scale > 1 => Some(LeaMultiply {
+            register: index.clone(),
+            factor: *scale as u64,
+            addend: *disp,
+        }),
+        _ => None,
+    }
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Explanations                                                  ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+fn idiom_notes(instruction: &Instruction) -> Vec<String> {
+    let mut notes = Vec::new();
+
+    if let Some(idiom) = lea_multiply_idiom(instruction) {
+        let addend = match idiom.addend {
+            0 => String::new(),
+            a if a < 0 => format!(" - {}", a.unsigned_abs()),
+            a => format!(" + {}", a),
+        };
+        notes.push(format!(
+            "Multiply idiom: this computes {} * {}{} in a single instruction, without touching flags.",
+            idiom.register, idiom.factor, addend
+        ));
+    } else if let (InstructionType::Lea, Some(dest @ Operand::Register(..)), Some(address)) =
+        (&instruction.instruction_type, instruction.operands.first(), instruction.operands.get(1))
+    {
+        notes.push(format!("Computes {} = {} as plain arithmetic; no memory is read.", dest, address.to_string().trim_matches(['[', ']'])));
+    }
+
+    match (&instruction.instruction_type, instruction.operands.as_slice()) {
+        (InstructionType::Xor, [a @ Operand::Register(..), b]) if a == b =>
+            notes.push(format!("Zeroing idiom: {} ^ {} is always 0, and the encoding is shorter than mov {}, 0.", a, a, a)),
+        (InstructionType::Test, [a @ Operand::Register(..), b]) if a == b =>
+            notes.push(format!("Checks whether {} is zero (ZF) or negative (SF) without an immediate.", a)),
+        (InstructionType::Mov, [Operand::Register(reg, RegisterSize::Dword), b]) if &instruction.operands[0] == b =>
+            notes.push(format!("Not a no-op: a 32-bit write zeroes the upper 32 bits of {}.", reg)),
+        // The one-byte `xchg eax, reg` opcodes start at 0x90, and 0x90 itself is `nop`
+        (InstructionType::Xchg, [Operand::Register(Register::Rax, RegisterSize::Qword), b]) if &instruction.operands[0] == b =>
+            notes.push("A no-op: this assembles to 48 90, the nop opcode with a REX.W prefix.".to_string()),
+        (InstructionType::Xchg, [Operand::Register(Register::Rax, RegisterSize::Dword), b]) if &instruction.operands[0] == b =>
+            notes.push("Not a no-op: the 32-bit write zeroes the upper 32 bits of rax. The short encoding would be 0x90, \
+                        which means nop in 64-bit mode, so it's assembled as 87 C0 instead.".to_string()),
+        (InstructionType::Xchg, [Operand::Register(reg, RegisterSize::Dword), b]) if &instruction.operands[0] == b =>
+            notes.push(format!("Not a no-op: a 32-bit write zeroes the upper 32 bits of {}.", reg)),
+        _ => notes.extend(no_op_note(instruction).map(String::from)),
+    }
+
+    notes
+}
+
+/// A note for instructions that leave their destination as it was, e.g. `mov rax, rax` or `add rax, 0`.
+/// 32-bit forms aren't no-ops, since the write zeroes the upper half of the register.
+pub fn no_op_note(instruction: &Instruction) -> Option<&'static str> {
+    use InstructionType::*;
+
+    let [dest @ Operand::Register(_, size), source] = instruction.operands.as_slice() else {
+        return None;
+    };
+    if *size == RegisterSize::Dword {
+        return None;
+    }
+    let all_ones = matches!(source, Operand::Immediate(imm) if *imm as u64 & size.mask() == size.mask());
+    match (&instruction.instruction_type, source) {
+        (Mov, _) if dest == source => Some("No-op: moving a register onto itself has no effect."),
+        (Xchg, _) if dest == source => Some("No-op: swapping a register with itself has no effect."),
+        (Shl | Shr | Rol | Ror, Operand::Immediate(0)) => Some("No-op: shifting or rotating by 0 changes neither the register nor the flags."),
+        (Add | Sub | Or | Xor, Operand::Immediate(0)) => Some("Near no-op: an operation with 0 leaves the register unchanged, but the flags are still updated."),
+        (And, _) if all_ones => Some("Near no-op: and with all ones leaves the register unchanged, but the flags are still updated."),
+        (And | Or, _) if dest == source => Some("Near no-op: the register keeps its value, but the flags are updated, like test."),
+        _ => None,
+    }
+}
+
+/// Why a conditional instruction that just ran did or didn't act, e.g. "jg taken because ZF=0 and SF=OF".
+pub fn condition_explanation(instruction: &Instruction, cpu: &CPU) -> Option<String> {
+    let (predicate, flags) = condition_predicate(&instruction.instruction_type)?;
+    let met = cpu.condition_met(&instruction.instruction_type)?;
+    let values = flags.iter()
+        .map(|flag| cpu.flag(flag).map(|value| format!("{}={}", flag, value as u8)))
+        .collect::<Option<Vec<_>>>()?
+        .join(", ");
+
+    let (yes, no) = match instruction.instruction_type {
+        InstructionType::Cmovne => ("moved", "did not move"),
+        InstructionType::Sete | InstructionType::Setne | InstructionType::Setg | InstructionType::Setge |
+        InstructionType::Setl | InstructionType::Setle | InstructionType::Seta | InstructionType::Setae |
+        InstructionType::Setb | InstructionType::Setbe => ("wrote 1", "wrote 0"),
+        _ => ("taken", "not taken"),
+    };
+    Some(if met {
+        format!("{} {} because {} ({})", instruction.instruction_type, yes, predicate, values)
+    } else {
+        format!("{} {} because it needs {} ({})", instruction.instruction_type, no, predicate, values)
+    })
+}
+
+/// Describes what an instruction does, plus any well-known idiom it matches.
+pub fn explain(instruction: &Instruction) -> Vec<String> {
+    let mut lines = Vec::new();
+    match isa::info(&instruction.instruction_type) {
+        Some(info) => lines.push(format!("{} ({}): {}", instruction.instruction_type, info.category, info.description)),
+        None => lines.push(format!("{}: no description available.", instruction.instruction_type)),
+    }
+    lines.extend(idiom_notes(instruction));
+    lines
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Signed vs Unsigned Comparison                                 ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+/// Parses a number the way the calculator does, as the 64-bit pattern `cmp` would see.
+pub fn parse_value(token: &str) -> Result<u64, String> {
+    parse_number(token)
+        .map(|value| value as u64)
+        .map_err(|e| format!("Invalid value {}: {}", token, e))
+}
+
+fn ordering_symbol(ordering: std::cmp::Ordering) -> &'static str {
+    match ordering {
+        std::cmp::Ordering::Less => "<",
+        std::cmp::Ordering::Equal => "=",
+        std::cmp::Ordering::Greater => ">",
+    }
+}
+
+/// Walks through `cmp a, b`: the flags it sets and which signed/unsigned jumps follow.
+pub fn signedness_report(a: u64, b: u64) -> Vec<String> {
+    let result = a.wrapping_sub(b);
+    let cf = a < b;
+    let zf = result == 0;
+    let sf = (result as i64) < 0;
+    let of = ((a ^ b) & (a ^ result)) >> 63 == 1;
+    let bit = |flag: bool| flag as u8;
+
+    let signed_jumps = [
+        ("jg", !zf && sf == of, "ZF=0 and SF=OF"),
+        ("jge", sf == of, "SF=OF"),
+        ("jl", sf != of, "SF≠OF"),
+        ("jle", zf || sf != of, "ZF=1 or SF≠OF"),
+    ];
+    let unsigned_jumps = [
+        ("ja", !cf && !zf, "CF=0 and ZF=0"),
+        ("jae", !cf, "CF=0"),
+        ("jb", cf, "CF=1"),
+        ("jbe", cf || zf, "CF=1 or ZF=1"),
+    ];
+    let describe = |(name, taken, condition): (&str, bool, &str)| {
+        format!("{:<4} {:<10} ({})", name, if taken { "taken" } else { "not taken" }, condition)
+    };
+
+    let mut lines = vec![
+        format!("cmp {:#x}, {:#x}  →  {:#x} - {:#x} = {:#x}", a, b, a, b, result),
+        String::new(),
+        format!("{:<8} {:>22}   {:>22}", "", "signed", "unsigned"),
+        format!("{:<8} {:>22}   {:>22}", "a", a as i64, a),
+        format!("{:<8} {:>22}   {:>22}", "b", b as i64, b),
+        format!("{:<8} {:>22}   {:>22}", "a ? b",
+            ordering_symbol((a as i64).cmp(&(b as i64))), ordering_symbol(a.cmp(&b))),
+        String::new(),
+        format!("Flags: CF={} ZF={} SF={} OF={}", bit(cf), bit(zf), bit(sf), bit(of)),
+        format!("  CF={}: {}", bit(cf), if cf { "a < b as unsigned values (the subtraction borrowed)" } else { "no borrow, so a ≥ b as unsigned values" }),
+        format!("  SF={} OF={}: {}", bit(sf), bit(of), if sf != of { "SF≠OF, so a < b as signed values" } else { "SF=OF, so a ≥ b as signed values" }),
+        String::new(),
+        format!("{:<36} {}", "Signed jumps", "Unsigned jumps"),
+    ];
+    for (signed, unsigned) in signed_jumps.into_iter().zip(unsigned_jumps) {
+        lines.push(format!("{:<36} {}", describe(signed), describe(unsigned)));
+    }
+    lines.push(format!("Both: je {}, jne {}", if zf { "taken" } else { "not taken" }, if zf { "not taken" } else { "taken" }));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_complete_instruction;
+
+    #[test]
+    fn recognizes_lea_multiplies() {
+        let idiom = lea_multiply_idiom(&parse_complete_instruction("lea rax, [rbx+rbx*4+3]").unwrap()).unwrap();
+        assert_eq!((idiom.register, idiom.factor, idiom.addend), (Register::Rbx, 5, 3));
+        let idiom = lea_multiply_idiom(&parse_complete_instruction("lea rax, [rcx*8]").unwrap()).unwrap();
+        assert_eq!((idiom.register, idiom.factor), (Register::Rcx, 8));
+        assert!(lea_multiply_idiom(&parse_complete_instruction("lea rax, [rbx+rcx*2]").unwrap()).is_none());
+    }
+
+    #[test]
+    fn parses_values_like_the_calculator() {
+        assert_eq!(parse_value("0b1000"), Ok(8));
+        assert_eq!(parse_value("0x10"), Ok(16));
+        assert_eq!(parse_value("-1"), Ok(u64::MAX));
+        assert_eq!(parse_value("-0x10"), Ok(16u64.wrapping_neg()));
+        assert_eq!(parse_value("0xffffffffffffffff"), Ok(u64::MAX));
+        assert!(parse_value("-0x8000000000000001").is_err());
+        assert!(parse_value("0xG").is_err());
+    }
+
+    fn report_line(report: &[String], start: &str) -> String {
+        report.iter().find(|line| line.starts_with(start)).cloned().unwrap()
+    }
+
+    #[test]
+    fn signed_and_unsigned_disagree_across_the_sign_bit() {
+        // -1 is less than 1 as a signed value but the largest unsigned one
+        let report = signedness_report(u64::MAX, 1);
+        assert_eq!(report_line(&report, "Flags:"), "Flags: CF=0 ZF=0 SF=1 OF=0");
+        assert!(report_line(&report, "jg ").contains("jg   not taken") && report_line(&report, "jg ").contains("ja   taken"));
+        assert!(report_line(&report, "jl ").contains("jl   taken") && report_line(&report, "jl ").contains("jb   not taken"));
+        assert_eq!(report_line(&report, "Both:"), "Both: je not taken, jne taken");
+    }
+
+    #[test]
+    fn equal_values_take_only_the_inclusive_jumps() {
+        let report = signedness_report(5, 5);
+        assert_eq!(report_line(&report, "Flags:"), "Flags: CF=0 ZF=1 SF=0 OF=0");
+        assert!(report_line(&report, "jge").contains("jge  taken") && report_line(&report, "jge").contains("jae  taken"));
+        assert!(report_line(&report, "jg ").contains("jg   not taken") && report_line(&report, "jg ").contains("ja   not taken"));
+        assert_eq!(report_line(&report, "Both:"), "Both: je taken, jne not taken");
+    }
+
+    #[test]
+    fn signed_overflow_sets_of() {
+        // i64::MIN - 1 wraps to i64::MAX
+        let report = signedness_report(1 << 63, 1);
+        assert_eq!(report_line(&report, "Flags:"), "Flags: CF=0 ZF=0 SF=0 OF=1");
+        assert!(report_line(&report, "jl ").contains("jl   taken"));
+    }
+
+    fn explain_after(setup: &[&str], line: &str) -> Option<String> {
+        let mut cpu = CPU::new();
+        for setup_line in setup {
+            cpu.execute(&parse_complete_instruction(setup_line).unwrap()).unwrap();
+        }
+        condition_explanation(&parse_complete_instruction(line).unwrap(), &cpu)
+    }
+
+    #[test]
+    fn explains_why_a_condition_held() {
+        let compare = ["mov rax, 5", "cmp rax, 3"];
+        assert_eq!(explain_after(&compare, "jg done"), Some("jg taken because ZF=0 and SF=OF (ZF=0, SF=0, OF=0)".to_string()));
+        assert_eq!(explain_after(&compare, "je done"), Some("je not taken because it needs ZF=1 (ZF=0)".to_string()));
+        assert_eq!(explain_after(&compare, "setl al"), Some("setl wrote 0 because it needs SF≠OF (SF=0, OF=0)".to_string()));
+        assert_eq!(explain_after(&compare, "jmp done"), None);
+    }
+
+    fn note(line: &str) -> Option<&'static str> {
+        no_op_note(&parse_complete_instruction(line).unwrap())
+    }
+
+    #[test]
+    fn notes_register_no_ops() {
+        assert_eq!(note("mov rax, rax"), Some("No-op: moving a register onto itself has no effect."));
+        assert_eq!(note("xchg rbx, rbx"), Some("No-op: swapping a register with itself has no effect."));
+        assert!(note("add rax, 0").unwrap().starts_with("Near no-op"));
+        // The 32-bit form clears the upper half, so it does something
+        assert_eq!(note("xchg eax, eax"), None);
+        assert_eq!(note("mov rax, rbx"), None);
+        assert_eq!(note("add rax, 1"), None);
+    }
+}
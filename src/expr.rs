@@ -0,0 +1,254 @@
+use crate::calculator::parse_number;
+use crate::cpu::CPU;
+use crate::parser::parse_register_name;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Operator(op) => write!(f, "{}", op),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExprError {
+    DivisionByZero,
+    Syntax(String),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprError::DivisionByZero => write!(f, "Division by zero"),
+            ExprError::Syntax(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// Each operator as written, and the operator it means; script mode's APL glyphs are aliases.
+// Longest first, so `<<` isn't read as two `<`.
+const OPERATORS: &[(&str, &str)] = &[
+    ("<<", "<<"), (">>", ">>"),
+    ("+", "+"), ("-", "-"), ("*", "*"), ("×", "*"), ("/", "/"), ("÷", "/"), ("%", "%"),
+    ("&", "&"), ("∧", "&"), ("|", "|"), ("∨", "|"), ("^", "^"), ("⊻", "^"), ("~", "~"),
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_alphanumeric() || c == '_' {
+            // Numbers take in letters too, for `0xff` and `0b101`
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let word = &rest[..end];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(parse_number(word).map_err(|e| ExprError::Syntax(format!("Invalid number {}: {}", word, e)))?)
+            } else {
+                Token::Name(word.to_string())
+            });
+            rest = &rest[end..];
+        } else if let Some((text, op)) = OPERATORS.iter().find(|(text, _)| rest.starts_with(text)) {
+            tokens.push(Token::Operator(op));
+            rest = &rest[text.len()..];
+        } else {
+            tokens.push(match c {
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                _ => return Err(ExprError::Syntax(format!("Unexpected '{}'", c))),
+            });
+            rest = &rest[c.len_utf8()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+// How tightly a binary operator holds its operands, loosest first, as in C
+fn binding_power(op: &str) -> Option<u8> {
+    match op {
+        "|" => Some(1),
+        "^" => Some(2),
+        "&" => Some(3),
+        "<<" | ">>" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        _ => None,
+    }
+}
+
+// Wraps on overflow like the CPU does. Shift counts are masked to 6 bits like `shl rax, cl`, and
+// `>>` is a logical shift, like the calculator's `shr`.
+fn apply(op: &str, a: i64, b: i64) -> Result<i64, ExprError> {
+    Ok(match op {
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" | "%" if b == 0 => return Err(ExprError::DivisionByZero),
+        "/" => a.wrapping_div(b),
+        "%" => a.wrapping_rem(b),
+        "&" => a & b,
+        "|" => a | b,
+        "^" => a ^ b,
+        "<<" => a.wrapping_shl(b as u32),
+        ">>" => (a as u64).wrapping_shr(b as u32) as i64,
+        _ => return Err(ExprError::Syntax(format!("Unknown operator {}", op))),
+    })
+}
+
+/// Whether `name` is `rip` or a general-purpose register at any width, like `rax` or `r8b`.
+pub fn is_register_name(name: &str) -> bool {
+    name.eq_ignore_ascii_case("rip") || parse_register_name(name).is_some()
+}
+
+/// Reads `rip` or a general-purpose register of any width by name, as an unsigned value of that width.
+pub fn read_register(name: &str, cpu: &CPU) -> Option<u64> {
+    if name.eq_ignore_ascii_case("rip") {
+        return Some(cpu.rip);
+    }
+    parse_register_name(name).map(|(reg, size)| cpu.register(&reg, size))
+}
+
+struct ExpressionParser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    resolve: &'a dyn Fn(&str) -> Result<i64, String>,
+}
+
+impl ExpressionParser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // Binary operators binding at least as tightly as `min_power`, by precedence climbing
+    fn expression(&mut self, min_power: u8) -> Result<i64, ExprError> {
+        let mut value = self.operand()?;
+        while let Some(Token::Operator(op)) = self.tokens.get(self.position) {
+            let op = *op;
+            match binding_power(op) {
+                Some(power) if power >= min_power => {
+                    self.position += 1;
+                    let right = self.expression(power + 1)?;
+                    value = apply(op, value, right)?;
+                }
+                _ => break,
+            }
+        }
+        Ok(value)
+    }
+
+    fn operand(&mut self) -> Result<i64, ExprError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Name(name)) => (self.resolve)(&name).map_err(ExprError::Syntax),
+            Some(Token::Operator("-")) => Ok(self.operand()?.wrapping_neg()),
+            Some(Token::Operator("~")) => Ok(!self.operand()?),
+            Some(Token::LeftParen) => {
+                let value = self.expression(0)?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(value),
+                    Some(token) => Err(ExprError::Syntax(format!("Expected ')' but found '{}'", token))),
+                    None => Err(ExprError::Syntax("Missing ')'".to_string())),
+                }
+            }
+            Some(token) => Err(ExprError::Syntax(format!("Expected a number but found '{}'", token))),
+            None => Err(ExprError::Syntax("Expected a number at the end of the expression".to_string())),
+        }
+    }
+}
+
+/// Evaluates an infix integer expression such as `(3 + 4) * 2` or `x × 2`, with C's operator
+/// precedence. Numbers can be decimal, `0x` hex or `0b` binary; `resolve` gives the value of any
+/// other name, or the error to report for it.
+pub fn evaluate_with(input: &str, resolve: &dyn Fn(&str) -> Result<i64, String>) -> Result<i64, ExprError> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Err(ExprError::Syntax("Empty expression".to_string()));
+    }
+    let mut parser = ExpressionParser { tokens, position: 0, resolve };
+    let value = parser.expression(0)?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(ExprError::Syntax(format!("Unexpected '{}'", token))),
+        None => Ok(value),
+    }
+}
+
+/// The calculator's expressions, like `rax & 0xff | rbx`: names are looked up in `variables`,
+/// then read from the CPU's registers, ignoring case.
+pub fn evaluate(input: &str, cpu: &CPU, variables: &HashMap<String, i64>) -> Result<i64, String> {
+    let resolve = |name: &str| {
+        let name = name.to_lowercase();
+        variables.get(&name).copied()
+            .or_else(|| read_register(&name, cpu).map(|value| value as i64))
+            .ok_or_else(|| format!("Unknown variable or register '{}'", name))
+    };
+    evaluate_with(input, &resolve).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Result<i64, ExprError> {
+        evaluate_with(input, &|name| Err(format!("Unknown '{}'", name)))
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("2 + 3 * 4"), Ok(14));
+        assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+        assert_eq!(eval("1 | 6 ^ 3 & 5"), Ok(7)); // 1 | (6 ^ (3 & 5))
+        assert_eq!(eval("-2 * ~0"), Ok(2));
+        assert_eq!(eval("0xff & 0b1010"), Ok(10));
+    }
+
+    #[test]
+    fn apl_glyphs_are_aliases() {
+        assert_eq!(eval("6 × 7 ÷ 2"), Ok(21));
+        assert_eq!(eval("12 ∧ 10 ∨ 1 ⊻ 3"), Ok((12 & 10) | (1 ^ 3)));
+    }
+
+    #[test]
+    fn wraps_and_masks_like_the_cpu() {
+        assert_eq!(eval("9223372036854775807 + 1"), Ok(i64::MIN));
+        assert_eq!(eval("1 << 64"), Ok(1));
+        assert_eq!(eval("-1 >> 60"), Ok(0xf));
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(eval("1 % 0"), Err(ExprError::DivisionByZero));
+        assert_eq!(eval(""), Err(ExprError::Syntax("Empty expression".to_string())));
+        assert_eq!(eval("(1 + 2"), Err(ExprError::Syntax("Missing ')'".to_string())));
+        assert_eq!(eval("1 2"), Err(ExprError::Syntax("Unexpected '2'".to_string())));
+        assert_eq!(eval("1 $ 2"), Err(ExprError::Syntax("Unexpected '$'".to_string())));
+        assert_eq!(eval("y + 1"), Err(ExprError::Syntax("Unknown 'y'".to_string())));
+    }
+
+    #[test]
+    fn names_are_variables_then_registers() {
+        let mut cpu = CPU::new();
+        cpu.rbx = 0x1ff;
+        cpu.rip = 3;
+        let variables = HashMap::from([("rbx".to_string(), 1), ("n".to_string(), 4)]);
+        assert_eq!(evaluate("N + BL + rip", &cpu, &variables), Ok(4 + 0xff + 3));
+        assert_eq!(evaluate("rbx", &cpu, &variables), Ok(1));
+        assert!(is_register_name("r8b") && is_register_name("RIP") && !is_register_name("r16"));
+    }
+}
@@ -0,0 +1,176 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::cpu::CPU;
+use crate::debugger::{Debugger, Executed};
+use crate::isa::Isa;
+use crate::parser::{strip_comment, Register};
+use crate::script_mode::evaluate_expression;
+
+// At the same position the longer operator wins, so `<=` isn't read as `<`
+const COMPARISONS: &[&str] = &["==", "!=", "<=", ">=", "<", ">"];
+const WIDTHS: &[(&str, usize)] = &[("byte", 1), ("word", 2), ("dword", 4), ("qword", 8)];
+
+pub struct Outcome {
+    pub line: usize,
+    pub assertion: String,
+    // The evaluated left and right sides, or why the assertion couldn't be evaluated
+    pub result: Result<(u64, u64), String>,
+    pub passed: bool,
+}
+
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.outcomes.iter().all(|outcome| outcome.passed)
+    }
+}
+
+/// Runs the program on a fresh CPU, then checks each assertion in the spec against the final state.
+///
+/// Spec lines look like `rax == 10` or `qword[rsp] != 0`: two expressions, using register and
+/// flag names as variables and `[addr]` for memory, joined by a comparison. Lines starting with
+/// `#` or `;` are comments.
+pub fn grade(program_path: &str, spec_path: &str) -> Result<Report, String> {
+    let program = read_lines(program_path)?;
+    let spec = fs::read_to_string(spec_path).map_err(|e| format!("Can't read {}: {}", spec_path, e))?;
+
+    let mut cpu = CPU::new();
+    let mut debugger = Debugger::load(&program, &Isa::full(), &mut cpu)?;
+    if let Some(Executed { index, fault: Some(fault), .. }) = debugger.run(&mut cpu).pop() {
+        return Err(format!("{} faulted at instruction {}: {}", program_path, index, fault));
+    }
+    if !debugger.is_finished(&cpu) {
+        return Err(format!("{} did not finish within {} steps", program_path, crate::debugger::MAX_STEPS));
+    }
+
+    let outcomes = spec.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+        .map(|(line, assertion)| {
+            let result = check(assertion, &cpu);
+            let passed = matches!(result, Ok(Comparison { holds: true, .. }));
+            Outcome { line, assertion: assertion.to_string(), result: result.map(|c| (c.left, c.right)), passed }
+        })
+        .collect();
+
+    Ok(Report { outcomes })
+}
+
+fn read_lines(path: &str) -> Result<Vec<String>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    Ok(source.lines()
+        .map(|line| strip_comment(line).trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+struct Comparison {
+    left: u64,
+    right: u64,
+    holds: bool,
+}
+
+fn check(assertion: &str, cpu: &CPU) -> Result<Comparison, String> {
+    let (position, operator) = COMPARISONS.iter()
+        .filter_map(|op| assertion.find(op).map(|position| (position, *op)))
+        .min_by_key(|(position, op)| (*position, Reverse(op.len())))
+        .ok_or_else(|| "Expected a comparison (==, !=, <, <=, >, >=)".to_string())?;
+
+    let vars = cpu_variables(cpu);
+    let left = evaluate(&assertion[..position], cpu, &vars)?;
+    let right = evaluate(&assertion[position + operator.len()..], cpu, &vars)?;
+
+    let holds = match operator {
+        "==" => left == right,
+        "!=" => left != right,
+        "<=" => left <= right,
+        ">=" => left >= right,
+        "<" => left < right,
+        _ => left > right,
+    };
+    Ok(Comparison { left, right, holds })
+}
+
+fn cpu_variables(cpu: &CPU) -> HashMap<String, u64> {
+    let mut vars: HashMap<String, u64> = Register::ALL.iter()
+        .map(|register| (register.to_string(), cpu[register]))
+        .collect();
+    vars.insert("rip".to_string(), cpu.rip);
+    for (flag, value) in [("cf", cpu.cf), ("pf", cpu.pf), ("af", cpu.af), ("zf", cpu.zf), ("sf", cpu.sf), ("df", cpu.df), ("of", cpu.of)] {
+        vars.insert(flag.to_string(), value as u64);
+    }
+    vars
+}
+
+// Replaces each `[addr]` (optionally prefixed with a width, qword by default) with the value in memory
+fn evaluate(expr: &str, cpu: &CPU, vars: &HashMap<String, u64>) -> Result<u64, String> {
+    let mut expr = expr.to_string();
+
+    while let Some(open) = expr.find('[') {
+        let close = expr[open..].find(']').map(|offset| open + offset).ok_or("Missing ']' in memory reference")?;
+        let address = evaluate_expression(&expr[open + 1..close], vars).map_err(|e| e.to_string())?;
+
+        let before = expr[..open].trim_end();
+        let (start, size) = WIDTHS.iter()
+            .find(|(width, _)| before.ends_with(width)
+                && !before[..before.len() - width.len()].ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+            .map_or((open, 8), |(width, size)| (before.len() - width.len(), *size));
+
+        let value = cpu.read_memory_sized(address, size).map_err(|e| e.to_string())?;
+        expr.replace_range(start..=close, &value.to_string());
+    }
+
+    evaluate_expression(&expr, vars).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes the program and spec to a temporary directory of their own, returning both paths
+    fn write_files(name: &str, program: &str, spec: &str) -> (String, String) {
+        let dir = std::env::temp_dir().join(format!("asmlab-grade-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (program_path, spec_path) = (dir.join("program.asm"), dir.join("program.spec"));
+        fs::write(&program_path, program).unwrap();
+        fs::write(&spec_path, spec).unwrap();
+        (program_path.to_string_lossy().into_owned(), spec_path.to_string_lossy().into_owned())
+    }
+
+    const SUM: &str = "mov rcx, 3\nxor rax, rax\nagain:\n  add rax, rcx ; accumulate\nloop again\n";
+
+    #[test]
+    fn passing_spec() {
+        let (program, spec) = write_files("pass", SUM, "# sum of 1..3\nrax == 6\nrcx == 0\nzf == 0\nrax * 2 >= 0xc\n");
+        let report = grade(&program, &spec).unwrap();
+        assert!(report.passed());
+        let outcomes: Vec<_> = report.outcomes.iter().map(|o| (o.line, o.result.clone(), o.passed)).collect();
+        assert_eq!(outcomes, vec![
+            (2, Ok((6, 6)), true),
+            (3, Ok((0, 0)), true),
+            (4, Ok((0, 0)), true),
+            (5, Ok((12, 12)), true),
+        ]);
+    }
+
+    #[test]
+    fn failing_spec() {
+        let (program, spec) = write_files("fail", SUM, "rax == 7\nrax != 7\nqword[rsp + 8] < 1\nrax\n");
+        let report = grade(&program, &spec).unwrap();
+        assert!(!report.passed());
+        let outcomes: Vec<_> = report.outcomes.iter().map(|o| (o.assertion.as_str(), o.result.clone(), o.passed)).collect();
+        assert_eq!(outcomes, vec![
+            ("rax == 7", Ok((6, 7)), false),
+            ("rax != 7", Ok((6, 7)), true),
+            ("qword[rsp + 8] < 1", Err("Memory access out of bounds: 8 byte(s) at 0x100000".to_string()), false),
+            ("rax", Err("Expected a comparison (==, !=, <, <=, >, >=)".to_string()), false),
+        ]);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::parser::{Instruction, InstructionType, Operand};
+use InstructionType::*;
+
+pub struct InstructionInfo {
+    pub instruction_type: InstructionType,
+    pub category: &'static str,
+    pub description: &'static str,
+}
+
+const fn entry(instruction_type: InstructionType, category: &'static str, description: &'static str) -> InstructionInfo {
+    InstructionInfo { instruction_type, category, description }
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Supported Instructions                                        ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+pub const INSTRUCTIONS: &[InstructionInfo] = &[
+    entry(InstructionType::Mov, "data transfer", "Copies the source operand into the destination."),
+    entry(InstructionType::Movzx, "data transfer", "Copies a narrower source into the destination, filling the upper bits with zeros."),
+    entry(InstructionType::Movsx, "data transfer", "Copies a narrower source into the destination, filling the upper bits with copies of its sign bit."),
+    entry(InstructionType::Xchg, "data transfer", "Swaps the values of its two operands; flags are unchanged."),
+    entry(InstructionType::Lea, "data transfer", "Loads the effective address of a memory operand without accessing memory or changing flags."),
+    entry(InstructionType::Add, "arithmetic", "Adds the source to the destination and updates the flags."),
+    entry(InstructionType::Sub, "arithmetic", "Subtracts the source from the destination and updates the flags."),
+    entry(InstructionType::Adc, "arithmetic", "Adds the source plus CF to the destination, carrying into the next word of a multi-word addition."),
+    entry(InstructionType::Sbb, "arithmetic", "Subtracts the source plus CF from the destination, borrowing from the next word of a multi-word subtraction."),
+    entry(InstructionType::Mul, "arithmetic", "Unsigned multiply of rax by the operand into rdx:rax (high half in rdx); CF and OF are set if rdx is nonzero."),
+    entry(InstructionType::Imul, "arithmetic", "Signed multiply: `imul a, b` sets a = a * b and `imul a, b, n` sets a = b * n; CF and OF flag a result that doesn't fit."),
+    entry(InstructionType::Div, "arithmetic", "Unsigned divide of rdx:rax by the operand: quotient in rax, remainder in rdx. Dividing by zero, or a quotient too big for rax, raises #DE."),
+    entry(InstructionType::Idiv, "arithmetic", "Signed divide of rdx:rax by the operand: quotient in rax, remainder (with the dividend's sign) in rdx. Raises #DE like div."),
+    entry(InstructionType::Cdq, "arithmetic", "Sign-extends eax into edx:eax, filling edx with copies of eax's sign bit; the usual setup for a 32-bit idiv."),
+    entry(InstructionType::Cqo, "arithmetic", "Sign-extends rax into rdx:rax, filling rdx with copies of rax's sign bit; the usual setup for a 64-bit idiv."),
+    entry(InstructionType::Inc, "arithmetic", "Adds 1 to the operand; CF is left unchanged."),
+    entry(InstructionType::Dec, "arithmetic", "Subtracts 1 from the operand; CF is left unchanged."),
+    entry(InstructionType::Neg, "arithmetic", "Replaces the operand with its two's complement (0 - operand)."),
+    entry(InstructionType::And, "logic", "Bitwise AND of destination and source; clears CF and OF."),
+    entry(InstructionType::Or, "logic", "Bitwise OR of destination and source; clears CF and OF."),
+    entry(InstructionType::Xor, "logic", "Bitwise exclusive OR of destination and source; clears CF and OF."),
+    entry(InstructionType::Not, "logic", "Flips every bit of the operand; flags are unchanged."),
+    entry(InstructionType::Shl, "shift/rotate", "Shifts bits left, filling with zeros; multiplies by powers of two. CF gets the last bit shifted out."),
+    entry(InstructionType::Shr, "shift/rotate", "Shifts bits right, filling with zeros; unsigned division by powers of two. CF gets the last bit shifted out."),
+    entry(InstructionType::Rol, "shift/rotate", "Rotates bits left; bits leaving the top re-enter at the bottom. Only CF and OF change."),
+    entry(InstructionType::Ror, "shift/rotate", "Rotates bits right; bits leaving the bottom re-enter at the top. Only CF and OF change."),
+    entry(InstructionType::Push, "stack", "Decrements rsp by 8 and stores the operand at the new top of the stack."),
+    entry(InstructionType::Pop, "stack", "Loads the top of the stack into the operand and increments rsp by 8."),
+    entry(InstructionType::Cmp, "compare", "Subtracts the source from the destination to set flags, discarding the result."),
+    entry(InstructionType::Test, "compare", "ANDs the operands to set flags, discarding the result."),
+    entry(InstructionType::Jmp, "jump", "Unconditionally jumps to the target."),
+    entry(InstructionType::Je, "jump", "Jumps if equal (ZF=1)."),
+    entry(InstructionType::Jne, "jump", "Jumps if not equal (ZF=0)."),
+    entry(InstructionType::Jg, "jump", "Jumps if greater, signed (ZF=0 and SF=OF)."),
+    entry(InstructionType::Jge, "jump", "Jumps if greater or equal, signed (SF=OF)."),
+    entry(InstructionType::Jl, "jump", "Jumps if less, signed (SF≠OF)."),
+    entry(InstructionType::Jle, "jump", "Jumps if less or equal, signed (ZF=1 or SF≠OF)."),
+    entry(InstructionType::Loop, "jump", "Decrements rcx and jumps if it isn't 0 yet, without touching the flags; a counted loop in one instruction."),
+    entry(InstructionType::Call, "call/return", "Pushes the return address and jumps to the target."),
+    entry(InstructionType::Ret, "call/return", "Pops the return address off the stack and jumps to it."),
+    entry(InstructionType::Syscall, "call/return", "Asks the kernel for a service numbered by rax: write (1) prints rdx bytes at rsi to fd rdi, exit (60) ends the program with status rdi."),
+    entry(InstructionType::Nop, "misc", "Does nothing but move on to the next instruction; used for padding and alignment."),
+    entry(InstructionType::Cld, "flag control", "Clears DF, so string instructions step forward through memory."),
+    entry(InstructionType::Std, "flag control", "Sets DF, so string instructions step backward through memory."),
+    entry(InstructionType::Paddd, "SIMD", "Adds four packed 32-bit integers lane by lane."),
+    entry(InstructionType::Bsf, "bit manipulation", "Finds the index of the lowest set bit; sets ZF if the source is zero."),
+    entry(InstructionType::Bsr, "bit manipulation", "Finds the index of the highest set bit; sets ZF if the source is zero."),
+    entry(InstructionType::Popcnt, "bit manipulation", "Counts the set bits of the source; sets ZF if the source is zero."),
+    entry(InstructionType::Lzcnt, "bit manipulation", "Counts the zero bits above the highest set bit; a zero source gives the operand width and sets CF."),
+    entry(InstructionType::Tzcnt, "bit manipulation", "Counts the zero bits below the lowest set bit; a zero source gives the operand width and sets CF."),
+    entry(InstructionType::Bswap, "bit manipulation", "Reverses the byte order of a 32- or 64-bit register, converting between little- and big-endian; flags are unchanged."),
+    entry(InstructionType::Bt, "bit manipulation", "Copies the bit at the given index into CF, e.g. `bt rax, 3` tests bit 3."),
+    entry(InstructionType::Bts, "bit manipulation", "Copies the bit at the given index into CF, then sets it to 1."),
+    entry(InstructionType::Btr, "bit manipulation", "Copies the bit at the given index into CF, then resets it to 0."),
+    entry(InstructionType::Btc, "bit manipulation", "Copies the bit at the given index into CF, then flips it."),
+    entry(InstructionType::Cmovne, "conditional move", "Moves the source into the destination only if ZF=0."),
+    entry(InstructionType::Sete, "conditional set", "Sets a byte register to 1 if equal (ZF=1), otherwise 0. Also spelled setz."),
+    entry(InstructionType::Setne, "conditional set", "Sets a byte register to 1 if not equal (ZF=0), otherwise 0. Also spelled setnz."),
+    entry(InstructionType::Setg, "conditional set", "Sets a byte register to 1 if greater, signed (ZF=0 and SF=OF), otherwise 0."),
+    entry(InstructionType::Setge, "conditional set", "Sets a byte register to 1 if greater or equal, signed (SF=OF), otherwise 0."),
+    entry(InstructionType::Setl, "conditional set", "Sets a byte register to 1 if less, signed (SF≠OF), otherwise 0."),
+    entry(InstructionType::Setle, "conditional set", "Sets a byte register to 1 if less or equal, signed (ZF=1 or SF≠OF), otherwise 0."),
+    entry(InstructionType::Seta, "conditional set", "Sets a byte register to 1 if above, unsigned (CF=0 and ZF=0), otherwise 0."),
+    entry(InstructionType::Setae, "conditional set", "Sets a byte register to 1 if above or equal, unsigned (CF=0), otherwise 0."),
+    entry(InstructionType::Setb, "conditional set", "Sets a byte register to 1 if below, unsigned (CF=1), otherwise 0."),
+    entry(InstructionType::Setbe, "conditional set", "Sets a byte register to 1 if below or equal, unsigned (CF=1 or ZF=1), otherwise 0."),
+];
+
+pub fn info(instruction_type: &InstructionType) -> Option<&'static InstructionInfo> {
+    INSTRUCTIONS.iter().find(|info| &info.instruction_type == instruction_type)
+}
+
+/// Instructions whose mnemonic, category or description mentions the keyword, ignoring case.
+pub fn search(keyword: &str) -> Vec<&'static InstructionInfo> {
+    let keyword = keyword.trim().to_lowercase();
+    INSTRUCTIONS.iter()
+        .filter(|info| {
+            info.instruction_type.to_string().contains(&keyword)
+                || info.category.to_lowercase().contains(&keyword)
+                || info.description.to_lowercase().contains(&keyword)
+        })
+        .collect()
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Cycle Estimates                                               ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+// Roughly what a load from the L1 cache adds
+const MEMORY_OPERAND_CYCLES: u32 = 4;
+
+/// A rough latency in cycles, loosely after recent Intel and AMD cores. It's for comparing
+/// programs, e.g. showing that `div` is far slower than a shift, not for predicting real timings.
+pub fn estimated_cycles(instruction: &Instruction) -> u32 {
+    let base = match instruction.instruction_type {
+        Mov | Movzx | Movsx | Lea | Xchg | Nop => 1,
+        Add | Sub | Adc | Sbb | Inc | Dec | Neg | Cdq | Cqo => 1,
+        And | Or | Xor | Not | Shl | Shr | Rol | Ror | Cmp | Test => 1,
+        Bt | Bts | Btr | Btc | Bswap | Cmovne | Paddd | Cld | Std => 1,
+        Sete | Setne | Setg | Setge | Setl | Setle | Seta | Setae | Setb | Setbe => 1,
+        Jmp | Je | Jne | Jg | Jge | Jl | Jle => 1,
+        Push | Pop | Call | Ret => 2,
+        Bsf | Bsr | Popcnt | Lzcnt | Tzcnt | Mul | Imul => 3,
+        Loop => 5,
+        Div | Idiv => 20,
+        Syscall => 100, // A trip into the kernel and back
+    };
+    let memory = instruction.operands.iter().any(|operand| matches!(operand, Operand::Memory { .. }));
+    // `lea` computes an address without loading from it
+    if memory && instruction.instruction_type != Lea { base + MEMORY_OPERAND_CYCLES } else { base }
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Instruction Set Levels                                        ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+const INTRO: &[InstructionType] = &[Mov, Add, Sub, Cmp, Jmp];
+const BASIC: &[InstructionType] = &[
+    Mov, Add, Sub, Inc, Dec, Neg, And, Or, Xor, Not, Cmp, Test,
+    Jmp, Je, Jne, Jg, Jge, Jl, Jle,
+];
+const PROCEDURES: &[InstructionType] = &[
+    Mov, Lea, Add, Sub, Inc, Dec, Neg, And, Or, Xor, Not, Shl, Shr, Rol, Ror, Cmp, Test,
+    Jmp, Je, Jne, Jg, Jge, Jl, Jle, Push, Pop, Call, Ret,
+];
+
+pub const LEVELS: &[(&str, &[InstructionType])] = &[
+    ("intro", INTRO),
+    ("basic", BASIC),
+    ("procedures", PROCEDURES),
+];
+
+/// Restricts which instructions may be assembled and executed, e.g. for coursework.
+pub struct Isa {
+    pub name: String,
+    // `None` allows every supported instruction
+    allowed: Option<Vec<InstructionType>>,
+    // Hardware-faithful behavior only, with no emulator conveniences (`--strict`)
+    pub strict: bool,
+}
+
+impl Isa {
+    pub fn full() -> Self {
+        Isa { name: "full".to_string(), allowed: None, strict: false }
+    }
+
+    /// Accepts a level name (`intro`, `basic`, `procedures`, `full`) or a comma/space separated list of mnemonics.
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim().to_lowercase();
+        if spec == "full" {
+            return Ok(Isa::full());
+        }
+        if let Some((name, allowed)) = LEVELS.iter().find(|(name, _)| *name == spec) {
+            return Ok(Isa { name: name.to_string(), allowed: Some(allowed.to_vec()), strict: false });
+        }
+
+        let allowed = spec.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                INSTRUCTIONS.iter()
+                    .find(|info| info.instruction_type.to_string() == name)
+                    .map(|info| info.instruction_type.clone())
+                    .ok_or_else(|| format!("'{}' is neither a level nor a supported instruction", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if allowed.is_empty() {
+            return Err("Expected a level name or a list of instructions".to_string());
+        }
+
+        Ok(Isa { name: "custom".to_string(), allowed: Some(allowed), strict: false })
+    }
+
+    pub fn allowed(&self) -> Vec<InstructionType> {
+        match &self.allowed {
+            Some(allowed) => allowed.clone(),
+            None => INSTRUCTIONS.iter().map(|info| info.instruction_type.clone()).collect(),
+        }
+    }
+
+    pub fn check(&self, instruction_type: &InstructionType) -> Result<(), String> {
+        match &self.allowed {
+            Some(allowed) if !allowed.contains(instruction_type) => Err(format!(
+                "'{}' is not allowed at the '{}' instruction level (allowed: {})",
+                instruction_type, self.name, join(allowed),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn join(instructions: &[InstructionType]) -> String {
+    instructions.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_complete_instruction;
+
+    #[test]
+    fn levels_restrict_instructions() {
+        let intro = Isa::from_spec("Intro").unwrap();
+        assert!(intro.check(&Mov).is_ok());
+        assert_eq!(intro.check(&Push).unwrap_err(),
+                   "'push' is not allowed at the 'intro' instruction level (allowed: mov, add, sub, cmp, jmp)");
+        assert!(Isa::full().check(&Popcnt).is_ok());
+    }
+
+    #[test]
+    fn custom_lists_of_mnemonics() {
+        let custom = Isa::from_spec("mov, add xor").unwrap();
+        assert_eq!((custom.name.as_str(), custom.allowed()), ("custom", vec![Mov, Add, Xor]));
+        assert_eq!(Isa::from_spec("mov, frob").err().unwrap(), "'frob' is neither a level nor a supported instruction");
+        assert!(Isa::from_spec(" , ").is_err());
+    }
+
+    #[test]
+    fn estimates_cycles() {
+        let cycles = |line: &str| estimated_cycles(&parse_complete_instruction(line).unwrap());
+        assert_eq!(cycles("add rax, 1"), 1);
+        assert_eq!(cycles("div rbx"), 20);
+        assert_eq!(cycles("xchg [rbx], rcx"), 1 + MEMORY_OPERAND_CYCLES);
+        // lea never touches memory
+        assert_eq!(cycles("lea rax, [rbx+8]"), 1);
+    }
+}
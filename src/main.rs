@@ -1,29 +1,64 @@
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use rustyline::config::{CompletionType, Config};
 use colored::*;
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
+mod analyzer;
 mod cpu;
+mod debugger;
+mod elf;
+mod disassembler;
+mod encodings;
+mod explain;
+mod expr;
+mod grade;
+mod isa;
 mod parser;
 mod assembler;
+mod buffer;
 mod calculator;
+mod checkpoints;
+mod completion;
+mod optimizer;
 mod script_mode;
+mod sessions;
+mod state;
 mod syntax_highlighter;
+mod undo;
 mod user_functions;
 
 use cpu::CPU;
-use parser::{parse_input, parse_instruction, Instruction, InputType};
-use assembler::assemble_instruction;
+use debugger::{Backtrace, Debugger, Executed};
+use isa::Isa;
+use parser::{instruction_lines, parse_complete_instruction, parse_error_message, parse_input, parse_label_definition, parse_program, Instruction, InputType, InstructionType, Register, RegisterSize};
+use assembler::{assemble_instruction, assemble_program};
+use buffer::Buffer;
 use calculator::calculate;
+use checkpoints::Checkpoints;
+use completion::{ReplEditor, ReplHelper};
 //use script_mode::execute_script;
 use syntax_highlighter::highlight_syntax;
 use script_mode::ScriptEnvironment;
+use sessions::Sessions;
+use undo::UndoHistory;
 
 
 
+// REPL toggles that control extra output
+struct DisplaySettings {
+    explain: bool,       // Explain conditional jumps while stepping, and point out no-ops
+    autostate: bool,     // Show the compact CPU state after every executed instruction
+    watches: Vec<&'static str>, // Registers and flags to report whenever a program changes them
+    colors: ColorScheme, // How `state` colors the bits of each register
+    little_endian: bool, // Show register bytes lowest first, in memory order, rather than most significant first
+}
+
 #[derive(Debug, PartialEq)]
 enum ReplMode {
     Single,
-    Multi,
+    Multi { live: bool }, // Live mode also executes each instruction as it's entered
     Calculator,
     Script,
 }
@@ -33,20 +68,83 @@ enum ReplMode {
 //╚═══════════════════════════════════════════════════════════════════╝
 
 fn main() -> rustyline::Result<()> {
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let mut test_mode = false;
+    let mut strict = false;
+    let mut keep_history = true;
+    for flag in &flags {
+        match flag.as_str() {
+            // Deterministic output for snapshot tests: no colors, no highlighting, a fixed prompt
+            "--test-mode" => test_mode = true,
+            // Hardware-faithful behavior only
+            "--strict" => strict = true,
+            // Don't read or write the history file
+            "--no-history" => keep_history = false,
+            _ => {
+                eprintln!("Unknown option: {}", flag);
+                std::process::exit(2);
+            }
+        }
+    }
+    if test_mode {
+        colored::control::set_override(false);
+    }
+
+    if args.first().map(String::as_str) == Some("grade") {
+        std::process::exit(grade_command(&args[1..]));
+    }
+    if args.first().map(String::as_str) == Some("check-encodings") {
+        std::process::exit(check_encodings_command(&args[1..]));
+    }
+
     let mut cpu = CPU::new();
-    let mut rl = DefaultEditor::new()?;
-    let mut code_buffer: Vec<String> = Vec::new();
+    // Tab lists every completion when there's more than one, like a shell
+    let mut rl = ReplEditor::with_config(Config::builder().completion_type(CompletionType::List).build())?;
+    rl.set_helper(Some(ReplHelper));
+    // Test runs shouldn't depend on, or add to, whatever the user typed before
+    let history_file = if keep_history && !test_mode { history_path() } else { None };
+    if let Some(path) = &history_file {
+        match rl.load_history(path) {
+            Ok(()) => {}
+            // Nothing saved yet on the first run
+            Err(ReadlineError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("{} Couldn't read history from {}: {}", "WARNING:".yellow(), path.display(), e),
+        }
+    }
+    let mut code_buffer = Buffer::new();
     let mut repl_mode = ReplMode::Single;
     let mut script_env = ScriptEnvironment::new();
+    // Calculator mode's variables, including `ans`, the last result
+    let mut calc_variables: HashMap<String, i64> = HashMap::new();
     user_functions::load_user_functions(&mut script_env);
+    // Confirmation prompts only make sense when a person is typing
+    let interactive = !test_mode && std::io::stdin().is_terminal();
+    // The CPU as of the last save, used to detect unsaved changes on exit
+    let mut saved_cpu = cpu.clone();
+    let mut checkpoints = Checkpoints::new();
+    let mut undo_history = UndoHistory::new();
+    let mut sessions = Sessions::new();
+    // The program being stepped through in multi-instruction mode, or paused at a breakpoint, if any
+    let mut debugger: Option<Debugger> = None;
+    // Buffered line numbers, counting from 1, where `run` and `continue` pause
+    let mut breakpoints: HashSet<usize> = HashSet::new();
+    // In live multi mode, the CPU as it was before the first buffered instruction ran
+    let mut live_start = cpu.clone();
+    let mut isa = Isa::full();
+    isa.strict = strict;
+    // The CPU as the last `state` showed it, so the next one can highlight what changed
+    let mut last_state: Option<CPU> = None;
+    let mut settings = DisplaySettings { explain: true, autostate: false, watches: Vec::new(), colors: ColorScheme::Gradient, little_endian: false };
 
     println!("{}", "Welcome to the ASMLab Assembly REPL!".green().bold());
     print_help();
 
     loop {
         let prompt = match repl_mode {
+            _ if test_mode => "asmlab> ".to_string(),
             ReplMode::Single => ">> ".cyan().bold().to_string(),
-            ReplMode::Multi => format!("{} ", " MULTI ".on_truecolor(188, 71, 73).truecolor(242, 232, 207).bold()),
+            ReplMode::Multi { live: false } => format!("{} ", " MULTI ".on_truecolor(188, 71, 73).truecolor(242, 232, 207).bold()),
+            ReplMode::Multi { live: true } => format!("{} ", " MULTI-LIVE ".on_truecolor(188, 71, 73).truecolor(242, 232, 207).bold()),
             ReplMode::Calculator => format!("{} ", " CALC ".on_green().white().bold()),
             ReplMode::Script => format!("{} ", " SCRIPT ".on_magenta().white().bold()),
         };
@@ -55,24 +153,212 @@ fn main() -> rustyline::Result<()> {
 
         match readline {
             Ok(line) => {
+                // Blank input is a no-op in every mode: no echo, no error, no spacing line
+                if line.trim().is_empty() {
+                    continue;
+                }
                 rl.add_history_entry(line.as_str())?;
-                let highlighted_input = highlight_syntax(&line);
-                println!("{}", highlighted_input);
+                if test_mode {
+                    println!("{}", line);
+                } else {
+                    println!("{}", highlight_syntax(&line));
+                }
 
                 let trimmed = line.trim();
                 match trimmed {
-                    "exit" => break,
+                    cmd if is_exit_command(cmd) => {
+                        let unsaved = unsaved_work(&cpu, &saved_cpu, code_buffer.lines());
+                        if unsaved.is_empty() || !interactive || confirm_exit(&mut rl, &unsaved) {
+                            break;
+                        }
+                    }
                     "help" => print_help(),
                     "cpu" => display_compact_cpu_state(&cpu),
-                    "state" => display_detailed_cpu_state(&cpu),
+                    "state" => {
+                        display_detailed_cpu_state(&cpu, last_state.as_ref(), &settings);
+                        last_state = Some(cpu.clone());
+                    }
+                    "frame" => display_stack_frame(&cpu),
+                    "checkpoints" => list_checkpoints(&checkpoints),
+                    cmd if cmd.split_whitespace().next() == Some("checkpoint") => {
+                        let name = cmd["checkpoint".len()..].trim();
+                        if name.is_empty() {
+                            println!("{} Usage: checkpoint <name>", "ERROR:".red());
+                            continue;
+                        }
+                        if checkpoints.save(name, &cpu) {
+                            println!("Checkpoint '{}' overwritten.", name);
+                        } else {
+                            println!("Checkpoint '{}' saved.", name);
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("restore") => {
+                        let name = cmd["restore".len()..].trim();
+                        match checkpoints.restore(name) {
+                            Ok(snapshot) => {
+                                cpu = snapshot;
+                                // A program being stepped through belonged to the old CPU
+                                debugger = None;
+                                undo_history.clear(); // Undo only steps back through instructions
+                                println!("Restored checkpoint '{}'.", name);
+                            }
+                            Err(e) => println!("{} {}", "ERROR:".red(), e),
+                        }
+                    }
+                    "sessions" => list_sessions(&sessions, &cpu),
+                    cmd if cmd.split_whitespace().next() == Some("save-state") => {
+                        let path = cmd["save-state".len()..].trim();
+                        if path.is_empty() {
+                            println!("{} Usage: save-state <path>", "ERROR:".red());
+                            continue;
+                        }
+                        match state::save_state(&cpu, path) {
+                            Ok(()) => {
+                                saved_cpu = cpu.clone();
+                                println!("CPU state saved to {}.", path);
+                            }
+                            Err(e) => println!("{} {}", "ERROR:".red(), e),
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("load-state") => {
+                        match state::load_state(cmd["load-state".len()..].trim()) {
+                            Ok(loaded) => {
+                                cpu = loaded;
+                                // What's on disk now matches the CPU, as after a save
+                                saved_cpu = cpu.clone();
+                                // Like restoring a checkpoint, this starts over from a different CPU
+                                debugger = None;
+                                undo_history.clear();
+                                println!("CPU state loaded.");
+                            }
+                            Err(e) => println!("{} {}", "ERROR:".red(), e),
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("load-mem") => {
+                        let before = cpu.clone();
+                        match load_memory_file(&cmd["load-mem".len()..], &mut cpu) {
+                            Ok((size, address)) => {
+                                println!("Loaded {} byte(s) into memory at {:#x}.", size, address);
+                                if repl_mode == ReplMode::Single {
+                                    undo_history.remember(before);
+                                }
+                            }
+                            Err(e) => println!("{} {}", "ERROR:".red(), e),
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("load") => {
+                        let path = cmd["load".len()..].trim();
+                        match repl_mode {
+                            ReplMode::Multi { live } => match read_program_file(path) {
+                                Ok(lines) => {
+                                    debugger = None;
+                                    if live && code_buffer.is_empty() {
+                                        live_start = cpu.clone();
+                                    }
+                                    let mut loaded = 0;
+                                    for (number, line) in lines {
+                                        // Live mode runs each line as if it had been typed, and stops at the first that fails
+                                        if live && !execute_live(&line, &mut cpu, &isa, &settings) {
+                                            println!("{} Stopped loading at {}:{}", "ERROR:".red(), path, number);
+                                            break;
+                                        }
+                                        code_buffer.push(line);
+                                        loaded += 1;
+                                    }
+                                    println!("Loaded {} line(s) from {} into the buffer.", loaded, path);
+                                }
+                                Err(e) => println!("{} {}", "ERROR:".red(), e),
+                            },
+                            ReplMode::Script => run_script_file(path, &mut script_env, &mut cpu),
+                            _ => println!("{} 'load' fills the buffer in multi-instruction mode or runs a script in script mode; use ':source' to run a program now.", "ERROR:".red()),
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some(":source") => {
+                        let path = cmd[":source".len()..].trim();
+                        match repl_mode {
+                            ReplMode::Script => run_script_file(path, &mut script_env, &mut cpu),
+                            ReplMode::Calculator => println!("{} ':source' runs programs and scripts, not calculations.", "ERROR:".red()),
+                            // Runs the file as a program on the current CPU, leaving the buffer alone
+                            _ => match read_program_file(path) {
+                                Ok(lines) => {
+                                    debugger = None;
+                                    let lines: Vec<String> = lines.into_iter().map(|(_, line)| line).collect();
+                                    execute_multi_instructions(&mut cpu, &lines, &isa, &HashSet::new(), &settings);
+                                }
+                                Err(e) => println!("{} {}", "ERROR:".red(), e),
+                            },
+                        }
+                    }
+                    "undo" => {
+                        if repl_mode != ReplMode::Single {
+                            println!("{} 'undo' is only available in single-instruction mode.", "ERROR:".red());
+                        } else if let Some(previous) = undo_history.undo() {
+                            print_state_changes(&cpu, &previous);
+                            cpu = previous;
+                            println!("Undid the last change; {} more can be undone.", undo_history.len());
+                        } else {
+                            println!("{} Nothing to undo", "ERROR:".red());
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("clone") => {
+                        let name = cmd["clone".len()..].trim();
+                        match sessions.clone_session(name, &cpu) {
+                            Ok(()) => println!("Cloned session '{}' into '{}'. Use `switch {}` to move to it.", sessions.current(), name, name),
+                            Err(e) => println!("{} {}", "ERROR:".red(), e),
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("switch") => {
+                        let name = cmd["switch".len()..].trim();
+                        match sessions.switch(name, &mut cpu) {
+                            Ok(()) => {
+                                // A stepping session and the undo history belong to the CPU they were made on
+                                debugger = None;
+                                undo_history.clear();
+                                println!("Switched to session '{}'.", name);
+                            }
+                            Err(e) => println!("{} {}", "ERROR:".red(), e),
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("find-instr") => find_instructions(&cmd["find-instr".len()..]),
+                    cmd if cmd.split_whitespace().next() == Some("xmm") => display_xmm_register(&cmd["xmm".len()..], &cpu, &settings),
+                    cmd if cmd.split_whitespace().next() == Some("disasm") => disassemble_bytes(&cmd["disasm".len()..]),
+                    cmd if cmd.split_whitespace().next() == Some("explain") => explain_instruction(&cmd["explain".len()..]),
+                    cmd if cmd.split_whitespace().next() == Some("signedness") => compare_signedness(&cmd["signedness".len()..]),
+                    cmd if cmd.split_whitespace().next() == Some("state") => handle_state_command(&cmd["state".len()..], &cpu),
+                    cmd if cmd.split_whitespace().next() == Some("grade") => {
+                        let args: Vec<String> = cmd.split_whitespace().skip(1).map(String::from).collect();
+                        grade_command(&args);
+                    }
+                    cmd if cmd.split_whitespace().next() == Some(":explain") => {
+                        toggle_setting(&mut settings.explain, &cmd[":explain".len()..], "Explanations");
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("endian") => select_byte_order(&cmd["endian".len()..], &mut settings),
+                    cmd if cmd.split_whitespace().next() == Some("colors") => select_color_scheme(&cmd["colors".len()..], &mut settings),
+                    cmd if cmd.split_whitespace().next() == Some(":autostate") => {
+                        toggle_setting(&mut settings.autostate, &cmd[":autostate".len()..], "Automatic state display");
+                    }
+                    ":isa" => print_isa(&isa),
+                    cmd if cmd.split_whitespace().next() == Some(":isa") => match Isa::from_spec(&cmd[":isa".len()..]) {
+                        Ok(level) => {
+                            isa = level;
+                            isa.strict = strict;
+                            debugger = None;
+                            print_isa(&isa);
+                        }
+                        Err(e) => println!("{} {}", "ERROR:".red(), e),
+                    },
                     ":single" => {
                         repl_mode = ReplMode::Single;
                         println!("Switched to single-instruction mode.");
                     }
                     ":multi" => {
-                        repl_mode = ReplMode::Multi;
+                        repl_mode = ReplMode::Multi { live: false };
                         println!("Switched to multiple-instruction mode.");
                     }
+                    ":multi-live" => {
+                        repl_mode = ReplMode::Multi { live: true };
+                        println!("Switched to live multiple-instruction mode: instructions run as they're entered, and 'run' replays them.");
+                    }
                     ":calc" => {
                         repl_mode = ReplMode::Calculator;
                         println!("Switched to calculator mode.");
@@ -82,25 +368,162 @@ fn main() -> rustyline::Result<()> {
                         println!("Switched to script mode.");
                     }
                     "run" => {
-                        if repl_mode == ReplMode::Multi {
-                            execute_multi_instructions(&mut cpu, &code_buffer);
-                            code_buffer.clear();
+                        if let ReplMode::Multi { live } = repl_mode {
+                            if live {
+                                // Replay from the state the buffer started from, not on top of the live run
+                                cpu = live_start.clone();
+                            }
+                            debugger = execute_multi_instructions(&mut cpu, code_buffer.lines(), &isa, &breakpoints, &settings);
+                            // A paused program keeps its buffer so it can be continued
+                            if debugger.is_none() {
+                                code_buffer.clear();
+                            }
                         } else {
                             println!("{} 'run' is only available in multi-instruction mode.", "ERROR:".red());
                         }
                     }
+                    "continue" => match debugger.take() {
+                        Some(session) => {
+                            debugger = continue_program(session, &mut cpu, &breakpoints, &settings);
+                            if debugger.is_none() {
+                                code_buffer.clear();
+                            }
+                        }
+                        None => println!("{} No program is paused. Use 'run' to start one.", "ERROR:".red()),
+                    },
+                    "break" => list_breakpoints(&breakpoints, code_buffer.lines()),
+                    cmd if matches!(cmd.split_whitespace().next(), Some("break" | "unbreak")) => {
+                        let (command, line) = cmd.split_once(' ').unwrap_or((cmd, ""));
+                        match line.trim().parse::<usize>() {
+                            Ok(line) if line > 0 && command == "break" => {
+                                breakpoints.insert(line);
+                                println!("Breakpoint set on line {}.", line);
+                            }
+                            Ok(line) if line > 0 => {
+                                if breakpoints.remove(&line) {
+                                    println!("Breakpoint on line {} removed.", line);
+                                } else {
+                                    println!("{} No breakpoint on line {}", "ERROR:".red(), line);
+                                }
+                            }
+                            _ => println!("{} Usage: {} <line>, counting buffered lines from 1", "ERROR:".red(), command),
+                        }
+                    }
+                    cmd if matches!(cmd.split_whitespace().next(), Some("watch" | "unwatch")) => {
+                        if let Err(e) = edit_watches(cmd, &mut settings.watches) {
+                            println!("{} {}", "ERROR:".red(), e);
+                        }
+                    }
+                    "backtrace" | "bt" => match &debugger {
+                        Some(session) => print_backtrace(session, &cpu),
+                        None => println!("{} No program is being stepped through. Use 'step' or 'stepinto' to start one.", "ERROR:".red()),
+                    },
+                    "reset-pc" => {
+                        if matches!(repl_mode, ReplMode::Multi { .. }) {
+                            // Dropping the session makes the next step start over; the registers are kept
+                            debugger = None;
+                            cpu.rip = 0;
+                            println!("Program counter reset; the next step starts from instruction 0.");
+                        } else {
+                            println!("{} 'reset-pc' is only available in multi-instruction mode.", "ERROR:".red());
+                        }
+                    }
+                    "step" | "stepinto" | "stepover" | "stepout" => {
+                        if matches!(repl_mode, ReplMode::Multi { .. }) {
+                            debug_step(trimmed, &mut debugger, &mut cpu, code_buffer.lines(), &isa, &settings);
+                        } else {
+                            println!("{} '{}' is only available in multi-instruction mode.", "ERROR:".red(), trimmed);
+                        }
+                    }
+                    "analyze" => {
+                        if matches!(repl_mode, ReplMode::Multi { .. }) {
+                            analyze_program(code_buffer.lines());
+                        } else {
+                            println!("{} 'analyze' is only available in multi-instruction mode.", "ERROR:".red());
+                        }
+                    }
+                    "optimize" => {
+                        if matches!(repl_mode, ReplMode::Multi { .. }) {
+                            optimize_program(code_buffer.lines());
+                        } else {
+                            println!("{} 'optimize' is only available in multi-instruction mode.", "ERROR:".red());
+                        }
+                    }
+                    cmd if matches!(cmd.split_whitespace().next(), Some("assemble-out" | "export-elf")) => {
+                        let (command, path) = cmd.split_once(' ').unwrap_or((cmd, ""));
+                        if matches!(repl_mode, ReplMode::Multi { .. }) {
+                            write_machine_code(command, path.trim(), code_buffer.lines());
+                        } else {
+                            println!("{} '{}' is only available in multi-instruction mode.", "ERROR:".red(), command);
+                        }
+                    }
+                    "list" => {
+                        if matches!(repl_mode, ReplMode::Multi { .. }) {
+                            list_buffer(code_buffer.lines(), &breakpoints);
+                        } else {
+                            println!("{} 'list' is only available in multi-instruction mode.", "ERROR:".red());
+                        }
+                    }
+                    cmd if matches!(cmd.split_whitespace().next(), Some("del" | "ins")) => {
+                        if let ReplMode::Multi { live } = repl_mode {
+                            match edit_buffer(cmd, &mut code_buffer, &mut breakpoints) {
+                                Ok(()) => {
+                                    // Editing the buffer invalidates any program being stepped through
+                                    debugger = None;
+                                    if live {
+                                        println!("The CPU still shows the lines as they ran; 'run' replays the edited buffer.");
+                                    }
+                                }
+                                Err(e) => println!("{} {}", "ERROR:".red(), e),
+                            }
+                        } else {
+                            println!("{} '{}' is only available in multi-instruction mode.", "ERROR:".red(), cmd.split_whitespace().next().unwrap_or(cmd));
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some("set") && matches!(repl_mode, ReplMode::Single | ReplMode::Multi { .. }) => {
+                        let before = cpu.clone();
+                        match parser::parse_memory_write(cmd).and_then(|write| set_memory(&mut cpu, &write)) {
+                            // Like an instruction, a write in single-instruction mode can be undone
+                            Ok(()) if repl_mode == ReplMode::Single => undo_history.remember(before),
+                            Ok(()) => {}
+                            Err(e) => println!("{} {}", "ERROR:".red(), e),
+                        }
+                    }
+                    // A comment on its own is a no-op when entering instructions
+                    input if matches!(repl_mode, ReplMode::Single | ReplMode::Multi { .. }) && parser::strip_comment(input).trim().is_empty() => {}
                     input => {
                         match repl_mode {
-                            ReplMode::Single => handle_single_instruction(input, &mut cpu),
-                            ReplMode::Multi => code_buffer.push(input.to_string()),
+                            ReplMode::Single => {
+                                let before = cpu.clone();
+                                if handle_single_instruction(input, &mut cpu, &isa, &settings) {
+                                    undo_history.remember(before);
+                                    if settings.autostate {
+                                        display_compact_cpu_state(&cpu);
+                                    }
+                                }
+                            }
+                            ReplMode::Multi { live } => {
+                                // Editing the buffer invalidates any program being stepped through
+                                debugger = None;
+                                if live {
+                                    if code_buffer.is_empty() {
+                                        live_start = cpu.clone();
+                                    }
+                                    if execute_live(input, &mut cpu, &isa, &settings) {
+                                        code_buffer.push(input.to_string());
+                                    }
+                                } else {
+                                    code_buffer.push(input.to_string());
+                                }
+                            }
                             ReplMode::Calculator => {
-                                match calculate(input, &cpu) {
+                                match calculate(input, &cpu, &mut calc_variables) {
                                     Ok(result) => println!("{}", result),
                                     Err(e) => println!("{} {}", "Calculation error:".red(), e),
                                 }
                             }
                             ReplMode::Script => {
-                                match script_env.execute_script(input, &cpu) {
+                                match script_env.execute_script(input, &mut cpu) {
                                     Ok(result) => println!("{}", result),
                                     Err(e) => println!("{} {}", "Script error:".red(), e),
                                 }
@@ -125,21 +548,122 @@ fn main() -> rustyline::Result<()> {
         println!(); // Add extra newline for spacing
     }
 
+    if let Some(path) = &history_file {
+        if let Err(e) = rl.save_history(path) {
+            eprintln!("{} Couldn't save history to {}: {}", "WARNING:".yellow(), path.display(), e);
+        }
+    }
     println!("{}", "Goodbye!".green());
     Ok(())
 }
 
+fn is_exit_command(input: &str) -> bool {
+    matches!(input, "exit" | "quit" | "q")
+}
+
+fn unsaved_work(cpu: &CPU, saved_cpu: &CPU, code_buffer: &[String]) -> Vec<String> {
+    let mut unsaved = Vec::new();
+    if !code_buffer.is_empty() {
+        unsaved.push(format!("{} buffered instruction(s) that haven't been run", code_buffer.len()));
+    }
+    if cpu != saved_cpu {
+        unsaved.push("CPU state changes that haven't been saved".to_string());
+    }
+    unsaved
+}
+
+// `~/.asmlab_history`, if there's a home directory to put it in
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".asmlab_history"))
+}
+
+fn confirm_exit(rl: &mut ReplEditor, unsaved: &[String]) -> bool {
+    println!("{}", "You have unsaved work:".yellow().bold());
+    for item in unsaved {
+        println!("  - {}", item);
+    }
+    match rl.readline("Exit anyway? [y/N] ") {
+        Ok(answer) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        // Ctrl-C / Ctrl-D at the prompt mean "just leave"
+        Err(_) => true,
+    }
+}
+
+fn list_checkpoints(checkpoints: &Checkpoints) {
+    if checkpoints.is_empty() {
+        println!("No checkpoints saved. Use `checkpoint <name>` to create one.");
+        return;
+    }
+    println!("{}", "Checkpoints:".yellow().bold());
+    for (name, snapshot) in checkpoints.iter() {
+        println!("  {:<16} rip {:#x}  rax {:#x}  rsp {:#x}", name.cyan(), snapshot.rip, snapshot.rax, snapshot.rsp);
+    }
+}
+
+fn list_sessions(sessions: &Sessions, cpu: &CPU) {
+    println!("{}", "Sessions:".yellow().bold());
+    for (name, snapshot, live) in sessions.list(cpu) {
+        let marker = if live { "*" } else { " " };
+        println!("{} {:<16} rip {:#x}  rax {:#x}  rsp {:#x}", marker, name.cyan(), snapshot.rip, snapshot.rax, snapshot.rsp);
+    }
+}
+
 fn print_help() {
     println!("\n{}", "Available commands:".yellow().bold());
-    println!("  {} - Exit the REPL", "exit".italic());
+    println!("  {} - Exit the REPL (aliases: quit, q)", "exit".italic());
     println!("  {} - Display this help message", "help".italic());
     println!("  {} - Display compact CPU state", "cpu".italic());
-    println!("  {} - Display detailed CPU state", "state".italic());
+    println!("  {} - Display detailed CPU state, highlighting bits changed since the last one", "state".italic());
+    println!("  {} - Group registers by calling-convention role (sysv or win64)", "state --role [abi]".italic());
+    println!("  {} - Compare two values as signed and unsigned, with the jumps each implies", "signedness <a> <b>".italic());
+    println!("  {} - Display the stack frame relative to rbp", "frame".italic());
+    println!("  {} - Show an XMM register as bits and as f32, f64, i32 and i64 lanes", "xmm <n>".italic());
+    println!("  {} - Describe an instruction and any idiom it uses", "explain <instr>".italic());
+    println!("  {} - List instructions whose name or description mentions a keyword", "find-instr <keyword>".italic());
+    println!("  {} - Decode machine code given as hex bytes, e.g. disasm 48 89 d8", "disasm <bytes>".italic());
+    println!("  {} - Save the CPU state under a name", "checkpoint <name>".italic());
+    println!("  {} - Return to a named checkpoint", "restore <name>".italic());
+    println!("  {} - List saved checkpoints", "checkpoints".italic());
+    println!("  {} - Take back the last instruction or memory write in single-instruction mode", "undo".italic());
+    println!("  {} - Write a value to memory, little-endian, e.g. set 0x100 qword 0xdeadbeef", "set <addr> <width> <value>".italic());
+    println!("  {} - Save the registers, flags and memory to a JSON file", "save-state <path>".italic());
+    println!("  {} - Replace the CPU state with one saved by save-state", "load-state <path>".italic());
+    println!("  {} - Fork the CPU state into a new named session", "clone <name>".italic());
+    println!("  {} - Move to another session, keeping this one as it is", "switch <name>".italic());
+    println!("  {} - List sessions; the current one is marked with *", "sessions".italic());
+    println!("  {} - Run a program file and check its final state against a spec file", "grade <program> <spec>".italic());
+    println!("  {} - Add a file's lines to the buffer (multi mode), or run it as a script (script mode)", "load <path>".italic());
+    println!("  {} - Copy a file's bytes into memory at an address", "load-mem <path> <addr>".italic());
+    println!("  {} - Run a program file now, or a script file in script mode", ":source <path>".italic());
+    println!("  {} - Show or restrict the allowed instructions (intro, basic, procedures, full, or a list)", ":isa [level|list]".italic());
     println!("  {} - Switch to single-instruction mode", ":single".italic());
     println!("  {} - Switch to multiple-instruction mode", ":multi".italic());
+    println!("  {} - Multiple-instruction mode that also runs each instruction as it's entered", ":multi-live".italic());
     println!("  {} - Switch to calculator mode", ":calc".italic());
     println!("  {} - Switch to script mode", ":script".italic());
     println!("  {} - Execute instructions in multi-instruction mode", "run".italic());
+    println!("  {} - Show the buffered lines with their numbers; breakpoints are marked with *", "list".italic());
+    println!("  {} - Assemble the buffer and write the raw machine code to a file", "assemble-out <path>".italic());
+    println!("  {} - Assemble the buffer into a Linux x86-64 executable", "export-elf <path>".italic());
+    println!("  {} - Remove a buffered line", "del <line>".italic());
+    println!("  {} - Insert an instruction before a buffered line", "ins <line> <instr>".italic());
+    println!("  {} - Pause 'run' before a buffered line; 'break' alone lists them", "break <line>".italic());
+    println!("  {} - Remove a breakpoint", "unbreak <line>".italic());
+    println!("  {} - Resume a program paused at a breakpoint", "continue".italic());
+    println!("  {} - Report each change to a register or flag during 'run' and stepping; 'watch' alone lists them", "watch <reg|flag>".italic());
+    println!("  {} - Remove a watch", "unwatch <reg|flag>".italic());
+    println!("  {} - Execute one buffered instruction and show the registers and flags it changed", "step".italic());
+    println!("  {} - Go back to the first buffered instruction for the next step", "reset-pc".italic());
+    println!("  {} - Execute one buffered instruction, entering calls", "stepinto".italic());
+    println!("  {} - Execute one buffered instruction, running calls to completion", "stepover".italic());
+    println!("  {} - Run until the current subroutine returns", "stepout".italic());
+    println!("  {} - Show the active calls while stepping (alias: bt)", "backtrace".italic());
+    println!("  {} - Toggle explanations of conditional jumps while stepping and notes on no-op instructions", ":explain [on|off]".italic());
+    println!("  {} - Toggle showing the CPU state after every instruction", ":autostate [on|off]".italic());
+    println!("  {} - Show register bytes lowest first (le, memory order) or most significant first (be)", "endian [le|be]".italic());
+    println!("  {} - Pick the register colors: gradient, mono, colorblind or blue; 'colors' alone lists them", "colors [scheme]".italic());
+    println!("  {} - Report unreachable instructions in multi-instruction mode", "analyze".italic());
+    println!("  {} - Suggest peephole improvements in multi-instruction mode", "optimize".italic());
     println!();
 }
 
@@ -147,46 +671,604 @@ fn print_help() {
 //║   ⇩ Instruction Processing                                        ║  
 //╚═══════════════════════════════════════════════════════════════════╝
 
-fn handle_single_instruction(input: &str, cpu: &mut CPU) {
+// `set 0x100 dword 5`: the value must fit the width, as a signed or an unsigned number
+fn set_memory(cpu: &mut CPU, write: &parser::MemoryWrite) -> Result<(), String> {
+    let bits = write.size.bits();
+    let fits = bits == 64 || (write.value as u64) >> bits == 0 || write.value >> (bits - 1) == -1;
+    if !fits {
+        return Err(format!("{:#x} doesn't fit in a {}", write.value, write.size.keyword()));
+    }
+    let size = bits as usize / 8;
+    cpu.write_memory_sized(write.address, write.value as u64, size).map_err(|e| e.to_string())?;
+    let bytes = (write.value as u64).to_le_bytes();
+    println!("Wrote {} at {:#x}: {}", write.size.keyword(), write.address, encodings::format_bytes(&bytes[..size]));
+    Ok(())
+}
+
+// Returns whether an instruction was executed
+fn handle_single_instruction(input: &str, cpu: &mut CPU, isa: &Isa, settings: &DisplaySettings) -> bool {
+    if let Some(name) = parse_label_definition(input) {
+        println!("{} Label '{}' can only be defined in a program (see :multi)", "ERROR:".red(), name);
+        return false;
+    }
     match parse_input(input) {
-        Ok((_, InputType::Instruction(instruction))) => {
-            process_instruction(&instruction, cpu);
-        }
+        Ok((_, InputType::Instruction(instruction))) => match isa.check(&instruction.instruction_type) {
+            Ok(()) => return process_instruction(&instruction, cpu, settings),
+            Err(e) => println!("{} {}", "ERROR:".red(), e),
+        },
         Ok((_, InputType::Register(register, options))) => {
             let formatted_value = cpu.format_register_value(&register, &options);
             println!("{}", formatted_value);
         }
         Ok((_, InputType::Memory(options))) => {
-            cpu.dump_memory(&options);
+            print!("{}", cpu.dump_memory(&options));
         }
-        Err(e) => println!("{} {}", "Error parsing input:".red(), e),
+        Err(e) => println!("{} {}", "Error parsing input:".red(), parse_error_message(input, &e)),
+    }
+    false
+}
+
+// Runs one line entered in live multi mode; returns whether it belongs in the buffer
+fn execute_live(input: &str, cpu: &mut CPU, isa: &Isa, settings: &DisplaySettings) -> bool {
+    if parse_label_definition(input).is_some() {
+        return true;
+    }
+    match parse_input(input) {
+        Ok((_, InputType::Instruction(instruction))) => {
+            if let Err(e) = isa.check(&instruction.instruction_type) {
+                println!("{} {}", "ERROR:".red(), e);
+                return false;
+            }
+            // Branch targets are instruction indexes in the buffer, so they only mean something in `run`
+            if instruction.instruction_type.is_branch() || instruction.instruction_type == InstructionType::Ret {
+                println!("Recorded; {} takes effect when the buffer is replayed with 'run'.", instruction.instruction_type);
+                return true;
+            }
+            let before = cpu.clone();
+            let executed = process_instruction(&instruction, cpu, settings);
+            if executed {
+                print_state_changes(&before, cpu);
+            }
+            executed
+        }
+        // Register and memory queries are answered but not recorded
+        _ => {
+            handle_single_instruction(input, cpu, isa, settings);
+            false
+        }
+    }
+}
+
+fn print_state_changes(before: &CPU, after: &CPU) {
+    let changes: Vec<String> = cpu::diff_registers(before, after).iter().map(ToString::to_string).collect();
+    if changes.is_empty() {
+        println!("{}", "No register or flag changes.".dimmed());
+    } else {
+        println!("{} {}", "Changed:".yellow(), changes.join(", "));
+    }
+}
+
+// Shows what the program wrote with the write syscall since the last call
+fn print_program_output(cpu: &mut CPU) {
+    if cpu.output.is_empty() {
+        return;
+    }
+    let output = std::mem::take(&mut cpu.output);
+    println!("{}", "Program output:".blue());
+    println!("{}", output.strip_suffix('\n').unwrap_or(&output));
+}
+
+// Loads the buffer and runs it; returns the program if it paused at a breakpoint
+fn execute_multi_instructions(cpu: &mut CPU, instructions: &[String], isa: &Isa, breakpoints: &HashSet<usize>, settings: &DisplaySettings) -> Option<Debugger> {
+    let program = match Debugger::load(instructions, isa, cpu) {
+        Ok(program) => program,
+        Err(e) => {
+            println!("{} {}", "ERROR:".red(), e);
+            return None;
+        }
+    };
+    // A breakpoint on the first instruction stops before anything runs
+    if let Some(line) = program.breakpoint_at(cpu, breakpoints) {
+        print_breakpoint_hit(&program, line);
+        return Some(program);
+    }
+    continue_program(program, cpu, breakpoints, settings)
+}
+
+// Runs a loaded program to the next breakpoint or the end; returns it if it paused at a breakpoint
+fn continue_program(mut program: Debugger, cpu: &mut CPU, breakpoints: &HashSet<usize>, settings: &DisplaySettings) -> Option<Debugger> {
+    let mut fault = None;
+    let executed = program.run_to_breakpoint(cpu, breakpoints);
+    let cycles: u32 = executed.iter().map(|step| isa::estimated_cycles(&step.instruction)).sum();
+    let count = executed.len();
+    for Executed { index, fault: step_fault, changes, .. } in executed {
+        println!("Executing: {}", program.source[index]);
+        println!("{} {:?}", "Assembled bytes:".blue(), program.bytes[index]);
+        match step_fault {
+            Some(e) => fault = Some((index, e)),
+            None => println!("{}", "Instruction executed.".green()),
+        }
+        print_watch_hits(&changes, index, &program.source[index], &settings.watches);
+    }
+    print_program_output(cpu);
+    if count > 0 {
+        println!("{} {} instruction(s), about {} cycles", "Executed".dimmed(), count, cycles);
+    }
+
+    if let Some((index, e)) = fault {
+        println!("{} Program stopped at instruction {}: {}", "ERROR:".red(), index, e);
+    } else if let Some(status) = cpu.exit_status.take() {
+        println!("{}", format!("Program exited with status {}.", status).green());
+    } else if let Some(line) = program.breakpoint_at(cpu, breakpoints) {
+        print_breakpoint_hit(&program, line);
+        return Some(program);
+    } else if program.is_finished(cpu) {
+        println!("{}", "All instructions executed successfully.".green());
+    } else {
+        println!("{} Stopped after {} steps; the program may be stuck in a loop.", "WARNING:".yellow(), debugger::MAX_STEPS);
+    }
+    None
+}
+
+fn print_watch_hits(changes: &[cpu::Change], index: usize, instruction: &str, watches: &[&str]) {
+    for change in changes.iter().filter(|change| watches.contains(&change.name)) {
+        println!("{} {} at {}: {}", "Watch:".magenta(), change, index, instruction);
+    }
+}
+
+// `watch` lists the watches, `watch rax` and `watch zf` add one, `unwatch rax` removes one
+fn edit_watches(cmd: &str, watches: &mut Vec<&'static str>) -> Result<(), String> {
+    let (command, name) = cmd.split_once(' ').unwrap_or((cmd, ""));
+    let name = name.trim();
+    if command == "watch" && name.is_empty() {
+        if watches.is_empty() {
+            println!("No watches set. Use `watch <register or flag>` to add one.");
+        } else {
+            println!("{} {}", "Watching:".yellow().bold(), watches.join(", "));
+        }
+        return Ok(());
+    }
+    let watched = Register::ALL.iter()
+        .filter_map(|reg| reg.name(RegisterSize::Qword))
+        .chain(cpu::FLAG_NAMES)
+        .find(|watchable| watchable.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("Can't watch '{}': use a 64-bit register like rax or a flag like zf", name))?;
+
+    if command == "watch" {
+        if watches.contains(&watched) {
+            return Err(format!("Already watching {}", watched));
+        }
+        watches.push(watched);
+        println!("Watching {}; 'run' and the step commands will report each change.", watched);
+    } else {
+        let position = watches.iter().position(|&w| w == watched).ok_or_else(|| format!("Not watching {}", watched))?;
+        watches.remove(position);
+        println!("Stopped watching {}.", watched);
+    }
+    Ok(())
+}
+
+fn print_breakpoint_hit(program: &Debugger, line: usize) {
+    let index = program.line_numbers.iter().position(|&l| l == line).unwrap_or_default();
+    println!("{} line {}: {}", "Breakpoint hit before".yellow(), line, program.source[index]);
+    println!("Type 'continue' to resume, or step through from here.");
+}
+
+fn list_breakpoints(breakpoints: &HashSet<usize>, code_buffer: &[String]) {
+    if breakpoints.is_empty() {
+        println!("No breakpoints set. Use `break <line>` to add one.");
+        return;
+    }
+    let mut lines: Vec<_> = breakpoints.iter().collect();
+    lines.sort();
+    println!("{}", "Breakpoints:".yellow().bold());
+    for &line in lines {
+        println!("  line {:>3}: {}", line, code_buffer.get(line - 1).map_or("(past the end of the buffer)", String::as_str));
+    }
+}
+
+// `assemble-out` writes the raw bytes, `export-elf` a Linux executable around them
+fn write_machine_code(command: &str, path: &str, code_buffer: &[String]) {
+    if path.is_empty() {
+        println!("{} Usage: {} <path>", "ERROR:".red(), command);
+        return;
+    }
+    let result = assemble_program(code_buffer).and_then(|code| {
+        if command == "export-elf" {
+            elf::write_executable(path, &code)?;
+        } else {
+            std::fs::write(path, &code).map_err(|e| format!("Can't write {}: {}", path, e))?;
+        }
+        Ok(code.len())
+    });
+    match result {
+        Ok(size) if command == "export-elf" => println!("Wrote an executable with {} bytes of machine code to {}.", size, path),
+        Ok(size) => println!("Wrote {} bytes of machine code to {}.", size, path),
+        Err(e) => println!("{} {}", "ERROR:".red(), e),
     }
 }
 
-fn execute_multi_instructions(cpu: &mut CPU, instructions: &[String]) {
-    for (i, instruction_str) in instructions.iter().enumerate() {
-        match parse_instruction(instruction_str) {
-            Ok((_, instruction)) => {
-                println!("Executing: {}", instruction_str);
-                process_instruction(&instruction, cpu);
+fn list_buffer(code_buffer: &[String], breakpoints: &HashSet<usize>) {
+    if code_buffer.is_empty() {
+        println!("The buffer is empty.");
+        return;
+    }
+    for (i, line) in code_buffer.iter().enumerate() {
+        let marker = if breakpoints.contains(&(i + 1)) { "*".red().to_string() } else { " ".to_string() };
+        println!("{}{:>3}: {}", marker, i + 1, line);
+    }
+}
+
+// `del N` and `ins N <instr>`. Breakpoints stay on the lines they were set on, so the ones below
+// an edit move with it
+fn edit_buffer(cmd: &str, code_buffer: &mut Buffer, breakpoints: &mut HashSet<usize>) -> Result<(), String> {
+    let (command, args) = cmd.split_once(' ').unwrap_or((cmd, ""));
+    let (number, instruction) = args.trim().split_once(char::is_whitespace).unwrap_or((args.trim(), ""));
+    let number = number.parse::<usize>().map_err(|_| match command {
+        "del" => "Usage: del <line>, counting buffered lines from 1".to_string(),
+        _ => "Usage: ins <line> <instruction>, counting buffered lines from 1".to_string(),
+    })?;
+
+    if command == "del" {
+        let removed = code_buffer.delete(number)?;
+        breakpoints.remove(&number);
+        *breakpoints = breakpoints.iter().map(|&line| if line > number { line - 1 } else { line }).collect();
+        println!("Deleted line {}: {}", number, removed);
+    } else {
+        let instruction = instruction.trim();
+        if instruction.is_empty() {
+            return Err("Usage: ins <line> <instruction>, counting buffered lines from 1".to_string());
+        }
+        code_buffer.insert(number, instruction.to_string())?;
+        *breakpoints = breakpoints.iter().map(|&line| if line >= number { line + 1 } else { line }).collect();
+        println!("Inserted line {}: {}", number, instruction);
+    }
+    Ok(())
+}
+
+fn debug_step(command: &str, session: &mut Option<Debugger>, cpu: &mut CPU, code_buffer: &[String], isa: &Isa, settings: &DisplaySettings) {
+    // Start a new session from the buffer when there's none in progress
+    if session.as_ref().is_none_or(|debugger| debugger.is_finished(cpu)) {
+        match Debugger::load(code_buffer, isa, cpu) {
+            Ok(debugger) => *session = Some(debugger),
+            Err(e) => {
+                println!("{} {}", "ERROR:".red(), e);
+                return;
             }
+        }
+    }
+    let Some(debugger) = session.as_mut() else { return };
+
+    // `step` is `stepinto` that also lists what changed
+    let before = (command == "step").then(|| cpu.clone());
+    let executed = match command {
+        "step" | "stepinto" => debugger.step(cpu).into_iter().collect(),
+        "stepover" => debugger.step_over(cpu),
+        _ => match debugger.step_out(cpu) {
+            Ok(executed) => executed,
             Err(e) => {
-                println!("{} Error in instruction {}: {}", "ERROR:".red(), i + 1, e);
+                println!("{} {}", "ERROR:".red(), e);
                 return;
             }
+        },
+    };
+
+    for Executed { index, instruction, fault, changes } in &executed {
+        match fault {
+            Some(e) => println!("{} {:>3}: {}: {}", "Faulted".red(), index, instruction, e),
+            None => println!("{} {:>3}: {}", "Executed".green(), index, instruction),
+        }
+        print_watch_hits(changes, *index, &instruction.to_string(), &settings.watches);
+    }
+    // Only the last instruction's effects are still visible in the registers and flags
+    if let Some(Executed { instruction, fault: None, .. }) = executed.last() {
+        if instruction.instruction_type == InstructionType::Ret {
+            display_return_value(cpu, CallingConvention::SystemV);
+        }
+        if let Some(explanation) = explain::condition_explanation(instruction, cpu).filter(|_| settings.explain) {
+            println!("  {}", explanation.italic());
+        }
+    }
+    if let Some(before) = before.as_ref().filter(|_| !executed.is_empty()) {
+        print_state_changes(before, cpu);
+    }
+    print_program_output(cpu);
+    if settings.autostate && !executed.is_empty() {
+        display_compact_cpu_state(cpu);
+    }
+
+    match debugger.current_index(cpu) {
+        Some(next) => println!("{} {:>3}: {}  (call depth {})",
+            "Next".cyan(), next, debugger.program[next], debugger.call_depth()),
+        None => {
+            match cpu.exit_status.take() {
+                Some(status) => println!("{}", format!("Program exited with status {}.", status).green()),
+                None => println!("{}", "Program finished.".green()),
+            }
+            *session = None;
+        }
+    }
+}
+
+// Returns the process exit code: 0 if every assertion passed, 1 if any failed, 2 if grading couldn't run
+fn grade_command(args: &[String]) -> i32 {
+    let [program, spec] = args else {
+        println!("{} Usage: grade <program.asm> <spec.txt>", "ERROR:".red());
+        return 2;
+    };
+
+    let report = match grade::grade(program, spec) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{} {}", "ERROR:".red(), e);
+            return 2;
+        }
+    };
+
+    for outcome in &report.outcomes {
+        let status = if outcome.passed { "PASS".green() } else { "FAIL".red() };
+        let detail = match &outcome.result {
+            Ok(_) if outcome.passed => String::new(),
+            Ok((left, right)) => format!("  (left = {:#x}, right = {:#x})", left, right),
+            Err(e) => format!("  ({})", e),
+        };
+        println!("{} line {:>3}: {}{}", status, outcome.line, outcome.assertion, detail.dimmed());
+    }
+
+    let passed = report.outcomes.iter().filter(|outcome| outcome.passed).count();
+    println!("{}/{} assertions passed", passed, report.outcomes.len());
+    if report.passed() { 0 } else { 1 }
+}
+
+// Same exit codes as `grade`: 0 if every encoding matches, 1 if any differs, 2 if the check couldn't run
+fn check_encodings_command(args: &[String]) -> i32 {
+    let [corpus] = args else {
+        println!("{} Usage: check-encodings <corpus.txt>", "ERROR:".red());
+        return 2;
+    };
+
+    let report = match encodings::check(corpus) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("{} {}", "ERROR:".red(), e);
+            return 2;
+        }
+    };
+
+    for mismatch in &report.mismatches {
+        let actual = match &mismatch.actual {
+            Ok(bytes) => encodings::format_bytes(bytes),
+            Err(e) => e.clone(),
+        };
+        println!("{} line {:>3}: {}", "FAIL".red(), mismatch.line, mismatch.instruction);
+        println!("    expected {}", encodings::format_bytes(&mismatch.expected));
+        println!("    got      {}", actual);
+    }
+    println!("{}/{} encodings match", report.checked - report.mismatches.len(), report.checked);
+    if report.mismatches.is_empty() { 0 } else { 1 }
+}
+
+// `on`/`off` set the toggle explicitly; no argument flips it
+fn toggle_setting(setting: &mut bool, arg: &str, name: &str) {
+    match arg.trim() {
+        "on" => *setting = true,
+        "off" => *setting = false,
+        "" => *setting = !*setting,
+        other => {
+            println!("{} Expected 'on' or 'off', got '{}'", "ERROR:".red(), other);
+            return;
+        }
+    }
+    println!("{} {}.", name, if *setting { "on" } else { "off" });
+}
+
+fn print_isa(isa: &Isa) {
+    println!("{} {}{}", "Instruction level:".bold(), isa.name, if isa.strict { " (strict)" } else { "" });
+    println!("  Allowed: {}", isa::join(&isa.allowed()));
+    let levels: Vec<&str> = isa::LEVELS.iter().map(|(name, _)| *name).collect();
+    println!("  {} {}, full, or a list such as ':isa mov,add,sub'", "Levels:".dimmed(), levels.join(", "));
+}
+
+fn find_instructions(keyword: &str) {
+    if keyword.trim().is_empty() {
+        println!("{} Usage: find-instr <keyword>", "ERROR:".red());
+        return;
+    }
+    let matches = isa::search(keyword);
+    if matches.is_empty() {
+        println!("No instructions mention '{}'.", keyword.trim());
+        return;
+    }
+    for info in matches {
+        println!("  {:<8} {:<18} {}", info.instruction_type.to_string().cyan(), info.category.dimmed(), info.description);
+    }
+}
+
+fn disassemble_bytes(hex: &str) {
+    match encodings::parse_hex_bytes(hex) {
+        Ok(bytes) if !bytes.is_empty() => {
+            for line in disassembler::disassemble(&bytes) {
+                println!("  {}", line.cyan());
+            }
+        }
+        Ok(_) => println!("{} Usage: disasm <bytes>, e.g. disasm 48 89 d8", "ERROR:".red()),
+        Err(e) => println!("{} {}", "ERROR:".red(), e),
+    }
+}
+
+fn print_backtrace(debugger: &Debugger, cpu: &CPU) {
+    let Backtrace { frames, truncated } = debugger.backtrace(cpu);
+
+    println!("{}", "Backtrace (innermost first):".bold());
+    for (depth, &index) in frames.iter().enumerate() {
+        let role = if depth == 0 { "at" } else { "called from" };
+        println!("  #{:<2} {:>11} {:>3}: {}", depth, role, index, debugger.program[index]);
+    }
+    if let Some(reason) = truncated {
+        println!("  {} {}", "...".dimmed(), reason.yellow());
+    }
+}
+
+fn explain_instruction(input: &str) {
+    match parse_complete_instruction(input) {
+        Ok(instruction) => {
+            for line in explain::explain(&instruction) {
+                println!("{}", line);
+            }
+        }
+        Err(e) => println!("{} {}", "Error parsing input:".red(), e),
+    }
+}
+
+fn compare_signedness(args: &str) {
+    let values: Result<Vec<u64>, String> = args.split_whitespace().map(explain::parse_value).collect();
+    match values.as_deref() {
+        Ok([a, b]) => {
+            for line in explain::signedness_report(*a, *b) {
+                println!("{}", line);
+            }
         }
+        Ok(_) => println!("{} Usage: signedness <a> <b>", "ERROR:".red()),
+        Err(e) => println!("{} {}", "ERROR:".red(), e),
     }
-    println!("{}", "All instructions executed successfully.".green());
 }
 
-fn process_instruction(instruction: &Instruction, cpu: &mut CPU) {
+fn analyze_program(instructions: &[String]) {
+    let program = match parse_program(instructions) {
+        Ok(program) => program,
+        Err(e) => {
+            println!("{} {}", "ERROR:".red(), e);
+            return;
+        }
+    };
+
+    let unreachable = analyzer::find_unreachable(&program);
+    if unreachable.is_empty() {
+        println!("{}", "No unreachable instructions found.".green());
+        return;
+    }
+
+    println!("{}", "Unreachable instructions:".yellow().bold());
+    let lines = instruction_lines(instructions);
+    for index in unreachable {
+        println!("  {:>3}: {}", index, lines[index].trim());
+    }
+}
+
+fn optimize_program(instructions: &[String]) {
+    let program = match parse_program(instructions) {
+        Ok(program) => program,
+        Err(e) => {
+            println!("{} {}", "ERROR:".red(), e);
+            return;
+        }
+    };
+
+    let suggestions = optimizer::suggest(&program);
+    if suggestions.is_empty() {
+        println!("{}", "No peephole improvements found.".green());
+        return;
+    }
+
+    println!("{}", "Suggested improvements:".yellow().bold());
+    let mut total_saved = 0;
+    for suggestion in &suggestions {
+        let after = match &suggestion.after {
+            Some(instruction) => instruction.to_string(),
+            None => "(remove)".to_string(),
+        };
+        match suggestion.sizes() {
+            Ok((before_size, after_size)) => {
+                total_saved += before_size.saturating_sub(after_size);
+                println!("  {:>3}: {:<20} → {:<20} {} → {} bytes",
+                    suggestion.index, suggestion.before.to_string(), after, before_size, after_size);
+            }
+            Err(e) => println!("  {:>3}: {:<20} → {:<20} ({})",
+                suggestion.index, suggestion.before.to_string(), after, e),
+        }
+        println!("       {}", suggestion.reason.italic());
+    }
+    println!("{} {} bytes", "Total savings:".green(), total_saved);
+}
+
+fn process_instruction(instruction: &Instruction, cpu: &mut CPU, settings: &DisplaySettings) -> bool {
     match assemble_instruction(instruction) {
         Ok(bytes) => {
             println!("{} {:?}", "Assembled bytes:".blue(), bytes);
-            cpu.execute(instruction);
+            if let Err(e) = cpu.execute(instruction) {
+                println!("{} {}", "ERROR:".red(), e);
+                return false;
+            }
             println!("{}", "Instruction executed.".green());
+            print_program_output(cpu);
+            // There's no program to stop here, so just report the status
+            if let Some(status) = cpu.exit_status.take() {
+                println!("exit called with status {}.", status);
+            }
+            if let Some(note) = explain::no_op_note(instruction).filter(|_| settings.explain) {
+                println!("{} {}", "Note:".yellow(), note);
+            }
+            true
         },
-        Err(e) => println!("{} {}", "ERROR:".red(), e),
+        Err(e) => {
+            println!("{} {}", "ERROR:".red(), e);
+            false
+        }
+    }
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Loading Files                                                 ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+// The instruction and label lines of an assembly file, each with its line number in the file.
+// Every line is parsed up front so a typo is reported against the file rather than the buffer
+fn read_program_file(path: &str) -> Result<Vec<(usize, String)>, String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let lines: Vec<(usize, String)> = source.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .filter(|(_, line)| !parser::strip_comment(line).trim().is_empty())
+        .collect();
+
+    for (number, line) in &lines {
+        if parse_label_definition(line).is_none() {
+            parse_complete_instruction(line).map_err(|e| format!("{}:{}: {}", path, number, e))?;
+        }
+    }
+    Ok(lines)
+}
+
+// `load-mem <path> <address>`: the address comes last, so the path may contain spaces
+fn load_memory_file(args: &str, cpu: &mut CPU) -> Result<(usize, u64), String> {
+    let (path, address) = args.trim().rsplit_once(char::is_whitespace)
+        .ok_or_else(|| "Usage: load-mem <path> <address>".to_string())?;
+    let address = explain::parse_value(address)?;
+    let path = path.trim();
+    let bytes = std::fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    cpu.load_into_memory(address, &bytes)
+        .map_err(|_| format!("{} is {} byte(s), which doesn't fit in memory at {:#x}", path, bytes.len(), address))?;
+    Ok((bytes.len(), address))
+}
+
+// Runs a script file line by line, stopping at the first error
+fn run_script_file(path: &str, script_env: &mut ScriptEnvironment, cpu: &mut CPU) {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("{} Can't read {}: {}", "ERROR:".red(), path, e);
+            return;
+        }
+    };
+    for (i, line) in source.lines().enumerate() {
+        match script_env.execute_script(line, cpu) {
+            Ok(result) if result.trim().is_empty() => {}
+            Ok(result) => print!("{}", result),
+            Err(e) => {
+                println!("{} {}:{}: {}", "Script error:".red(), path, i + 1, e);
+                return;
+            }
+        }
     }
 }
 
@@ -194,29 +1276,108 @@ fn process_instruction(instruction: &Instruction, cpu: &mut CPU) {
 //║   ⇩ Register Visualization                                        ║  
 //╚═══════════════════════════════════════════════════════════════════╝
 
-fn visualize_register(name: &str, value: u64) {
+/// The palettes `colors` can pick for the register visualization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorScheme {
+    Gradient,   // A warm-to-cool gradient per byte (the default)
+    Mono,       // No color at all, just the bit glyphs
+    Colorblind, // The Okabe-Ito palette, one color per byte
+    Blue,       // Shades of a single hue, darker toward the low byte
+}
+
+impl ColorScheme {
+    const ALL: [ColorScheme; 4] = [ColorScheme::Gradient, ColorScheme::Mono, ColorScheme::Colorblind, ColorScheme::Blue];
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorScheme::Gradient => "gradient",
+            ColorScheme::Mono => "mono",
+            ColorScheme::Colorblind => "colorblind",
+            ColorScheme::Blue => "blue",
+        }
+    }
+
+    /// The color of a set bit, counting from the most significant bit of a 64-bit register.
+    fn bit_color(self, index: usize) -> Color {
+        const OKABE_ITO: [(u8, u8, u8); 8] = [
+            (230,159,0), (86,180,233), (0,158,115), (240,228,66), (0,114,178), (213,94,0), (204,121,167), (153,153,153),
+        ];
+        const BLUES: [(u8, u8, u8); 8] = [
+            (227,242,253), (187,222,251), (144,202,249), (100,181,246), (66,165,245), (33,150,243), (30,136,229), (21,101,192),
+        ];
+        let (r, g, b) = match self {
+            ColorScheme::Gradient => return get_bit_color(index),
+            ColorScheme::Mono => return Color::BrightWhite,
+            ColorScheme::Colorblind => OKABE_ITO[index / 8],
+            ColorScheme::Blue => BLUES[index / 8],
+        };
+        Color::TrueColor { r, g, b }
+    }
+
+    // The color of one 32-bit lane of an XMM register, counting from the most significant
+    fn lane_color(self, lane: usize) -> Color {
+        match self {
+            ColorScheme::Gradient => [Color::Red, Color::Green, Color::Blue, Color::Yellow][lane],
+            _ => self.bit_color(lane * 16),
+        }
+    }
+}
+
+fn select_color_scheme(name: &str, settings: &mut DisplaySettings) {
+    let name = name.trim();
+    if name.is_empty() {
+        let names: Vec<String> = ColorScheme::ALL.iter()
+            .map(|scheme| if *scheme == settings.colors { format!("{} (current)", scheme.name()) } else { scheme.name().to_string() })
+            .collect();
+        println!("Color schemes: {}", names.join(", "));
+        return;
+    }
+    match ColorScheme::ALL.into_iter().find(|scheme| scheme.name().eq_ignore_ascii_case(name)) {
+        Some(scheme) => {
+            settings.colors = scheme;
+            println!("Registers are now shown with the {} color scheme.", scheme.name());
+        }
+        None => println!("{} Unknown color scheme '{}'; type 'colors' to list them", "ERROR:".red(), name),
+    }
+}
+
+// Groups the glyphs into bytes, most significant first, or lowest first when `little_endian` is set.
+// Bits keep their order within a byte, so each group still reads as that byte's value
+fn group_bytes(glyphs: Vec<ColoredString>, little_endian: bool) -> String {
+    let mut bytes: Vec<String> = glyphs.chunks(8)
+        .map(|byte| byte.iter().map(ToString::to_string).collect())
+        .collect();
+    if little_endian {
+        bytes.reverse();
+    }
+    bytes.join(" ")
+}
+
+// Against an earlier value, a bit that changed is underlined and one that didn't is dimmed
+fn mark_bit_change(glyph: ColoredString, changed: Option<bool>) -> ColoredString {
+    match changed {
+        None => glyph,
+        Some(true) => glyph.bold().underline(),
+        Some(false) => glyph.dimmed(),
+    }
+}
+
+fn visualize_register(name: &str, value: u64, previous: Option<u64>, settings: &DisplaySettings) {
     let bits = format!("{:064b}", value);
-    let visualization = bits.chars()
+    let glyphs = bits.chars()
         .enumerate()
         .map(|(i, c)| {
-            let color = get_bit_color(i);
-            if c == '1' {
+            let color = settings.colors.bit_color(i);
+            let glyph = if c == '1' {
                 "█".color(color)
             } else {
                 "▁".white()
-            }
-        })
-        .enumerate()
-        .map(|(i, colored_char)| {
-            if (i + 1) % 8 == 0 && i < 63 {
-                format!("{} ", colored_char)
-            } else {
-                colored_char.to_string()
-            }
+            };
+            mark_bit_change(glyph, previous.map(|prev| (prev ^ value) >> (63 - i) & 1 == 1))
         })
-        .collect::<String>();
+        .collect();
 
-    println!("{:<4} {} {:#018x}", name.white(), visualization, value);
+    println!("{:<4} {} {:#018x}", name.white(), group_bytes(glyphs, settings.little_endian), value);
 }
 
 fn get_bit_color(index: usize) -> Color {
@@ -246,27 +1407,64 @@ fn get_bit_color(index: usize) -> Color {
     Color::TrueColor { r, g, b }
 }
 
-fn visualize_xmm_register(name: &str, value: u128) {
+fn visualize_xmm_register(name: &str, value: u128, previous: Option<u128>, settings: &DisplaySettings) {
     let bits = format!("{:0128b}", value);
-    let visualization = bits.chars()
+    let glyphs = bits.chars()
         .enumerate()
         .map(|(i, c)| {
-            let color = match i / 32 {
-                0 => Color::Red,
-                1 => Color::Green,
-                2 => Color::Blue,
-                3 => Color::Yellow,
-                _ => unreachable!(),
-            };
-            if c == '1' { "█".color(color) } else { "▁".color(color).dimmed() }
+            let color = settings.colors.lane_color(i / 32);
+            let glyph = if c == '1' { "█".color(color) } else { "▁".color(color).dimmed() };
+            mark_bit_change(glyph, previous.map(|prev| (prev ^ value) >> (127 - i) & 1 == 1))
         })
-        .collect::<Vec<_>>()
-        .chunks(8)
-        .map(|chunk| chunk.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(""))
-        .collect::<Vec<_>>()
-        .join(" ");
+        .collect();
+
+    println!("{:<5} {}", name, group_bytes(glyphs, settings.little_endian));
+}
+
+// The `width`-bit lanes of an XMM register, lowest lane first
+fn xmm_lanes(value: u128, width: u32) -> Vec<u128> {
+    let mask = u128::MAX >> (128 - width);
+    (0..128 / width).map(|lane| (value >> (lane * width)) & mask).collect()
+}
 
-    println!("{:<5} {}", name, visualization);
+fn join_lanes<T: std::fmt::Display>(lanes: impl Iterator<Item = T>) -> String {
+    lanes.map(|lane| lane.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+// `xmm 3` or `xmm xmm3`: the bits, then the lanes as SSE instructions would read them
+fn display_xmm_register(arg: &str, cpu: &CPU, settings: &DisplaySettings) {
+    let arg = arg.trim().to_lowercase();
+    let index = match arg.strip_prefix("xmm").unwrap_or(&arg).parse::<usize>() {
+        Ok(index) if index < cpu.xmm.len() => index,
+        _ => {
+            println!("{} Usage: xmm <0-15>", "ERROR:".red());
+            return;
+        }
+    };
+    let value = cpu.xmm[index];
+    visualize_xmm_register(&format!("XMM{}", index), value, None, settings);
+    println!("Lanes, lowest first:");
+    println!("  {} [{}]", "f32:".cyan(), join_lanes(xmm_lanes(value, 32).into_iter().map(|lane| f32::from_bits(lane as u32))));
+    println!("  {} [{}]", "f64:".cyan(), join_lanes(xmm_lanes(value, 64).into_iter().map(|lane| f64::from_bits(lane as u64))));
+    println!("  {} [{}]", "i32:".cyan(), join_lanes(xmm_lanes(value, 32).into_iter().map(|lane| lane as u32 as i32)));
+    println!("  {} [{}]", "i64:".cyan(), join_lanes(xmm_lanes(value, 64).into_iter().map(|lane| lane as u64 as i64)));
+}
+
+fn select_byte_order(order: &str, settings: &mut DisplaySettings) {
+    match order.trim() {
+        "le" => settings.little_endian = true,
+        "be" => settings.little_endian = false,
+        "" => {}
+        other => {
+            println!("{} Unknown byte order '{}'; use 'le' or 'be'", "ERROR:".red(), other);
+            return;
+        }
+    }
+    if settings.little_endian {
+        println!("Register bytes are shown lowest first (le), as they're laid out in memory.");
+    } else {
+        println!("Register bytes are shown most significant first (be).");
+    }
 }
 
 fn display_compact_cpu_state(cpu: &CPU) {
@@ -300,8 +1498,8 @@ fn display_compact_cpu_state(cpu: &CPU) {
 
     println!("\n{}", "FLAGS:".yellow());
     let flags = [
-        ("CF", cpu.cf), ("ZF", cpu.zf),
-        ("SF", cpu.sf), ("OF", cpu.of),
+        ("CF", cpu.cf), ("PF", cpu.pf), ("AF", cpu.af), ("ZF", cpu.zf),
+        ("SF", cpu.sf), ("DF", cpu.df), ("OF", cpu.of),
     ];
     let active_flags: Vec<_> = flags.iter()
         .filter(|&&(_, value)| value)
@@ -310,43 +1508,246 @@ fn display_compact_cpu_state(cpu: &CPU) {
     println!("[{}]", active_flags.join(", "));
 }
 
-fn display_detailed_cpu_state(cpu: &CPU) {
+/// Shows every register bit by bit. Given the CPU from the last time this was shown, bits that
+/// changed since then are underlined and the rest dimmed.
+fn display_detailed_cpu_state(cpu: &CPU, previous: Option<&CPU>, settings: &DisplaySettings) {
     println!("{}", "Detailed CPU State:".yellow().bold());
+    if previous.is_some() {
+        println!("Bits changed since the last 'state' are underlined; the rest are dimmed.");
+    }
 
     // Visualize general-purpose registers
-    visualize_register("RAX", cpu.rax);
-    visualize_register("RBX", cpu.rbx);
-    visualize_register("RCX", cpu.rcx);
-    visualize_register("RDX", cpu.rdx);
-    visualize_register("RSI", cpu.rsi);
-    visualize_register("RDI", cpu.rdi);
-    visualize_register("RBP", cpu.rbp);
-    visualize_register("RSP", cpu.rsp);
-    visualize_register("R8", cpu.r8);
-    visualize_register("R9", cpu.r9);
-    visualize_register("R10", cpu.r10);
-    visualize_register("R11", cpu.r11);
-    visualize_register("R12", cpu.r12);
-    visualize_register("R13", cpu.r13);
-    visualize_register("R14", cpu.r14);
-    visualize_register("R15", cpu.r15);
+    visualize_register("RAX", cpu.rax, previous.map(|p| p.rax), settings);
+    visualize_register("RBX", cpu.rbx, previous.map(|p| p.rbx), settings);
+    visualize_register("RCX", cpu.rcx, previous.map(|p| p.rcx), settings);
+    visualize_register("RDX", cpu.rdx, previous.map(|p| p.rdx), settings);
+    visualize_register("RSI", cpu.rsi, previous.map(|p| p.rsi), settings);
+    visualize_register("RDI", cpu.rdi, previous.map(|p| p.rdi), settings);
+    visualize_register("RBP", cpu.rbp, previous.map(|p| p.rbp), settings);
+    visualize_register("RSP", cpu.rsp, previous.map(|p| p.rsp), settings);
+    visualize_register("R8", cpu.r8, previous.map(|p| p.r8), settings);
+    visualize_register("R9", cpu.r9, previous.map(|p| p.r9), settings);
+    visualize_register("R10", cpu.r10, previous.map(|p| p.r10), settings);
+    visualize_register("R11", cpu.r11, previous.map(|p| p.r11), settings);
+    visualize_register("R12", cpu.r12, previous.map(|p| p.r12), settings);
+    visualize_register("R13", cpu.r13, previous.map(|p| p.r13), settings);
+    visualize_register("R14", cpu.r14, previous.map(|p| p.r14), settings);
+    visualize_register("R15", cpu.r15, previous.map(|p| p.r15), settings);
 
     println!("\n{:<7} {:#018x}", "RIP".cyan(), cpu.rip);
 
     // Visualize XMM registers
     println!("\nXMM Registers:");
     for (i, xmm_value) in cpu.xmm.iter().enumerate() {
-        visualize_xmm_register(&format!("XMM{}", i), *xmm_value);
+        visualize_xmm_register(&format!("XMM{}", i), *xmm_value, previous.map(|p| p.xmm[i]), settings);
     }
 
     println!("\n{}", "FLAGS:".yellow());
     let flags = [
-        ("CF", cpu.cf), ("ZF", cpu.zf),
-        ("SF", cpu.sf), ("OF", cpu.of),
+        ("CF", cpu.cf), ("PF", cpu.pf), ("AF", cpu.af), ("ZF", cpu.zf),
+        ("SF", cpu.sf), ("DF", cpu.df), ("OF", cpu.of),
     ];
     let active_flags: Vec<_> = flags.iter()
         .filter(|&&(_, value)| value)
         .map(|&(name, _)| name.to_string())
         .collect();
     println!("[{}]", active_flags.join(", "));
-}
\ No newline at end of file
+}
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Calling-Convention Roles                                      ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum CallingConvention {
+    SystemV,
+    Win64,
+}
+
+impl CallingConvention {
+    fn name(self) -> &'static str {
+        match self {
+            CallingConvention::SystemV => "System V AMD64",
+            CallingConvention::Win64 => "Windows x64",
+        }
+    }
+}
+
+// A register can play several roles (rdx carries both an argument and the high half of a return value)
+fn register_roles(convention: CallingConvention) -> Vec<(&'static str, Vec<Register>)> {
+    use Register::*;
+    match convention {
+        CallingConvention::SystemV => vec![
+            ("Arguments", vec![Rdi, Rsi, Rdx, Rcx, R8, R9]),
+            ("Return value", vec![Rax, Rdx]),
+            ("Callee-saved", vec![Rbx, Rbp, R12, R13, R14, R15]),
+            ("Caller-saved", vec![Rax, Rcx, Rdx, Rsi, Rdi, R8, R9, R10, R11]),
+            ("Stack/base pointers", vec![Rsp, Rbp]),
+        ],
+        CallingConvention::Win64 => vec![
+            ("Arguments", vec![Rcx, Rdx, R8, R9]),
+            ("Return value", vec![Rax]),
+            ("Callee-saved", vec![Rbx, Rbp, Rdi, Rsi, R12, R13, R14, R15]),
+            ("Caller-saved", vec![Rax, Rcx, Rdx, R8, R9, R10, R11]),
+            ("Stack/base pointers", vec![Rsp, Rbp]),
+        ],
+    }
+}
+
+fn display_return_value(cpu: &CPU, convention: CallingConvention) {
+    let (_, registers) = register_roles(convention).into_iter()
+        .find(|(role, _)| *role == "Return value")
+        .expect("every convention has a return value role");
+
+    println!("{} ({})", "Return value".bold().yellow(), convention.name());
+    for (i, register) in registers.iter().enumerate() {
+        let note = if i == 0 { "" } else { "  high half of a 128-bit return" };
+        println!("  {:<4} = {:#018x} ({}){}", register.to_string().yellow(), cpu[register], cpu[register], note.dimmed());
+    }
+    if let [low, high] = registers.as_slice() {
+        let wide = (u128::from(cpu[high]) << 64) | u128::from(cpu[low]);
+        println!("  {}:{} = {:#x}", high, low, wide);
+    }
+}
+
+fn handle_state_command(args: &str, cpu: &CPU) {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let convention = match args.as_slice() {
+        ["--role"] | ["--role", "sysv"] => CallingConvention::SystemV,
+        ["--role", "win64"] => CallingConvention::Win64,
+        _ => {
+            println!("{} Usage: state --role [sysv|win64]", "ERROR:".red());
+            return;
+        }
+    };
+    display_registers_by_role(cpu, convention);
+}
+
+fn display_registers_by_role(cpu: &CPU, convention: CallingConvention) {
+    println!("{} {}", "Registers by role:".yellow().bold(), convention.name());
+
+    for (role, registers) in register_roles(convention) {
+        println!("\n{}", role.cyan());
+        for register in &registers {
+            println!("  {:<4} {:#018x}", register.to_string(), cpu[register]);
+        }
+    }
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Stack Frame View                                              ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+const FRAME_ARGUMENT_SLOTS: i64 = 2;
+const FRAME_MAX_LOCAL_SLOTS: i64 = 16;
+
+fn frame_slot_role(offset: i64) -> &'static str {
+    match offset {
+        8 => "return address",
+        0 => "saved rbp",
+        o if o > 8 => "argument",
+        _ => "local",
+    }
+}
+
+fn display_stack_frame(cpu: &CPU) {
+    if cpu.rbp == 0 || cpu.rbp < cpu.rsp {
+        println!("No stack frame set up (rbp = {:#x}, rsp = {:#x}).", cpu.rbp, cpu.rsp);
+        println!("A typical prologue is: push rbp / mov rbp, rsp / sub rsp, <locals>");
+        return;
+    }
+
+    println!("{} rbp = {:#x}, rsp = {:#x}", "Stack Frame:".yellow().bold(), cpu.rbp, cpu.rsp);
+
+    let local_slots = (((cpu.rbp - cpu.rsp) / 8) as i64).min(FRAME_MAX_LOCAL_SLOTS);
+    let highest = 8 + 8 * FRAME_ARGUMENT_SLOTS;
+    let lowest = -8 * local_slots;
+
+    for offset in (lowest..=highest).rev().step_by(8) {
+        let address = cpu.rbp.wrapping_add_signed(offset);
+        let label = match offset {
+            0 => "[rbp]".to_string(),
+            o if o > 0 => format!("[rbp+{}]", o),
+            o => format!("[rbp-{}]", -o),
+        };
+        let value = match cpu.read_memory_sized(address, 8) {
+            Ok(value) => format!("{:#018x}", value),
+            Err(_) => "??".to_string(),
+        };
+        let marker = if address == cpu.rsp { " ← rsp" } else { "" };
+        println!("  {:<10} {:#010x}  {:<18}  {}{}",
+            label.cyan(), address, value, frame_slot_role(offset).italic(), marker);
+    }
+
+    if (cpu.rbp - cpu.rsp) / 8 > FRAME_MAX_LOCAL_SLOTS as u64 {
+        println!("  ... ({} more bytes down to rsp)", cpu.rbp - cpu.rsp - 8 * FRAME_MAX_LOCAL_SLOTS as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_aliases() {
+        assert!(["exit", "quit", "q"].iter().all(|cmd| is_exit_command(cmd)));
+        assert!(!is_exit_command("exit now") && !is_exit_command("Q"));
+    }
+
+    #[test]
+    fn unsaved_work_lists_the_buffer_and_cpu_changes() {
+        let saved = CPU::new();
+        assert!(unsaved_work(&saved, &saved, &[]).is_empty());
+
+        let mut cpu = saved.clone();
+        cpu.rax = 1;
+        let buffer = ["mov rax, 1".to_string(), "nop".to_string()];
+        assert_eq!(unsaved_work(&cpu, &saved, &buffer), [
+            "2 buffered instruction(s) that haven't been run",
+            "CPU state changes that haven't been saved",
+        ]);
+    }
+
+    fn settings() -> DisplaySettings {
+        DisplaySettings { explain: false, autostate: false, watches: Vec::new(), colors: ColorScheme::Gradient, little_endian: false }
+    }
+
+    #[test]
+    fn live_mode_runs_each_line_as_it_is_entered() {
+        let (mut cpu, isa) = (CPU::new(), Isa::full());
+        assert!(execute_live("mov rax, 5", &mut cpu, &isa, &settings()));
+        assert_eq!(cpu.rax, 5);
+        assert!(!execute_live("mov rax,", &mut cpu, &isa, &settings()));
+        assert!(!execute_live("push rax", &mut cpu, &Isa::from_spec("intro").unwrap(), &settings()));
+
+        // Branches are kept for `run` but not taken now
+        let before = cpu.clone();
+        assert!(execute_live("jmp 0", &mut cpu, &isa, &settings()));
+        assert!(execute_live("top:", &mut cpu, &isa, &settings()));
+        assert!(cpu::diff_registers(&before, &cpu).is_empty());
+        assert_eq!(cpu.rip, before.rip);
+    }
+
+    #[test]
+    fn watch_and_unwatch() {
+        let mut watches = Vec::new();
+        edit_watches("watch RAX", &mut watches).unwrap();
+        edit_watches("watch zf", &mut watches).unwrap();
+        assert_eq!(watches, ["rax", "ZF"]);
+        assert_eq!(edit_watches("watch rax", &mut watches), Err("Already watching rax".to_string()));
+        assert!(edit_watches("watch eax", &mut watches).is_err());
+        edit_watches("unwatch rax", &mut watches).unwrap();
+        assert_eq!(edit_watches("unwatch rax", &mut watches), Err("Not watching rax".to_string()));
+        assert_eq!(watches, ["ZF"]);
+    }
+
+    #[test]
+    fn set_checks_the_value_fits_the_width() {
+        let mut cpu = CPU::new();
+        let write = |size, value| parser::MemoryWrite { address: 0x100, size, value };
+        set_memory(&mut cpu, &write(RegisterSize::Word, -1)).unwrap();
+        set_memory(&mut cpu, &write(RegisterSize::Byte, 0xff)).unwrap();
+        assert_eq!(cpu.read_memory_sized(0x100, 2), Ok(0xffff));
+        assert_eq!(set_memory(&mut cpu, &write(RegisterSize::Byte, 0x100)), Err("0x100 doesn't fit in a byte".to_string()));
+        assert!(set_memory(&mut cpu, &write(RegisterSize::Byte, -129)).is_err());
+    }
+}
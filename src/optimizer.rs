@@ -0,0 +1,197 @@
+use crate::assembler::assemble_instruction;
+use crate::parser::{Instruction, InstructionType, Operand, Register, RegisterSize};
+
+pub struct Suggestion {
+    pub index: usize,
+    pub before: Instruction,
+    pub after: Option<Instruction>, // `None` means the instruction can be removed
+    pub reason: &'static str,
+}
+
+impl Suggestion {
+    /// Encoded size of the original and the replacement, in bytes.
+    pub fn sizes(&self) -> Result<(usize, usize), String> {
+        let before = assemble_instruction(&self.before)?.len();
+        let after = match &self.after {
+            Some(instruction) => assemble_instruction(instruction)?.len(),
+            None => 0,
+        };
+        Ok((before, after))
+    }
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Peephole Rules                                                ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+fn rewrite(instruction_type: InstructionType, operands: Vec<Operand>) -> Option<Instruction> {
+    Some(Instruction { instruction_type, operands })
+}
+
+// `reg * factor` as an lea address, for the factors a scaled index plus a base can make
+fn lea_multiply(register: &Register, factor: i64) -> Option<Operand> {
+    let scale = match factor {
+        3 | 5 | 9 => factor as u8 - 1,
+        _ => return None,
+    };
+    Some(Operand::Memory { base: Some(register.clone()), index: Some(register.clone()), scale, disp: 0, size: None })
+}
+
+// A rewrite, and whether it leaves different flags behind than the original. Those are only
+// suggested when nothing reads the flags before they're set again.
+struct Rule {
+    after: Option<Instruction>,
+    reason: &'static str,
+    changes_flags: bool,
+}
+
+fn peephole(instruction: &Instruction) -> Option<Rule> {
+    use InstructionType::*;
+
+    let (after, reason, changes_flags) = match (&instruction.instruction_type, instruction.operands.as_slice()) {
+        (Mov, [dest @ Operand::Register(..), Operand::Immediate(0)]) => (
+            rewrite(Xor, vec![dest.clone(), dest.clone()]),
+            "zeroing idiom: xor is shorter (but clobbers flags)",
+            true,
+        ),
+        // A 32-bit write zeroes the upper half, so only 64-bit forms are true no-ops
+        (Mov, [dest @ Operand::Register(_, RegisterSize::Qword), src]) if dest == src => (
+            None,
+            "moving a register onto itself does nothing",
+            false,
+        ),
+        (Add | Sub, [Operand::Register(_, RegisterSize::Qword), Operand::Immediate(0)]) => (
+            None,
+            "adding or subtracting 0 only updates flags",
+            true,
+        ),
+        (Or | Xor, [Operand::Register(_, RegisterSize::Qword), Operand::Immediate(0)]) => (
+            None,
+            "or/xor with 0 only updates flags",
+            true,
+        ),
+        // A count of 0 leaves the flags alone too
+        (Shl | Shr | Rol | Ror, [Operand::Register(_, RegisterSize::Qword), Operand::Immediate(0)]) => (
+            None,
+            "shifting or rotating by 0 does nothing",
+            false,
+        ),
+        (Add, [dest @ Operand::Register(..), Operand::Immediate(1)]) => (
+            rewrite(Inc, vec![dest.clone()]),
+            "inc is shorter (but leaves CF untouched)",
+            true,
+        ),
+        (Sub, [dest @ Operand::Register(..), Operand::Immediate(1)]) => (
+            rewrite(Dec, vec![dest.clone()]),
+            "dec is shorter (but leaves CF untouched)",
+            true,
+        ),
+        (Cmp, [dest @ Operand::Register(..), Operand::Immediate(0)]) => (
+            rewrite(Test, vec![dest.clone(), dest.clone()]),
+            "test reg, reg sets the same ZF/SF without an immediate",
+            false,
+        ),
+        (Imul, [dest @ Operand::Register(reg, RegisterSize::Qword), Operand::Immediate(factor)]) |
+        (Imul, [dest @ Operand::Register(_, RegisterSize::Qword), Operand::Register(reg, RegisterSize::Qword), Operand::Immediate(factor)]) => (
+            rewrite(Lea, vec![dest.clone(), lea_multiply(reg, *factor)?]),
+            "lea multiplies by 3, 5 or 9 without a multiply (but doesn't detect overflow)",
+            true,
+        ),
+        _ => return None,
+    };
+    Some(Rule { after, reason, changes_flags })
+}
+
+// Whether the flags as they are before `rest` may be read: by a conditional jump, adc/sbb,
+// cmov or setcc before an instruction sets them all again. Where a jump, call or ret goes
+// isn't followed, so they count as a read.
+fn flags_read(rest: &[Instruction]) -> bool {
+    use InstructionType::*;
+
+    for instruction in rest {
+        match instruction.instruction_type {
+            Je | Jne | Jg | Jge | Jl | Jle | Adc | Sbb | Cmovne |
+            Sete | Setne | Setg | Setge | Setl | Setle | Seta | Setae | Setb | Setbe => return true,
+            Jmp | Loop | Call | Ret => return true,
+            Add | Sub | And | Or | Xor | Cmp | Test | Neg => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Suggests peephole improvements for a program without modifying it.
+pub fn suggest(program: &[Instruction]) -> Vec<Suggestion> {
+    program.iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| {
+            let rule = peephole(instruction)?;
+            if rule.changes_flags && flags_read(&program[index + 1..]) {
+                return None;
+            }
+            Some(Suggestion { index, before: instruction.clone(), after: rule.after, reason: rule.reason })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_complete_instruction;
+
+    fn program(lines: &[&str]) -> Vec<Instruction> {
+        lines.iter().map(|line| parse_complete_instruction(line).unwrap()).collect()
+    }
+
+    fn suggested(lines: &[&str]) -> Vec<(usize, Option<String>)> {
+        suggest(&program(lines)).into_iter()
+            .map(|s| (s.index, s.after.map(|after| after.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn rewrites_each_pattern() {
+        assert_eq!(suggested(&["mov rax, 0"]), vec![(0, Some("xor rax, rax".to_string()))]);
+        assert_eq!(suggested(&["mov rbx, rbx", "mov ebx, ebx"]), vec![(0, None)]);
+        assert_eq!(suggested(&["add rax, 0", "sub rbx, 0"]), vec![(0, None), (1, None)]);
+        assert_eq!(suggested(&["add rax, 1", "sub rbx, 1"]),
+                   vec![(0, Some("inc rax".to_string())), (1, Some("dec rbx".to_string()))]);
+        assert_eq!(suggested(&["cmp rax, 0", "je 0"]), vec![(0, Some("test rax, rax".to_string()))]);
+    }
+
+    #[test]
+    fn reports_the_bytes_saved() {
+        let suggestions = suggest(&program(&["mov rax, 0", "add rbx, 0"]));
+        assert_eq!(suggestions[0].sizes(), Ok((10, 3)));
+        assert_eq!(suggestions[1].sizes(), Ok((4, 0)));
+    }
+
+    #[test]
+    fn or_xor_with_zero_only_updates_flags() {
+        let suggestions = suggest(&program(&["or rax, 0", "xor rbx, 0"]));
+        assert_eq!(suggestions.len(), 2);
+        assert!(suggestions.iter().all(|s| s.after.is_none() && s.reason == "or/xor with 0 only updates flags"));
+    }
+
+    #[test]
+    fn keeps_flag_updates_that_are_read() {
+        assert_eq!(suggested(&["or rax, 0", "je 0"]), vec![]);
+        assert_eq!(suggested(&["xor rax, 0", "mov rbx, 1", "setne bl"]), vec![]);
+        assert_eq!(suggested(&["add rax, 1", "adc rdx, 0"]), vec![]);
+        // The flags are set again before anything reads them
+        assert_eq!(suggested(&["or rax, 0", "cmp rax, rbx", "je 0"]), vec![(0, None)]);
+    }
+
+    #[test]
+    fn shifts_by_zero_are_dropped_even_before_a_jump() {
+        assert_eq!(suggested(&["shl rax, 0", "jne 0"]), vec![(0, None)]);
+    }
+
+    #[test]
+    fn multiplies_by_3_5_or_9_with_lea() {
+        assert_eq!(suggested(&["imul rax, rbx, 5", "imul rcx, 3", "imul rdx, 4"]), vec![
+            (0, Some("lea rax, [rbx+rbx*4]".to_string())),
+            (1, Some("lea rcx, [rcx+rcx*2]".to_string())),
+        ]);
+    }
+}
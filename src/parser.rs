@@ -1,11 +1,14 @@
 use nom::{
     IResult,
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{digit1, hex_digit1, space0, space1}, // removed multispace0, alphanumeric1
-    combinator::{map, map_res, opt}, // Removed value
-    sequence::{delimited, preceded, tuple},
+    bytes::complete::{tag, tag_no_case, take_while},
+    character::complete::{anychar, char, digit1, hex_digit1, multispace0, none_of, satisfy, space0, space1},
+    combinator::{cut, eof, map, map_opt, map_res, not, opt, recognize, verify}, // Removed value
+    multi::{many0, many_m_n},
+    sequence::{delimited, pair, preceded, terminated, tuple},
 };
+use std::collections::HashMap;
+use std::fmt;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Register {
@@ -14,26 +17,147 @@ pub enum Register {
     R8, R9, R10, R11,
     R12, R13, R14, R15
 }
+impl Register {
+    pub const ALL: [Register; 16] = [
+        Register::Rax, Register::Rbx, Register::Rcx, Register::Rdx,
+        Register::Rsi, Register::Rdi, Register::Rbp, Register::Rsp,
+        Register::R8, Register::R9, Register::R10, Register::R11,
+        Register::R12, Register::R13, Register::R14, Register::R15,
+    ];
+
+    /// The assembler name for this register at the given width, e.g. `eax`, `r8w` or `ah`.
+    /// Only rax..rdx have a high-byte form.
+    pub fn name(&self, size: RegisterSize) -> Option<&'static str> {
+        use Register::*;
+        let name = match size {
+            RegisterSize::Qword => match self {
+                Rax => "rax", Rbx => "rbx", Rcx => "rcx", Rdx => "rdx",
+                Rsi => "rsi", Rdi => "rdi", Rbp => "rbp", Rsp => "rsp",
+                R8 => "r8", R9 => "r9", R10 => "r10", R11 => "r11",
+                R12 => "r12", R13 => "r13", R14 => "r14", R15 => "r15",
+            },
+            RegisterSize::Dword => match self {
+                Rax => "eax", Rbx => "ebx", Rcx => "ecx", Rdx => "edx",
+                Rsi => "esi", Rdi => "edi", Rbp => "ebp", Rsp => "esp",
+                R8 => "r8d", R9 => "r9d", R10 => "r10d", R11 => "r11d",
+                R12 => "r12d", R13 => "r13d", R14 => "r14d", R15 => "r15d",
+            },
+            RegisterSize::Word => match self {
+                Rax => "ax", Rbx => "bx", Rcx => "cx", Rdx => "dx",
+                Rsi => "si", Rdi => "di", Rbp => "bp", Rsp => "sp",
+                R8 => "r8w", R9 => "r9w", R10 => "r10w", R11 => "r11w",
+                R12 => "r12w", R13 => "r13w", R14 => "r14w", R15 => "r15w",
+            },
+            RegisterSize::Byte => match self {
+                Rax => "al", Rbx => "bl", Rcx => "cl", Rdx => "dl",
+                Rsi => "sil", Rdi => "dil", Rbp => "bpl", Rsp => "spl",
+                R8 => "r8b", R9 => "r9b", R10 => "r10b", R11 => "r11b",
+                R12 => "r12b", R13 => "r13b", R14 => "r14b", R15 => "r15b",
+            },
+            RegisterSize::HighByte => match self {
+                Rax => "ah", Rbx => "bh", Rcx => "ch", Rdx => "dh",
+                _ => return None,
+            },
+        };
+        Some(name)
+    }
+}
+
+/// How much of a general-purpose register an operand refers to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RegisterSize {
+    Byte,     // al, r8b, ...: bits 0-7, the rest is preserved
+    HighByte, // ah, bh, ch, dh: bits 8-15, the rest is preserved
+    Word,     // ax, r8w, ...: bits 0-15, the rest is preserved
+    Dword,    // eax, r8d, ...: writes zero the upper 32 bits
+    Qword,    // rax, r8, ...
+}
+
+impl RegisterSize {
+    pub const ALL: [RegisterSize; 5] = [
+        RegisterSize::Byte, RegisterSize::HighByte, RegisterSize::Word, RegisterSize::Dword, RegisterSize::Qword,
+    ];
+
+    pub fn bits(self) -> u32 {
+        match self {
+            RegisterSize::Byte | RegisterSize::HighByte => 8,
+            RegisterSize::Word => 16,
+            RegisterSize::Dword => 32,
+            RegisterSize::Qword => 64,
+        }
+    }
+
+    pub fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+
+    /// Where the sub-register starts within the full register.
+    pub fn shift(self) -> u32 {
+        if self == RegisterSize::HighByte { 8 } else { 0 }
+    }
+
+    /// The width keyword used on memory operands, e.g. `byte` in `byte [rbx]`.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            RegisterSize::Byte | RegisterSize::HighByte => "byte",
+            RegisterSize::Word => "word",
+            RegisterSize::Dword => "dword",
+            RegisterSize::Qword => "qword",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum InstructionType {
     Mov, Add, Sub, And, Or, Xor,
+    Adc, Sbb,
+    Mul, Imul, Div, Idiv,
+    Cdq, Cqo,
     Inc, Dec, Neg, Not,
     Shl, Shr, Rol, Ror,
     Push, Pop,
     Cmp, Test,
     Jmp, Je, Jne, Jg, Jge, Jl, Jle,
+    Loop,
     Call, Ret,
+    Syscall,
+    Lea,
     Paddd, // Packed Add Doublewords
-    Bsf,
+    Bsf, Bsr,
+    Popcnt, Lzcnt, Tzcnt,
+    Bswap,
+    Bt, Bts, Btr, Btc,
     Cmovne,
+    Sete, Setne, Setg, Setge, Setl, Setle, Seta, Setae, Setb, Setbe,
+    Movzx, Movsx,
+    Xchg,
+    Nop,
+    Cld, Std,
     //TODO: Add other instructions over time
 }
 
+impl InstructionType {
+    /// Jumps and calls, the instructions whose operand is a code target.
+    pub fn is_branch(&self) -> bool {
+        use InstructionType::*;
+        matches!(self, Jmp | Je | Jne | Jg | Jge | Jl | Jle | Loop | Call)
+    }
+
+    /// Instructions written without operands, so anything after the mnemonic is left-over text.
+    pub fn takes_no_operands(&self) -> bool {
+        use InstructionType::*;
+        matches!(self, Cdq | Cqo | Ret | Syscall | Nop | Cld | Std)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Operand {
-    Register(Register),
-    Immediate(i32),
+    Register(Register, RegisterSize),
+    Immediate(i64),
     XmmRegister(u8),
+    // `size` comes from a `byte`/`word`/`dword`/`qword` prefix, when the instruction needs one
+    Memory { base: Option<Register>, index: Option<Register>, scale: u8, disp: i64, size: Option<RegisterSize> },
+    Label(String), // A jump/call target by name; programs resolve it to an instruction index
     // ... other operand types as needed
 }
 
@@ -63,10 +187,113 @@ pub struct MemoryDumpOptions {
     pub format: MemoryDumpFormat,
 }
 
+/// `set <address> <width> <value>`: a value to store in memory, e.g. `set 0x100 qword 0xdeadbeef`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MemoryWrite {
+    pub address: u64,
+    pub size: RegisterSize,
+    pub value: i64,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum MemoryDumpFormat {
     Hex,
     Decimal,
+    Binary,
+    // Each byte as its character, or `.` when it isn't printable
+    Char,
+}
+
+impl MemoryDumpFormat {
+    pub fn bytes_per_row(&self) -> usize {
+        match self {
+            MemoryDumpFormat::Binary => 8,
+            _ => 16,
+        }
+    }
+
+    // How many characters one byte takes up in a row
+    pub fn cell_width(&self) -> usize {
+        match self {
+            MemoryDumpFormat::Hex => 2,
+            MemoryDumpFormat::Decimal => 3,
+            MemoryDumpFormat::Binary => 8,
+            MemoryDumpFormat::Char => 1,
+        }
+    }
+
+    pub fn format_byte(&self, byte: u8) -> String {
+        match self {
+            MemoryDumpFormat::Hex => format!("{:02x}", byte),
+            MemoryDumpFormat::Decimal => format!("{:3}", byte),
+            MemoryDumpFormat::Binary => format!("{:08b}", byte),
+            MemoryDumpFormat::Char => printable_char(byte).to_string(),
+        }
+    }
+}
+
+/// `byte` as ASCII if it's printable, otherwise `.`, as hex dumps show it.
+pub fn printable_char(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Display                                                       ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl fmt::Display for InstructionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(reg, size) => f.write_str(reg.name(*size).unwrap_or("?")),
+            Operand::Immediate(imm) => write!(f, "{}", imm),
+            Operand::XmmRegister(index) => write!(f, "xmm{}", index),
+            Operand::Memory { base, index, scale, disp, size } => {
+                if let Some(size) = size {
+                    write!(f, "{} ", size.keyword())?;
+                }
+                let mut terms = Vec::new();
+                if let Some(base) = base {
+                    terms.push(base.to_string());
+                }
+                if let Some(index) = index {
+                    terms.push(if *scale == 1 { index.to_string() } else { format!("{}*{}", index, scale) });
+                }
+                let mut address = terms.join("+");
+                if *disp != 0 || address.is_empty() {
+                    match (address.is_empty(), *disp < 0) {
+                        (true, _) => address = disp.to_string(),
+                        (false, true) => address += &format!("-{}", disp.unsigned_abs()),
+                        (false, false) => address += &format!("+{}", disp),
+                    }
+                }
+                write!(f, "[{}]", address)
+            }
+            Operand::Label(name) => f.write_str(name),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.instruction_type)?;
+        let operands: Vec<String> = self.operands.iter().map(|op| op.to_string()).collect();
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+        Ok(())
+    }
 }
 
 fn usize_decimal(input: &str) -> IResult<&str, usize> {
@@ -86,10 +313,10 @@ fn memory_command(input: &str) -> IResult<&str, MemoryDumpOptions> {
         |hex_str: &str| u64::from_str_radix(hex_str, 16)
     )(input)?;
 
-    let (input, size) = opt(delimited(
+    // Leaves the spaces after the size for the format flag, so `-s 32 -b` works
+    let (input, size) = opt(preceded(
         space1,
-        preceded(alt((tag("-s"), tag("--size"))), delimited(space0, usize_decimal, space0)),
-        space0
+        preceded(alt((tag("-s"), tag("--size"))), preceded(space0, usize_decimal)),
     ))(input)?;
 
     let (input, format) = opt(delimited(
@@ -97,9 +324,12 @@ fn memory_command(input: &str) -> IResult<&str, MemoryDumpOptions> {
         alt((
             map(alt((tag("-x"), tag("--hex"))), |_| MemoryDumpFormat::Hex),
             map(alt((tag("-d"), tag("--decimal"))), |_| MemoryDumpFormat::Decimal),
+            map(alt((tag("-b"), tag("--binary"))), |_| MemoryDumpFormat::Binary),
+            map(alt((tag("-c"), tag("--char"))), |_| MemoryDumpFormat::Char),
         )),
         space0,
     ))(input)?;
+    let (input, _) = space0(input)?;
 
     Ok((input, MemoryDumpOptions {
         address,
@@ -108,30 +338,51 @@ fn memory_command(input: &str) -> IResult<&str, MemoryDumpOptions> {
     }))
 }
 
+fn set_command(input: &str) -> IResult<&str, MemoryWrite> {
+    let (input, _) = tuple((tag("set"), space1))(input)?;
+    let (input, address) = terminated(unsigned_immediate, space1)(input)?;
+    let (input, size) = memory_width(input)?;
+    let (input, value) = immediate(input)?;
+    Ok((input, MemoryWrite { address, size, value }))
+}
+
+pub fn parse_memory_write(line: &str) -> Result<MemoryWrite, String> {
+    terminated(set_command, end_of_line)(strip_comment(line).trim_start())
+        .map(|(_, write)| write)
+        .map_err(|e| parse_error_message(line, &e))
+}
+
 
 // ╔═══════════════════════════════════════════════════════════════════╗ 
 // ║   ⇩ Register Parsing Function                                     ║  
 // ╚═══════════════════════════════════════════════════════════════════╝ 
 
+// 64-bit names only, as used in addresses and register display
 fn register(input: &str) -> IResult<&str, Register> {
-    alt((
-        map_res(tag("rax"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rax)),
-        map_res(tag("rbx"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rbx)),
-        map_res(tag("rcx"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rcx)),
-        map_res(tag("rdx"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rdx)),
-        map_res(tag("rsi"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rsi)),
-        map_res(tag("rdi"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rdi)),
-        map_res(tag("rbp"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rbp)),
-        map_res(tag("rsp"), |_| Ok::<Register, nom::error::Error<&str>>(Register::Rsp)),
-        map_res(tag("r8"),  |_| Ok::<Register, nom::error::Error<&str>>(Register::R8)),
-        map_res(tag("r9"),  |_| Ok::<Register, nom::error::Error<&str>>(Register::R9)),
-        map_res(tag("r10"), |_| Ok::<Register, nom::error::Error<&str>>(Register::R10)),
-        map_res(tag("r11"), |_| Ok::<Register, nom::error::Error<&str>>(Register::R11)),
-        map_res(tag("r12"), |_| Ok::<Register, nom::error::Error<&str>>(Register::R12)),
-        map_res(tag("r13"), |_| Ok::<Register, nom::error::Error<&str>>(Register::R13)),
-        map_res(tag("r14"), |_| Ok::<Register, nom::error::Error<&str>>(Register::R14)),
-        map_res(tag("r15"), |_| Ok::<Register, nom::error::Error<&str>>(Register::R15))
-    ))(input)
+    map(
+        verify(sized_register, |(_, size)| *size == RegisterSize::Qword),
+        |(reg, _)| reg,
+    )(input)
+}
+
+/// Reads a whole register name of any width, in any case, e.g. `rax`, `R8D` or `ah`.
+pub fn parse_register_name(name: &str) -> Option<(Register, RegisterSize)> {
+    match sized_register(name) {
+        Ok(("", register)) => Some(register),
+        _ => None,
+    }
+}
+
+// Any width of any register; the longest matching name wins, so `r8d` isn't read as `r8`
+fn sized_register(input: &str) -> IResult<&str, (Register, RegisterSize)> {
+    RegisterSize::ALL.iter()
+        .flat_map(|&size| Register::ALL.iter().filter_map(move |reg| Some((reg, size, reg.name(size)?))))
+        .filter(|(_, _, name)| input.get(..name.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(name)))
+        // `raxloop` is a label, not `rax` followed by junk
+        .filter(|(_, _, name)| !input[name.len()..].starts_with(is_identifier_char))
+        .max_by_key(|(_, _, name)| name.len())
+        .map(|(reg, size, name)| (&input[name.len()..], (reg.clone(), size)))
+        .ok_or_else(|| nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag)))
 }
 
 
@@ -139,55 +390,223 @@ fn register(input: &str) -> IResult<&str, Register> {
 //║   ⇩ Immediate Value Parser                                        ║  
 //╚═══════════════════════════════════════════════════════════════════╝
 
-fn immediate(input: &str) -> IResult<&str, i32> {
+fn hex_immediate(input: &str) -> IResult<&str, u64> {
+    // Once we've seen `0x` the operand must be hex, so `0xG1` is an error rather than `0` plus junk
+    preceded(
+        alt((tag("0x"), tag("0X"))),
+        cut(map_res(hex_digit1, |s: &str| u64::from_str_radix(s, 16))),
+    )(input)
+}
+
+fn binary_immediate(input: &str) -> IResult<&str, u64> {
+    // Digits are taken greedily and then checked, so `0b102` is rejected rather than read as `0b10`
+    preceded(
+        alt((tag("0b"), tag("0B"))),
+        cut(map_res(digit1, |s: &str| u64::from_str_radix(s, 2))),
+    )(input)
+}
+
+// `'A'` is its ASCII code; `''`, `'ab'` and non-ASCII characters are errors
+fn char_immediate(input: &str) -> IResult<&str, u64> {
+    let escape = map_opt(anychar, |c| match c {
+        'n' => Some(b'\n'),
+        't' => Some(b'\t'),
+        '0' => Some(0),
+        '\'' => Some(b'\''),
+        '\\' => Some(b'\\'),
+        _ => None,
+    });
+    let plain = map_opt(none_of("'\\"), |c: char| u8::try_from(c).ok().filter(u8::is_ascii));
+
+    preceded(
+        char('\''),
+        cut(terminated(map(alt((preceded(char('\\'), escape), plain)), u64::from), char('\''))),
+    )(input)
+}
+
+fn decimal_immediate(input: &str) -> IResult<&str, u64> {
     map_res(digit1, |s: &str| s.parse())(input)
 }
 
+fn unsigned_immediate(input: &str) -> IResult<&str, u64> {
+    alt((hex_immediate, binary_immediate, decimal_immediate, char_immediate))(input)
+}
+
+// Values are kept as 64-bit patterns, so `18446744073709551615` and `-1` are the same immediate.
+// A space after the minus (`- 1`) is accepted; anything else after it is a hard error.
+fn immediate(input: &str) -> IResult<&str, i64> {
+    alt((
+        preceded(
+            tuple((tag("-"), space0)),
+            cut(map_res(unsigned_immediate, |magnitude| {
+                if magnitude <= i64::MIN.unsigned_abs() {
+                    Ok((magnitude as i64).wrapping_neg())
+                } else {
+                    Err("negative immediate out of range")
+                }
+            })),
+        ),
+        map(unsigned_immediate, |value| value as i64),
+    ))(input)
+}
+
 //╔═══════════════════════════════════════════════════════════════════╗ 
 //║   ⇩ Input Parsers                                                 ║  
 //╚═══════════════════════════════════════════════════════════════════╝
 
+/// Drops a trailing `;` or `#` comment, leaving quoted character literals like `';'` alone.
+pub fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '\'' => in_quotes = !in_quotes,
+            ';' | '#' if !in_quotes => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
 pub fn parse_instruction(input: &str) -> IResult<&str, Instruction> {
+    let input = strip_comment(input);
     let (input, _) = space0(input)?; // Optional leading whitespace
     let (input, instruction_type) = parse_instruction_type(input)?;
-    let (input, operands) = parse_operands(input)?;
+    // `nop extra` has trailing text, not an operand the assembler would then reject
+    let (input, operands) = if instruction_type.takes_no_operands() {
+        (input, Vec::new())
+    } else {
+        parse_operands(input)?
+    };
 
     Ok((input, Instruction { instruction_type, operands }))
 }
 
+/// Parses a whole line as one instruction; anything left over besides a comment is an error.
+pub fn parse_complete_instruction(line: &str) -> Result<Instruction, String> {
+    terminated(parse_instruction, end_of_line)(strip_comment(line))
+        .map(|(_, instruction)| instruction)
+        .map_err(|e| parse_error_message(line, &e))
+}
+
+/// Parses a program, resolving label operands to instruction indices.
+///
+/// Label definitions (`loop:`) take up a line of their own and name the instruction after them,
+/// so targets may come before or after the jump that uses them. They aren't part of the result.
+pub fn parse_program(lines: &[String]) -> Result<Vec<Instruction>, String> {
+    // First pass: find where each label points
+    let mut labels = HashMap::new();
+    let mut index = 0;
+    for (i, line) in lines.iter().enumerate() {
+        match parse_label_definition(line) {
+            Some(name) => {
+                if matches!(sized_register(name), Ok(("", _))) {
+                    return Err(format!("Error on line {}: '{}' is a register name and can't be used as a label", i + 1, name));
+                }
+                if labels.insert(name, index).is_some() {
+                    return Err(format!("Error on line {}: label '{}' is already defined", i + 1, name));
+                }
+            }
+            None => index += 1,
+        }
+    }
+
+    // Second pass: parse the instructions and replace each label with its index
+    lines.iter()
+        .enumerate()
+        .filter(|(_, line)| parse_label_definition(line).is_none())
+        .map(|(i, line)| {
+            let mut instruction = parse_complete_instruction(line)
+                .map_err(|e| format!("Error on line {}: {}", i + 1, e))?;
+            // Other instructions keep the name, and the assembler reports it as an unknown operand
+            for operand in instruction.operands.iter_mut().filter(|_| instruction.instruction_type.is_branch()) {
+                if let Operand::Label(name) = operand {
+                    let target = labels.get(name.as_str())
+                        .ok_or_else(|| format!("Error on line {}: undefined label '{}'", i + 1, name))?;
+                    *operand = Operand::Immediate(*target as i64);
+                }
+            }
+            Ok(instruction)
+        })
+        .collect()
+}
+
+/// The lines of a program that hold instructions, i.e. everything but label definitions.
+pub fn instruction_lines(lines: &[String]) -> Vec<String> {
+    lines.iter().filter(|line| parse_label_definition(line).is_none()).cloned().collect()
+}
+
+/// The name defined by a `name:` line, if that's what the line is.
+pub fn parse_label_definition(line: &str) -> Option<&str> {
+    terminated(delimited(space0, identifier, char(':')), end_of_line)(strip_comment(line))
+        .ok()
+        .map(|(_, name)| name)
+}
+
+// Trailing text like `mov rax, 5 garbage` is rejected rather than silently ignored
+fn end_of_line(input: &str) -> IResult<&str, ()> {
+    // multispace0 also takes the `\r` left behind by CRLF line endings
+    map(tuple((multispace0, eof)), |_| ())(input)
+}
+
+/// Turns a parse error into a message pointing at the column where parsing stopped.
+pub fn parse_error_message(line: &str, error: &nom::Err<nom::error::Error<&str>>) -> String {
+    let (nom::Err::Error(e) | nom::Err::Failure(e)) = error else {
+        return "incomplete input".to_string();
+    };
+    // The error's input is always a suffix of some slice of `line`
+    let column = (e.input.as_ptr() as usize).saturating_sub(line.as_ptr() as usize) + 1;
+    let rest = e.input.trim();
+
+    if rest.is_empty() {
+        format!("unexpected end of input at column {}", column)
+    } else if e.code == nom::error::ErrorKind::Eof {
+        format!("unexpected trailing input '{}' at column {}", rest, column)
+    } else {
+        format!("couldn't parse '{}' at column {}", rest, column)
+    }
+}
+
 pub fn parse_input(input: &str) -> IResult<&str, InputType> {
-    alt((
+    let input = strip_comment(input);
+    terminated(preceded(space0, alt((
         map(parse_instruction, InputType::Instruction),
         map(
-            tuple((register, opt(tag("-h")))), // Check for -h flag
+            tuple((register, opt(preceded(space1, tag("-h"))))), // Check for -h flag
             |(reg, human)| InputType::Register(
                 reg,
                 RegisterDisplayOptions { human_readable: human.is_some() },
             ),
         ),
-        map(memory_command, |options| InputType::Memory(options))
-    ))(input)
+        map(memory_command, InputType::Memory)
+    ))), end_of_line)(input)
 }
 
 
+// Up to three operands separated by commas, e.g. `imul rax, rbx, 5`. Without the comma, as in
+// `mov rax 5`, the rest of the line is left over and reported as such.
 fn parse_operands(input: &str) -> IResult<&str, Vec<Operand>> {
     let (input, first_operand) = opt(delimited(space1, operand, space0))(input)?;
-    let (input, second_operand) = opt(delimited(
-        tuple((space0, opt(tag(",")), space0)),
+    let Some(first_operand) = first_operand else {
+        return Ok((input, vec![]));
+    };
+    let (input, rest) = many_m_n(0, 2, delimited(
+        tuple((space0, tag(","), space0)),
         operand,
         space0
     ))(input)?;
 
-    let operands = match (first_operand, second_operand) {
-        (Some(op1), Some(op2)) => vec![op1, op2],
-        (Some(op1), None) => vec![op1],
-        _ => vec![],
-    };
-
-    Ok((input, operands))
+    Ok((input, std::iter::once(first_operand).chain(rest).collect()))
 }
 
 
+// A mnemonic must end at a word boundary, so `jge` isn't read as `jg` followed by a label `e`
+fn mnemonic<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(tag_no_case(name), not(satisfy(is_identifier_char)))
+}
+
 fn parse_instruction_type(input: &str) -> IResult<&str, InstructionType> {
     alt((
         parse_arithmetic_instructions,
@@ -197,6 +616,8 @@ fn parse_instruction_type(input: &str) -> IResult<&str, InstructionType> {
         parse_compare_instructions,
         parse_jump_instructions,
         parse_call_ret_instructions,
+        parse_address_instructions,
+        parse_set_instructions,
         parse_advanced_instructions,
     ))(input)
 }
@@ -207,71 +628,115 @@ fn parse_instruction_type(input: &str) -> IResult<&str, InstructionType> {
 
 fn parse_arithmetic_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("mov"), |_| InstructionType::Mov),
-        map(tag("add"), |_| InstructionType::Add),
-        map(tag("sub"), |_| InstructionType::Sub),
-        map(tag("inc"), |_| InstructionType::Inc),
-        map(tag("dec"), |_| InstructionType::Dec),
-        map(tag("neg"), |_| InstructionType::Neg),
+        map(mnemonic("mov"), |_| InstructionType::Mov),
+        map(mnemonic("add"), |_| InstructionType::Add),
+        map(mnemonic("sub"), |_| InstructionType::Sub),
+        map(mnemonic("adc"), |_| InstructionType::Adc),
+        map(mnemonic("sbb"), |_| InstructionType::Sbb),
+        map(mnemonic("inc"), |_| InstructionType::Inc),
+        map(mnemonic("dec"), |_| InstructionType::Dec),
+        map(mnemonic("neg"), |_| InstructionType::Neg),
+        map(mnemonic("imul"), |_| InstructionType::Imul),
+        map(mnemonic("mul"), |_| InstructionType::Mul),
+        map(mnemonic("div"), |_| InstructionType::Div),
+        map(mnemonic("idiv"), |_| InstructionType::Idiv),
+        map(mnemonic("cdq"), |_| InstructionType::Cdq),
+        map(mnemonic("cqo"), |_| InstructionType::Cqo),
     ))(input)
 }
 
 fn parse_logic_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("and"), |_| InstructionType::And),
-        map(tag("or"),  |_| InstructionType::Or),
-        map(tag("xor"), |_| InstructionType::Xor),
-        map(tag("not"), |_| InstructionType::Not),
+        map(mnemonic("and"), |_| InstructionType::And),
+        map(mnemonic("or"),  |_| InstructionType::Or),
+        map(mnemonic("xor"), |_| InstructionType::Xor),
+        map(mnemonic("not"), |_| InstructionType::Not),
     ))(input)
 }
 
 fn parse_shift_rotate_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("shl"), |_| InstructionType::Shl),
-        map(tag("shr"), |_| InstructionType::Shr),
-        map(tag("rol"), |_| InstructionType::Rol),
-        map(tag("ror"), |_| InstructionType::Ror),
+        map(mnemonic("shl"), |_| InstructionType::Shl),
+        map(mnemonic("shr"), |_| InstructionType::Shr),
+        map(mnemonic("rol"), |_| InstructionType::Rol),
+        map(mnemonic("ror"), |_| InstructionType::Ror),
     ))(input)
 }
 
 fn parse_stack_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("push"), |_| InstructionType::Push),
-        map(tag("pop"), |_| InstructionType::Pop),
+        map(mnemonic("push"), |_| InstructionType::Push),
+        map(mnemonic("pop"), |_| InstructionType::Pop),
     ))(input)
 }
 
 fn parse_compare_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("cmp"), |_| InstructionType::Cmp),
-        map(tag("test"), |_| InstructionType::Test),
+        map(mnemonic("cmp"), |_| InstructionType::Cmp),
+        map(mnemonic("test"), |_| InstructionType::Test),
     ))(input)
 }
 
 fn parse_jump_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("jmp"), |_| InstructionType::Jmp),
-        map(tag("je"), |_| InstructionType::Je),
-        map(tag("jne"), |_| InstructionType::Jne),
-        map(tag("jg"), |_| InstructionType::Jg),
-        map(tag("jge"), |_| InstructionType::Jge),
-        map(tag("jl"), |_| InstructionType::Jl),
-        map(tag("jle"), |_| InstructionType::Jle),
+        map(mnemonic("jmp"), |_| InstructionType::Jmp),
+        map(mnemonic("je"), |_| InstructionType::Je),
+        map(mnemonic("jne"), |_| InstructionType::Jne),
+        map(mnemonic("jg"), |_| InstructionType::Jg),
+        map(mnemonic("jge"), |_| InstructionType::Jge),
+        map(mnemonic("jl"), |_| InstructionType::Jl),
+        map(mnemonic("jle"), |_| InstructionType::Jle),
+        map(mnemonic("loop"), |_| InstructionType::Loop),
     ))(input)
 }
 
+fn parse_set_instructions(input: &str) -> IResult<&str, InstructionType> {
+    alt((
+        map(alt((mnemonic("sete"), mnemonic("setz"))), |_| InstructionType::Sete),
+        map(alt((mnemonic("setne"), mnemonic("setnz"))), |_| InstructionType::Setne),
+        map(mnemonic("setg"), |_| InstructionType::Setg),
+        map(mnemonic("setge"), |_| InstructionType::Setge),
+        map(mnemonic("setl"), |_| InstructionType::Setl),
+        map(mnemonic("setle"), |_| InstructionType::Setle),
+        map(mnemonic("seta"), |_| InstructionType::Seta),
+        map(mnemonic("setae"), |_| InstructionType::Setae),
+        map(mnemonic("setb"), |_| InstructionType::Setb),
+        map(mnemonic("setbe"), |_| InstructionType::Setbe),
+    ))(input)
+}
+
+fn parse_address_instructions(input: &str) -> IResult<&str, InstructionType> {
+    map(mnemonic("lea"), |_| InstructionType::Lea)(input)
+}
+
 fn parse_call_ret_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("call"), |_| InstructionType::Call),
-        map(tag("ret"), |_| InstructionType::Ret),
+        map(mnemonic("call"), |_| InstructionType::Call),
+        map(mnemonic("ret"), |_| InstructionType::Ret),
+        map(mnemonic("syscall"), |_| InstructionType::Syscall),
     ))(input)
 }
 
 fn parse_advanced_instructions(input: &str) -> IResult<&str, InstructionType> {
     alt((
-        map(tag("paddd"), |_| InstructionType::Paddd),
-        map(tag("bsf"), |_| InstructionType::Bsf),
-        map(tag("cmovne"), |_| InstructionType::Cmovne),
+        map(mnemonic("paddd"), |_| InstructionType::Paddd),
+        map(mnemonic("bsf"), |_| InstructionType::Bsf),
+        map(mnemonic("bsr"), |_| InstructionType::Bsr),
+        map(mnemonic("popcnt"), |_| InstructionType::Popcnt),
+        map(mnemonic("lzcnt"), |_| InstructionType::Lzcnt),
+        map(mnemonic("tzcnt"), |_| InstructionType::Tzcnt),
+        map(mnemonic("bswap"), |_| InstructionType::Bswap),
+        map(mnemonic("bt"), |_| InstructionType::Bt),
+        map(mnemonic("bts"), |_| InstructionType::Bts),
+        map(mnemonic("btr"), |_| InstructionType::Btr),
+        map(mnemonic("btc"), |_| InstructionType::Btc),
+        map(mnemonic("cmovne"), |_| InstructionType::Cmovne),
+        map(mnemonic("movzx"), |_| InstructionType::Movzx),
+        map(mnemonic("movsx"), |_| InstructionType::Movsx),
+        map(mnemonic("xchg"), |_| InstructionType::Xchg),
+        map(mnemonic("nop"), |_| InstructionType::Nop),
+        map(mnemonic("cld"), |_| InstructionType::Cld),
+        map(mnemonic("std"), |_| InstructionType::Std),
     ))(input)
 }
 
@@ -282,17 +747,271 @@ fn parse_advanced_instructions(input: &str) -> IResult<&str, InstructionType> {
 
 fn operand(input: &str) -> IResult<&str, Operand> {
     alt((
-        map(register, Operand::Register),
+        map(sized_register, |(reg, size)| Operand::Register(reg, size)),
         map(immediate, Operand::Immediate),
         map(xmm_register, Operand::XmmRegister),
+        memory_operand,
+        map(identifier, |name| Operand::Label(name.to_string())),
     ))(input)
 }
 
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Labels                                                        ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// Label names: letters, digits, `_` and `.`, not starting with a digit
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '_' || c == '.'),
+        take_while(is_identifier_char),
+    ))(input)
+}
+
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Memory Operand                                                ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+#[derive(Debug)]
+enum AddressTerm {
+    Register(Register, u8),
+    Displacement(i64),
+}
+
+fn scale(input: &str) -> IResult<&str, u8> {
+    map_res(digit1, |s: &str| match s {
+        "1" | "2" | "4" | "8" => Ok(s.parse::<u8>().unwrap()),
+        _ => Err("scale must be 1, 2, 4 or 8"),
+    })(input)
+}
+
+fn address_term(input: &str) -> IResult<&str, AddressTerm> {
+    alt((
+        map(
+            // After `*` only a valid scale will do, so `rcx*3` points at the 3 instead of the `*`
+            tuple((register, opt(preceded(tuple((space0, tag("*"), space0)), cut(scale))))),
+            |(reg, scale)| AddressTerm::Register(reg, scale.unwrap_or(1)),
+        ),
+        map(immediate, AddressTerm::Displacement),
+    ))(input)
+}
+
+// Folds `[base + index*scale + disp]` terms in any order; the first unscaled register is the base
+fn build_memory_operand(first: AddressTerm, rest: Vec<(&str, AddressTerm)>) -> Result<Operand, &'static str> {
+    let (mut base, mut index, mut scale, mut disp) = (None, None, 1, 0i64);
+    let terms = std::iter::once(("+", first)).chain(rest);
+
+    for (sign, term) in terms {
+        match term {
+            AddressTerm::Displacement(value) => {
+                disp = if sign == "-" { disp.wrapping_sub(value) } else { disp.wrapping_add(value) };
+            }
+            AddressTerm::Register(_, _) if sign == "-" => return Err("registers cannot be subtracted in an address"),
+            AddressTerm::Register(reg, 1) if base.is_none() => base = Some(reg),
+            AddressTerm::Register(reg, s) if index.is_none() => {
+                index = Some(reg);
+                scale = s;
+            }
+            AddressTerm::Register(_, _) => return Err("too many registers in address"),
+        }
+    }
+
+    Ok(Operand::Memory { base, index, scale, disp, size: None })
+}
+
+// `byte`, `word`, `dword` or `qword`, optionally followed by `ptr`
+fn memory_width(input: &str) -> IResult<&str, RegisterSize> {
+    terminated(
+        alt((
+            map(mnemonic("byte"), |_| RegisterSize::Byte),
+            map(mnemonic("word"), |_| RegisterSize::Word),
+            map(mnemonic("dword"), |_| RegisterSize::Dword),
+            map(mnemonic("qword"), |_| RegisterSize::Qword),
+        )),
+        tuple((space0, opt(terminated(mnemonic("ptr"), space0)))),
+    )(input)
+}
+
+fn memory_operand(input: &str) -> IResult<&str, Operand> {
+    let (input, size) = opt(memory_width)(input)?;
+    let (input, mut operand) = memory_address(input)?;
+    if let Operand::Memory { size: operand_size, .. } = &mut operand {
+        *operand_size = size;
+    }
+    Ok((input, operand))
+}
+
+// Once `[` is seen the operand must be a well-formed address, so `[rbx+]` is an error rather than no operand
+fn memory_address(input: &str) -> IResult<&str, Operand> {
+    preceded(
+        tuple((tag("["), space0)),
+        cut(map_res(
+            terminated(
+                tuple((
+                    address_term,
+                    many0(tuple((delimited(space0, alt((tag("+"), tag("-"))), space0), address_term))),
+                )),
+                tuple((space0, tag("]"))),
+            ),
+            |(first, rest)| build_memory_operand(first, rest),
+        )),
+    )(input)
+}
+
 //╔═══════════════════════════════════════════════════════════════════╗ 
 //║   ⇩ XMM Register                                                  ║  
 //╚═══════════════════════════════════════════════════════════════════╝
 
 fn xmm_register(input: &str) -> IResult<&str, u8> {
-    let (input, _) = tag("xmm")(input)?;
+    let (input, _) = tag_no_case("xmm")(input)?;
     map_res(digit1, |s: &str| s.parse::<u8>())(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operands_need_commas() {
+        assert_eq!(parse_complete_instruction("mov rax 5 junk").unwrap_err(),
+                   "unexpected trailing input '5 junk' at column 9");
+        assert_eq!(parse_complete_instruction("mov rax, 5 junk").unwrap_err(),
+                   "unexpected trailing input 'junk' at column 12");
+    }
+
+    #[test]
+    fn operandless_instructions_reject_trailing_text() {
+        assert_eq!(parse_complete_instruction("nop extra").unwrap_err(),
+                   "unexpected trailing input 'extra' at column 5");
+        assert!(parse_complete_instruction("ret 5").is_err());
+        assert!(parse_complete_instruction("cqo rax").is_err());
+    }
+
+    #[test]
+    fn parses_complete_instructions() {
+        assert_eq!(parse_complete_instruction("nop ; comment"),
+                   Ok(Instruction { instruction_type: InstructionType::Nop, operands: vec![] }));
+        assert_eq!(parse_complete_instruction("mov\trax ,\t5"), Ok(Instruction {
+            instruction_type: InstructionType::Mov,
+            operands: vec![Operand::Register(Register::Rax, RegisterSize::Qword), Operand::Immediate(5)],
+        }));
+        assert_eq!(parse_complete_instruction("imul rax, rbx, 5").unwrap().operands.len(), 3);
+    }
+
+    fn source_operand(line: &str) -> Result<Operand, String> {
+        parse_complete_instruction(line).map(|instruction| instruction.operands[1].clone())
+    }
+
+    #[test]
+    fn hex_immediates() {
+        assert_eq!(source_operand("mov rax, 0x1F"), Ok(Operand::Immediate(31)));
+        assert_eq!(source_operand("mov rax, 0XfF"), Ok(Operand::Immediate(255)));
+        assert_eq!(source_operand("mov rax, 0xffffffffffffffff"), Ok(Operand::Immediate(-1)));
+        assert!(source_operand("mov rax, 0xG1").is_err());
+    }
+
+    #[test]
+    fn negative_immediates() {
+        assert_eq!(source_operand("mov rax, -5"), Ok(Operand::Immediate(-5)));
+        assert_eq!(source_operand("mov rax, -0x10"), Ok(Operand::Immediate(-16)));
+        assert_eq!(source_operand("add rax, -9223372036854775808"), Ok(Operand::Immediate(i64::MIN)));
+        assert!(source_operand("mov rax, --5").is_err());
+    }
+
+    #[test]
+    fn binary_immediates() {
+        assert_eq!(source_operand("mov rax, 0b101"), Ok(Operand::Immediate(5)));
+        assert_eq!(source_operand("mov rax, 0B11"), Ok(Operand::Immediate(3)));
+        assert!(source_operand("mov rax, 0b102").is_err());
+        assert!(source_operand("mov rax, 0b").is_err());
+    }
+
+    #[test]
+    fn mnemonics_and_registers_ignore_case() {
+        assert_eq!(parse_complete_instruction("MOV RAX, 5"), parse_complete_instruction("mov rax, 5"));
+        assert_eq!(parse_complete_instruction("Xor Eax, eAX"), parse_complete_instruction("xor eax, eax"));
+        assert_eq!(parse_register_name("AH"), Some((Register::Rax, RegisterSize::HighByte)));
+    }
+
+    #[test]
+    fn character_literals() {
+        assert_eq!(source_operand("mov rax, 'A'"), Ok(Operand::Immediate(65)));
+        assert_eq!(source_operand("mov al, '\\n'"), Ok(Operand::Immediate(10)));
+        assert_eq!(source_operand("mov al, '\\t'"), Ok(Operand::Immediate(9)));
+        assert!(source_operand("mov rax, 'ab'").is_err());
+        assert!(source_operand("mov rax, ''").is_err());
+    }
+
+    #[test]
+    fn inline_comments() {
+        assert_eq!(strip_comment("mov rax, 1 ; set up"), "mov rax, 1 ");
+        assert_eq!(strip_comment("add rax, rbx # total"), "add rax, rbx ");
+        assert_eq!(strip_comment("mov al, ';' ; a semicolon"), "mov al, ';' ");
+        assert_eq!(strip_comment("mov al, '\\'' ; a quote"), "mov al, '\\'' ");
+        assert_eq!(source_operand("mov al, '#' # a hash"), Ok(Operand::Immediate(35)));
+    }
+
+    #[test]
+    fn memory_operands() {
+        let memory = |base, disp| Operand::Memory { base, index: None, scale: 1, disp, size: None };
+        assert_eq!(source_operand("mov rax, [rbx+8]"), Ok(memory(Some(Register::Rbx), 8)));
+        assert_eq!(source_operand("mov rax, [ rbp - 0x10 ]"), Ok(memory(Some(Register::Rbp), -16)));
+        assert_eq!(source_operand("mov rax, [0x100]"), Ok(memory(None, 0x100)));
+        assert_eq!(source_operand("mov rax, qword [rbx+rcx*4+8]"), Ok(Operand::Memory {
+            base: Some(Register::Rbx), index: Some(Register::Rcx), scale: 4, disp: 8, size: Some(RegisterSize::Qword),
+        }));
+        assert!(source_operand("mov rax, [rbx+]").is_err());
+        assert!(source_operand("mov rax, [rbx").is_err());
+    }
+
+    #[test]
+    fn labels_resolve_to_instruction_indices() {
+        let lines: Vec<String> = ["jmp end", "nop", "end:", "ret"].map(String::from).to_vec();
+        let program = parse_program(&lines).unwrap();
+        assert_eq!(program[0].operands, vec![Operand::Immediate(2)]);
+        assert!(parse_program(&["jmp nowhere".to_string()]).is_err());
+    }
+
+    #[test]
+    fn register_names_at_every_width() {
+        assert_eq!(parse_register_name("AH"), Some((Register::Rax, RegisterSize::HighByte)));
+        assert_eq!(parse_register_name("r8d"), Some((Register::R8, RegisterSize::Dword)));
+        assert_eq!(parse_register_name("r15"), Some((Register::R15, RegisterSize::Qword)));
+        assert_eq!(parse_register_name("r1"), None);
+        assert_eq!(parse_register_name("raxx"), None);
+        // Not a register, so the assembler can say so
+        assert_eq!(source_operand("mov rax, r1"), Ok(Operand::Label("r1".to_string())));
+    }
+
+    #[test]
+    fn tabs_and_line_endings_are_whitespace() {
+        let expected = parse_complete_instruction("mov rax, 5").unwrap();
+        assert_eq!(parse_complete_instruction("\tmov\trax,\t5"), Ok(expected.clone()));
+        assert_eq!(parse_complete_instruction("  mov rax, 5 \r\n"), Ok(expected));
+        assert!(matches!(parse_input("\tmov rax, 5\r"), Ok(("", InputType::Instruction(_)))));
+    }
+
+    #[test]
+    fn memory_writes() {
+        assert_eq!(parse_memory_write("set 0x100 dword -2"), Ok(MemoryWrite { address: 0x100, size: RegisterSize::Dword, value: -2 }));
+        assert_eq!(parse_memory_write("set 0x8 byte 'A' ; a letter"), Ok(MemoryWrite { address: 8, size: RegisterSize::Byte, value: 65 }));
+        assert!(parse_memory_write("set 0x100 5").is_err());
+        assert!(parse_memory_write("set 0x100 word 1 2").is_err());
+    }
+
+    #[test]
+    fn memory_dump_flags() {
+        let dump = |line| match parse_input(line) {
+            Ok((_, InputType::Memory(options))) => Some((options.address, options.size, options.format)),
+            _ => None,
+        };
+        assert_eq!(dump("memory 0x100"), Some((0x100, 16, MemoryDumpFormat::Hex)));
+        assert_eq!(dump("memory 0x100 -s 20 -d"), Some((0x100, 20, MemoryDumpFormat::Decimal)));
+        assert_eq!(dump("memory 0x0 --binary"), Some((0, 16, MemoryDumpFormat::Binary)));
+        assert_eq!(dump("memory 0x0 -c"), Some((0, 16, MemoryDumpFormat::Char)));
+        assert_eq!(MemoryDumpFormat::Binary.format_byte(5), "00000101");
+    }
+}
@@ -1,10 +1,18 @@
 use crate::cpu::CPU;
+use crate::expr::{self, ExprError};
 use std::collections::HashMap;
 
-type ScriptFunction = fn(&[&str], &CPU, &mut HashMap<String, u64>) -> Result<String, String>;
+type ScriptFunction = fn(&[&str], &mut CPU, &mut HashMap<String, u64>) -> Result<String, String>;
+
+// A function defined at runtime with `:defun name arg => expression`
+struct UserDefinedFunction {
+    params: Vec<String>,
+    body: String,
+}
 
 pub struct ScriptEnvironment {
     functions: HashMap<String, ScriptFunction>,
+    user_defined: HashMap<String, UserDefinedFunction>,
     variables: HashMap<String, u64>,
 }
 
@@ -12,6 +20,7 @@ impl ScriptEnvironment {
     pub fn new() -> Self {
         let mut env = ScriptEnvironment {
             functions: HashMap::new(),
+            user_defined: HashMap::new(),
             variables: HashMap::new(),
         };
         env.load_default_functions();
@@ -35,14 +44,19 @@ impl ScriptEnvironment {
         self.add_function("↑", |args, cpu, vars| shift(args, cpu, vars, true));
         self.add_function("↓", |args, cpu, vars| shift(args, cpu, vars, false));
         self.add_function("?", memory_operation);
+        self.add_function("@", memory_access);
         self.add_function("ι", range);
+        self.add_function("sum", |args, cpu, vars| aggregate(args, cpu, vars, "sum"));
+        self.add_function("min", |args, cpu, vars| aggregate(args, cpu, vars, "min"));
+        self.add_function("max", |args, cpu, vars| aggregate(args, cpu, vars, "max"));
+        self.add_function("avg", |args, cpu, vars| aggregate(args, cpu, vars, "avg"));
     }
 
     pub fn add_function(&mut self, name: &str, func: ScriptFunction) {
         self.functions.insert(name.to_string(), func);
     }
 
-    pub fn execute_script(&mut self, script: &str, cpu: &CPU) -> Result<String, String> {
+    pub fn execute_script(&mut self, script: &str, cpu: &mut CPU) -> Result<String, String> {
         let lines: Vec<&str> = script.lines().collect();
         let mut output = String::new();
 
@@ -55,7 +69,7 @@ impl ScriptEnvironment {
         Ok(output)
     }
 
-    fn execute_line(&mut self, line: &str, cpu: &CPU) -> Result<String, String> {
+    fn execute_line(&mut self, line: &str, cpu: &mut CPU) -> Result<String, String> {
         if line.is_empty() || line.starts_with("//") {
             return Ok(String::new());
         }
@@ -68,14 +82,65 @@ impl ScriptEnvironment {
         let function_name = tokens[0];
         let args = &tokens[1..];
 
+        match function_name {
+            ":defun" => return self.define_function(&line[":defun".len()..], false),
+            ":defun!" => return self.define_function(&line[":defun!".len()..], true),
+            _ => {}
+        }
+
+        // User-defined functions shadow builtins that were redefined with `:defun!`
+        if let Some(func) = self.user_defined.get(function_name) {
+            return call_user_defined(function_name, func, args, &self.variables);
+        }
+
         match self.functions.get(function_name) {
             Some(func) => func(args, cpu, &mut self.variables),
             None => Err(format!("Unknown function: {}", function_name)),
         }
     }
+
+    fn define_function(&mut self, definition: &str, force: bool) -> Result<String, String> {
+        let (signature, body) = definition.split_once("=>")
+            .ok_or("Usage: :defun <name> <arg>... => <expression>".to_string())?;
+        let mut signature = signature.split_whitespace();
+        let name = signature.next()
+            .ok_or("Usage: :defun <name> <arg>... => <expression>".to_string())?;
+        let params: Vec<String> = signature.map(|param| param.to_string()).collect();
+        let body = body.trim();
+
+        if body.is_empty() {
+            return Err("Function body cannot be empty".to_string());
+        }
+        if self.functions.contains_key(name) && !force {
+            return Err(format!("'{}' is a builtin; use :defun! to redefine it", name));
+        }
+
+        // Evaluate once with every parameter bound to 0 so typos surface now rather than at call time
+        let mut scope = self.variables.clone();
+        scope.extend(params.iter().map(|param| (param.clone(), 0)));
+        match evaluate_expression(body, &scope) {
+            Ok(_) | Err(ExprError::DivisionByZero) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+
+        self.user_defined.insert(name.to_string(), UserDefinedFunction { params: params.clone(), body: body.to_string() });
+        Ok(format!("Defined {}({}) => {}", name, params.join(", "), body))
+    }
+}
+
+fn call_user_defined(name: &str, func: &UserDefinedFunction, args: &[&str], vars: &HashMap<String, u64>) -> Result<String, String> {
+    if args.len() != func.params.len() {
+        return Err(format!("{} expects {} argument(s), got {}", name, func.params.len(), args.len()));
+    }
+    let mut scope = vars.clone();
+    for (param, arg) in func.params.iter().zip(args) {
+        scope.insert(param.clone(), get_value(arg, vars)?);
+    }
+    let result = evaluate_expression(&func.body, &scope).map_err(|e| e.to_string())?;
+    Ok(format!("Result: {}", result))
 }
 
-fn decimal(args: &[&str], cpu: &CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
+fn decimal(args: &[&str], cpu: &mut CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
     if args.len() != 1 {
         return Err("Usage: decimal <register>".to_string());
     }
@@ -101,16 +166,16 @@ fn decimal(args: &[&str], cpu: &CPU, _vars: &mut HashMap<String, u64>) -> Result
     }
 }
 
-fn assignment(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>) -> Result<String, String> {
-    if args.len() != 2 {
+fn assignment(args: &[&str], _cpu: &mut CPU, vars: &mut HashMap<String, u64>) -> Result<String, String> {
+    if args.len() < 2 {
         return Err("Invalid assignment syntax".to_string());
     }
-    let value = evaluate_expression(args[1], vars)?;
+    let value = evaluate_expression(&args[1..].join(" "), vars).map_err(|e| e.to_string())?;
     vars.insert(args[0].to_string(), value);
     Ok(format!("{} ← {}", args[0], value))
 }
 
-fn arithmetic(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>, op: char) -> Result<String, String> {
+fn arithmetic(args: &[&str], _cpu: &mut CPU, vars: &mut HashMap<String, u64>, op: char) -> Result<String, String> {
     if args.len() != 2 {
         return Err("Invalid arithmetic syntax".to_string());
     }
@@ -120,13 +185,13 @@ fn arithmetic(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>, op: ch
         '+' => a.wrapping_add(b),
         '-' => a.wrapping_sub(b),
         '*' => a.wrapping_mul(b),
-        '/' => if b == 0 { return Err("Division by zero".to_string()); } else { a / b },
+        '/' => a.checked_div(b).ok_or("Division by zero".to_string())?,
         _ => return Err("Unknown arithmetic operation".to_string()),
     };
     Ok(format!("Result: {}", result))
 }
 
-fn bitwise(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>, op: char) -> Result<String, String> {
+fn bitwise(args: &[&str], _cpu: &mut CPU, vars: &mut HashMap<String, u64>, op: char) -> Result<String, String> {
     if args.len() != 2 {
         return Err("Invalid bitwise syntax".to_string());
     }
@@ -141,7 +206,7 @@ fn bitwise(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>, op: char)
     Ok(format!("Result: {:#x}", result))
 }
 
-fn rotate(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>) -> Result<String, String> {
+fn rotate(args: &[&str], _cpu: &mut CPU, vars: &mut HashMap<String, u64>) -> Result<String, String> {
     if args.len() != 2 {
         return Err("Invalid rotate syntax".to_string());
     }
@@ -151,44 +216,139 @@ fn rotate(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>) -> Result<
     Ok(format!("Result: {:#x}", result))
 }
 
-fn shift(args: &[&str], _cpu: &CPU, vars: &mut HashMap<String, u64>, left: bool) -> Result<String, String> {
+fn shift(args: &[&str], _cpu: &mut CPU, vars: &mut HashMap<String, u64>, left: bool) -> Result<String, String> {
     if args.len() != 2 {
         return Err("Invalid shift syntax".to_string());
     }
     let value = get_value(args[0], vars)?;
     let shift: u32 = args[1].parse().map_err(|_| "Invalid shift amount".to_string())?;
-    let result = if left { value << shift } else { value >> shift };
+    // The count is masked to 6 bits, as for `shl rax, cl`
+    let result = if left { value.wrapping_shl(shift) } else { value.wrapping_shr(shift) };
     Ok(format!("Result: {:#x}", result))
 }
 
-fn memory_operation(args: &[&str], cpu: &CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
-    if args.len() != 1 {
-        return Err("Invalid memory operation syntax".to_string());
+// `? <address>` is shorthand for `@ byte <address>`
+fn memory_operation(args: &[&str], cpu: &mut CPU, vars: &mut HashMap<String, u64>) -> Result<String, String> {
+    match args {
+        [address] => memory_access(&["byte", address], cpu, vars),
+        _ => Err("Usage: ? <address>".to_string()),
     }
-    let address: usize = args[0].parse().map_err(|_| "Invalid memory address".to_string())?;
-    if address >= cpu.memory.len() {
-        return Err("Memory address out of bounds".to_string());
+}
+
+fn memory_access(args: &[&str], cpu: &mut CPU, vars: &mut HashMap<String, u64>) -> Result<String, String> {
+    const USAGE: &str = "Usage: @ [byte|word|dword|qword] <address> [value]";
+
+    let (width, size, args) = match args.first().copied() {
+        Some("byte") => ("byte", 1, &args[1..]),
+        Some("word") => ("word", 2, &args[1..]),
+        Some("dword") => ("dword", 4, &args[1..]),
+        Some("qword") => ("qword", 8, &args[1..]),
+        _ => ("qword", 8, args),
+    };
+
+    match args {
+        [address] => {
+            let address = get_value(address, vars)?;
+            let value = cpu.read_memory_sized(address, size).map_err(|e| e.to_string())?;
+            Ok(format!("Value at address {:#x} ({}): {:#x}", address, width, value))
+        }
+        [address, value] => {
+            let address = get_value(address, vars)?;
+            let value = get_value(value, vars)?;
+            cpu.write_memory_sized(address, value, size).map_err(|e| e.to_string())?;
+            Ok(format!("[{:#x}] ← {:#x} ({})", address, value, width))
+        }
+        _ => Err(USAGE.to_string()),
     }
-    Ok(format!("Value at address {:#x}: {:#x}", address, cpu.memory[address]))
 }
 
-fn range(args: &[&str], _cpu: &CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
-    if args.len() != 1 && args.len() != 2 {
-        return Err("Invalid range syntax".to_string());
+fn range(args: &[&str], _cpu: &mut CPU, vars: &mut HashMap<String, u64>) -> Result<String, String> {
+    let range = parse_range(args, vars)?;
+    Ok(format!("Range: {:?}", range))
+}
+
+// Accepts either `sum ι 0 10 2` or the bare range arguments `sum 0 10 2`
+fn aggregate(args: &[&str], _cpu: &mut CPU, vars: &mut HashMap<String, u64>, op: &str) -> Result<String, String> {
+    let args = match args.first() {
+        Some(&"ι") => &args[1..],
+        _ => args,
+    };
+    let range = parse_range(args, vars)?;
+
+    if op == "sum" {
+        let (sum, overflowed) = range.iter()
+            .fold((0u64, false), |(acc, overflowed), &x| {
+                let (sum, overflow) = acc.overflowing_add(x);
+                (sum, overflowed || overflow)
+            });
+        return if overflowed {
+            Ok(format!("Result: {} (overflowed, wrapped modulo 2^64)", sum))
+        } else {
+            Ok(format!("Result: {}", sum))
+        };
+    }
+
+    if range.is_empty() {
+        return Err(format!("Cannot take the {} of an empty range", op));
     }
-    let start = if args.len() == 2 {
-        args[0].parse().map_err(|_| "Invalid start value".to_string())?
-    } else {
-        0
+    match op {
+        "min" => Ok(format!("Result: {}", range.iter().min().unwrap())),
+        "max" => Ok(format!("Result: {}", range.iter().max().unwrap())),
+        "avg" => {
+            // Sum in u128 so the average stays exact even when the u64 sum would wrap
+            let total: u128 = range.iter().map(|&x| x as u128).sum();
+            let len = range.len() as u128;
+            if total.is_multiple_of(len) {
+                Ok(format!("Result: {}", total / len))
+            } else {
+                Ok(format!("Result: {}", total as f64 / len as f64))
+            }
+        }
+        _ => Err(format!("Unknown aggregation: {}", op)),
+    }
+}
+
+// Keeps a typo like `ι 0 99999999999` from allocating gigabytes
+const MAX_RANGE_LEN: usize = 1 << 16;
+
+/// Parses `<end>`, `<start> <end>` or `<start> <end> <stride>` into the values of the range.
+fn parse_range(args: &[&str], vars: &HashMap<String, u64>) -> Result<Vec<u64>, String> {
+    let (start, end, stride) = match args {
+        [end] => (0, get_value(end, vars)?, 1),
+        [start, end] => (get_value(start, vars)?, get_value(end, vars)?, 1),
+        [start, end, stride] => {
+            let stride: i64 = stride.parse().map_err(|_| "Invalid stride".to_string())?;
+            (get_value(start, vars)?, get_value(end, vars)?, stride)
+        }
+        _ => return Err("Usage: ι <end> | ι <start> <end> [stride]".to_string()),
     };
-    let end: u64 = args[args.len() - 1].parse().map_err(|_| "Invalid end value".to_string())?;
-    let range: Vec<u64> = (start..end).collect();
-    Ok(format!("Range: {:?}", range))
+    build_range(start, end, stride)
 }
 
+/// Builds `start..end` (exclusive) stepping by `stride`, counting down when `start > end`.
+fn build_range(start: u64, end: u64, stride: i64) -> Result<Vec<u64>, String> {
+    if stride == 0 {
+        return Err("Range stride cannot be zero".to_string());
+    }
+    if stride < 0 && start < end {
+        return Err("Negative stride requires start > end".to_string());
+    }
+
+    let step = stride.unsigned_abs();
+    let len = (start.abs_diff(end)).div_ceil(step);
+    if len > MAX_RANGE_LEN as u64 {
+        return Err(format!("Range too large ({} elements, max {})", len, MAX_RANGE_LEN));
+    }
+
+    Ok((0..len)
+        .map(|i| if start <= end { start + i * step } else { start - i * step })
+        .collect())
+}
 
 fn get_value(token: &str, vars: &HashMap<String, u64>) -> Result<u64, String> {
-    if let Ok(value) = token.parse::<u64>() {
+    if let Some(hex) = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).map_err(|_| format!("Invalid hex literal: {}", token))
+    } else if let Ok(value) = token.parse::<u64>() {
         Ok(value)
     } else if let Some(value) = vars.get(token) {
         Ok(*value)
@@ -197,8 +357,78 @@ fn get_value(token: &str, vars: &HashMap<String, u64>) -> Result<u64, String> {
     }
 }
 
-fn evaluate_expression(expr: &str, vars: &HashMap<String, u64>) -> Result<u64, String> {
-    // Implement expression evaluation here
-    // For now, just return the value directly
-    get_value(expr, vars)
-}
\ No newline at end of file
+//╔═══════════════════════════════════════════════════════════════════╗ 
+//║   ⇩ Expression Evaluator                                          ║  
+//╚═══════════════════════════════════════════════════════════════════╝
+
+/// Evaluates an expression like `x × (y + 1)` over script variables, with the calculator's
+/// evaluator: the values are 64-bit patterns either way.
+pub fn evaluate_expression(expr: &str, vars: &HashMap<String, u64>) -> Result<u64, ExprError> {
+    let resolve = |name: &str| vars.get(name)
+        .map(|&value| value as i64)
+        .ok_or_else(|| format!("Unknown variable: {}", name));
+    expr::evaluate_with(expr, &resolve).map(|value| value as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(env: &mut ScriptEnvironment, cpu: &mut CPU, line: &str) -> Result<String, String> {
+        env.execute_script(line, cpu).map(|output| output.trim_end().to_string())
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        let (mut env, mut cpu) = (ScriptEnvironment::new(), CPU::new());
+        run(&mut env, &mut cpu, "@ dword 0x100 0x12345678").unwrap();
+        assert_eq!(run(&mut env, &mut cpu, "@ dword 0x100").unwrap(), "Value at address 0x100 (dword): 0x12345678");
+        assert_eq!(run(&mut env, &mut cpu, "@ byte 0x101").unwrap(), "Value at address 0x101 (byte): 0x56");
+    }
+
+    #[test]
+    fn question_mark_reads_a_byte_like_at() {
+        let (mut env, mut cpu) = (ScriptEnvironment::new(), CPU::new());
+        run(&mut env, &mut cpu, "@ byte 0x10 0xab").unwrap();
+        assert_eq!(run(&mut env, &mut cpu, "? 0x10"), run(&mut env, &mut cpu, "@ byte 0x10"));
+        assert_eq!(run(&mut env, &mut cpu, "? 16").unwrap(), "Value at address 0x10 (byte): 0xab");
+        assert!(run(&mut env, &mut cpu, "? 0x100000").is_err());
+    }
+
+    #[test]
+    fn user_defined_functions() {
+        let (mut env, mut cpu) = (ScriptEnvironment::new(), CPU::new());
+        assert_eq!(run(&mut env, &mut cpu, ":defun sq x => x × x").unwrap(), "Defined sq(x) => x × x");
+        assert_eq!(run(&mut env, &mut cpu, "sq 5").unwrap(), "Result: 25");
+        run(&mut env, &mut cpu, ":defun f a b => (a + b) ÷ 2").unwrap();
+        assert_eq!(run(&mut env, &mut cpu, "f 3 7").unwrap(), "Result: 5");
+        // Dividing by a parameter is only checked when the function is called
+        run(&mut env, &mut cpu, ":defun g a b => a ÷ b").unwrap();
+        assert_eq!(run(&mut env, &mut cpu, "g 1 0"), Err("Division by zero".to_string()));
+    }
+
+    #[test]
+    fn ranges_with_strides() {
+        let (mut env, mut cpu) = (ScriptEnvironment::new(), CPU::new());
+        assert_eq!(run(&mut env, &mut cpu, "ι 5").unwrap(), "Range: [0, 1, 2, 3, 4]");
+        assert_eq!(run(&mut env, &mut cpu, "ι 0 10 3").unwrap(), "Range: [0, 3, 6, 9]");
+        assert_eq!(run(&mut env, &mut cpu, "ι 10 0 4").unwrap(), "Range: [10, 6, 2]");
+        assert_eq!(run(&mut env, &mut cpu, "ι 0 5 0"), Err("Range stride cannot be zero".to_string()));
+    }
+
+    #[test]
+    fn aggregates_ranges() {
+        let (mut env, mut cpu) = (ScriptEnvironment::new(), CPU::new());
+        assert_eq!(run(&mut env, &mut cpu, "sum ι 1 11").unwrap(), "Result: 55");
+        assert_eq!(run(&mut env, &mut cpu, "avg 0 10 2").unwrap(), "Result: 4");
+        assert_eq!(run(&mut env, &mut cpu, "min 3 9").unwrap(), "Result: 3");
+        assert_eq!(run(&mut env, &mut cpu, "max ι 4").unwrap(), "Result: 3");
+    }
+
+    #[test]
+    fn shifts_mask_the_count() {
+        let (mut env, mut cpu) = (ScriptEnvironment::new(), CPU::new());
+        assert_eq!(run(&mut env, &mut cpu, "↑ 1 64").unwrap(), "Result: 0x1");
+        assert_eq!(run(&mut env, &mut cpu, "↓ 8 65").unwrap(), "Result: 0x4");
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::mem;
+
+use crate::cpu::CPU;
+
+/// Named forks of the CPU, like branches: one is live and the rest wait to be switched to.
+/// Forks share memory copy-on-write, so cloning is cheap until one of them writes.
+pub struct Sessions {
+    current: String,
+    others: BTreeMap<String, CPU>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Sessions { current: "main".to_string(), others: BTreeMap::new() }
+    }
+
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// Forks the live CPU into a new session without switching to it.
+    pub fn clone_session(&mut self, name: &str, cpu: &CPU) -> Result<(), String> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err("Session names must be a single word".to_string());
+        }
+        if name == self.current || self.others.contains_key(name) {
+            return Err(format!("A session named '{}' already exists", name));
+        }
+        self.others.insert(name.to_string(), cpu.clone());
+        Ok(())
+    }
+
+    /// Parks the live CPU under the current name and makes `name` live.
+    pub fn switch(&mut self, name: &str, cpu: &mut CPU) -> Result<(), String> {
+        if name == self.current {
+            return Err(format!("Already in session '{}'", name));
+        }
+        let target = self.others.remove(name).ok_or_else(|| format!("No session named '{}'", name))?;
+        let previous = mem::replace(&mut self.current, name.to_string());
+        self.others.insert(previous, mem::replace(cpu, target));
+        Ok(())
+    }
+
+    /// Every session name with its CPU, the live one taken from `cpu`.
+    pub fn list<'a>(&'a self, cpu: &'a CPU) -> Vec<(&'a str, &'a CPU, bool)> {
+        let mut sessions: Vec<_> = self.others.iter()
+            .map(|(name, snapshot)| (name.as_str(), snapshot, false))
+            .chain(std::iter::once((self.current.as_str(), cpu, true)))
+            .collect();
+        sessions.sort_by_key(|(name, _, _)| *name);
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switching_keeps_each_session_separate() {
+        let (mut sessions, mut cpu) = (Sessions::new(), CPU::new());
+        cpu.rax = 1;
+        sessions.clone_session("experiment", &cpu).unwrap();
+        cpu.rax = 2;
+        sessions.switch("experiment", &mut cpu).unwrap();
+        assert_eq!((sessions.current(), cpu.rax), ("experiment", 1));
+        sessions.switch("main", &mut cpu).unwrap();
+        assert_eq!((sessions.current(), cpu.rax), ("main", 2));
+
+        let listed: Vec<_> = sessions.list(&cpu).into_iter().map(|(name, cpu, live)| (name, cpu.rax, live)).collect();
+        assert_eq!(listed, vec![("experiment", 1, false), ("main", 2, true)]);
+    }
+
+    #[test]
+    fn rejects_bad_and_duplicate_names() {
+        let (mut sessions, mut cpu) = (Sessions::new(), CPU::new());
+        assert!(sessions.clone_session("two words", &cpu).is_err());
+        assert!(sessions.clone_session("main", &cpu).is_err());
+        assert!(sessions.switch("main", &mut cpu).is_err());
+        assert_eq!(sessions.switch("missing", &mut cpu), Err("No session named 'missing'".to_string()));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::cpu::{CPU, MEMORY_SIZE};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs;
+use std::rc::Rc;
+
+// Zero gaps shorter than this stay inside a run, so a value like 0x0100 isn't split in two
+const MAX_GAP: usize = 8;
+
+/// A stretch of memory that isn't all zeros; the rest of memory is zero-filled.
+#[derive(Serialize, Deserialize)]
+struct MemoryRun {
+    address: u64,
+    bytes: String, // Hex, two digits per byte
+}
+
+/// Writes the whole CPU state to a JSON file, for sharing or reproducing a bug report.
+pub fn save_state(cpu: &CPU, path: &str) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(cpu).map_err(|e| format!("Can't serialize the CPU state: {}", e))?;
+    fs::write(path, json + "\n").map_err(|e| format!("Can't write {}: {}", path, e))
+}
+
+/// Reads a file written by `save_state`. The individual flags are what counts, so `rflags` is rebuilt from them.
+pub fn load_state(path: &str) -> Result<CPU, String> {
+    let json = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let mut cpu: CPU = serde_json::from_str(&json).map_err(|e| format!("{} is not a saved CPU state: {}", path, e))?;
+    cpu.recompute_rflags();
+    Ok(cpu)
+}
+
+pub fn serialize_memory<S: Serializer>(memory: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    memory_runs(memory).serialize(serializer)
+}
+
+// Expands the runs back into a full, zero-filled memory
+pub fn deserialize_memory<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rc<Vec<u8>>, D::Error> {
+    let mut memory = vec![0; MEMORY_SIZE];
+    for run in Vec::<MemoryRun>::deserialize(deserializer)? {
+        let bytes = decode_hex(&run.bytes)
+            .ok_or_else(|| D::Error::custom(format!("the memory at {:#x} is not valid hex", run.address)))?;
+        let (start, end) = usize::try_from(run.address).ok()
+            .and_then(|start| Some((start, start.checked_add(bytes.len())?)))
+            .filter(|&(_, end)| end <= MEMORY_SIZE)
+            .ok_or_else(|| D::Error::custom(format!("the memory at {:#x} runs past the end of memory", run.address)))?;
+        memory[start..end].copy_from_slice(&bytes);
+    }
+    Ok(Rc::new(memory))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn memory_runs(memory: &[u8]) -> Vec<MemoryRun> {
+    let mut runs = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = memory[position..].iter().position(|&byte| byte != 0) {
+        let start = position + offset;
+        let mut end = start + 1;
+        // Extend past each nonzero byte that's close enough to the end of the run so far
+        while let Some(gap) = memory[end..].iter().take(MAX_GAP).position(|&byte| byte != 0) {
+            end += gap + 1;
+        }
+        let bytes = memory[start..end].iter().map(|byte| format!("{:02x}", byte)).collect();
+        runs.push(MemoryRun { address: start as u64, bytes });
+        position = end;
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_gaps_stay_inside_a_run() {
+        let mut memory = vec![0; 32];
+        memory[2] = 1;
+        memory[6] = 2;
+        memory[20] = 3;
+        let runs: Vec<_> = memory_runs(&memory).into_iter().map(|run| (run.address, run.bytes)).collect();
+        assert_eq!(runs, vec![(2, "0100000002".to_string()), (20, "03".to_string())]);
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut cpu = CPU::new();
+        cpu.rax = 0x1234;
+        cpu.zf = true;
+        cpu.recompute_rflags();
+        cpu.write_memory_sized(0x100, 0x0100, 2).unwrap();
+        cpu.write_memory_sized(0x8000, 0xff, 1).unwrap();
+
+        let json = serde_json::to_string(&cpu).unwrap();
+        assert_eq!(serde_json::from_str::<CPU>(&json).unwrap(), cpu);
+    }
+
+    #[test]
+    fn rejects_memory_past_the_end() {
+        let mut json = serde_json::to_value(CPU::new()).unwrap();
+        for address in [MEMORY_SIZE as u64 - 1, u64::MAX] {
+            json["memory"] = serde_json::json!([{ "address": address, "bytes": "0102" }]);
+            let error = serde_json::from_value::<CPU>(json.clone()).unwrap_err();
+            assert!(error.to_string().contains("runs past the end of memory"), "{}", error);
+        }
+        json["memory"] = serde_json::json!([{ "address": 0, "bytes": "0g" }]);
+        assert!(serde_json::from_value::<CPU>(json).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+
+use crate::cpu::CPU;
+
+// How many instructions `undo` can take back; each entry is a whole CPU, though memory is only
+// copied once an instruction writes to it
+pub const UNDO_LIMIT: usize = 100;
+
+/// The CPU before each change made in single-instruction mode, oldest first.
+pub struct UndoHistory {
+    states: VecDeque<CPU>,
+}
+
+impl UndoHistory {
+    pub fn new() -> Self {
+        UndoHistory { states: VecDeque::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Records the CPU as it was before a change, forgetting the oldest entry past `UNDO_LIMIT`.
+    pub fn remember(&mut self, before: CPU) {
+        if self.states.len() == UNDO_LIMIT {
+            self.states.pop_front();
+        }
+        self.states.push_back(before);
+    }
+
+    /// The CPU as it was before the most recent change, if any is left to undo.
+    pub fn undo(&mut self) -> Option<CPU> {
+        self.states.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_complete_instruction;
+
+    #[test]
+    fn undo_restores_registers_and_flags() {
+        let (mut history, mut cpu) = (UndoHistory::new(), CPU::new());
+        cpu.rax = 1;
+        history.remember(cpu.clone());
+        cpu.execute(&parse_complete_instruction("sub rax, 2").unwrap()).unwrap();
+        history.remember(cpu.clone());
+        cpu.execute(&parse_complete_instruction("mov rax, 7").unwrap()).unwrap();
+
+        let previous = history.undo().unwrap();
+        assert_eq!((previous.rax, previous.cf, previous.sf), (u64::MAX, true, true));
+        let previous = history.undo().unwrap();
+        assert_eq!((previous.rax, previous.cf, previous.sf), (1, false, false));
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn forgets_the_oldest_past_the_limit() {
+        let (mut history, mut cpu) = (UndoHistory::new(), CPU::new());
+        for value in 0..UNDO_LIMIT as u64 + 5 {
+            cpu.rax = value;
+            history.remember(cpu.clone());
+        }
+        assert_eq!(history.len(), UNDO_LIMIT);
+        for _ in 1..UNDO_LIMIT {
+            history.undo();
+        }
+        assert_eq!(history.undo().unwrap().rax, 5);
+        assert!(history.undo().is_none());
+    }
+}
@@ -6,7 +6,7 @@ pub fn load_user_functions(env: &mut ScriptEnvironment) {
     env.add_function("binary", binary);
 }
 
-fn double(args: &[&str], _cpu: &crate::cpu::CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
+fn double(args: &[&str], _cpu: &mut crate::cpu::CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
     if args.len() != 1 {
         return Err("Usage: double <value>".to_string());
     }
@@ -14,7 +14,7 @@ fn double(args: &[&str], _cpu: &crate::cpu::CPU, _vars: &mut HashMap<String, u64
     Ok(format!("Result: {}", value * 2))
 }
 
-fn binary(args: &[&str], cpu: &crate::cpu::CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
+fn binary(args: &[&str], cpu: &mut crate::cpu::CPU, _vars: &mut HashMap<String, u64>) -> Result<String, String> {
     if args.len() != 1 {
         return Err("Usage: binary <register>".to_string());
     }
@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Pipes `input` into the REPL in test mode and returns everything it printed
+fn run_session(input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_asmlab"))
+        .arg("--test-mode")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the asmlab binary runs");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn blank_lines_do_nothing_in_any_mode() {
+    let session = ["mov rax, 1", ":multi", "mov rbx, 2", ":calc", "+ 1 2", ":script", "ι 3", ":single", "exit"];
+    let with_blanks = session.iter().map(|line| format!("{}\n  \n\t\n\n", line)).collect::<String>();
+    assert_eq!(run_session(&with_blanks), run_session(&(session.join("\n") + "\n")));
+}
+
+#[test]
+fn restoring_a_checkpoint_stops_stepping() {
+    let output = run_session("checkpoint start\n:multi\ncall f\nret\nf:\nret\nstep\nstep\nrestore start\nbacktrace\nexit\n");
+    let after_restore = output.split("Restored checkpoint 'start'.").nth(1).unwrap();
+    assert!(after_restore.contains("No program is being stepped through"), "{}", output);
+}
+
+#[test]
+fn stepping_over_ret_shows_the_return_value() {
+    let output = run_session(":multi\ncall f\nret\nf:\nmov rax, 2\nmov rdx, 1\nret\nstep\nstep\nstep\nstep\nexit\n");
+    let after_ret = output.split("Executed   4: ret\n").nth(1).unwrap();
+    assert!(after_ret.starts_with("Return value (System V AMD64)\n  rax  = 0x0000000000000002 (2)\n"), "{}", output);
+    assert!(after_ret.contains("rdx:rax = 0x10000000000000002"));
+    // Only a ret shows it
+    assert_eq!(output.matches("Return value").count(), 1);
+}
+
+// Everything after the welcome banner and help, which change whenever a command is added
+fn transcript(output: &str) -> &str {
+    let start = output.find("\nmov rax, 5\n").expect("the session starts with `mov rax, 5`");
+    &output[start + 1..]
+}
+
+#[test]
+fn test_mode_snapshot() {
+    let output = run_session("mov rax, 5\nadd rax, 0x10\ncpu\nfoo\n:calc\n+ 2 3\nexit\n");
+    assert!(!output.contains('\x1b'), "test mode prints no color codes");
+    assert_eq!(transcript(&output), "\
+mov rax, 5
+Assembled bytes: [72, 184, 5, 0, 0, 0, 0, 0, 0, 0]
+Instruction executed.
+
+add rax, 0x10
+Assembled bytes: [72, 5, 16, 0, 0, 0]
+Instruction executed.
+
+cpu
+CPU State:
+rax 0x0000000000000015  r8  0x0000000000000000
+rbx 0x0000000000000000  r9  0x0000000000000000
+rcx 0x0000000000000000  r10 0x0000000000000000
+rdx 0x0000000000000000  r11 0x0000000000000000
+rdi 0x0000000000000000  r12 0x0000000000000000
+rsi 0x0000000000000000  r13 0x0000000000000000
+rbp 0x0000000000000000  r14 0x0000000000000000
+rsp 0x00000000000ffff8  r15 0x0000000000000000
+
+rip     0x0000000000000002
+rflags  0x0000000000000002
+cs      0x0000000000000000
+fs      0x0000000000000000
+gs      0x0000000000000000
+
+FLAGS:
+[]
+
+foo
+Error parsing input: couldn't parse 'foo' at column 1
+
+:calc
+Switched to calculator mode.
+
++ 2 3
+Result: 5
+
+exit
+Goodbye!
+");
+}